@@ -0,0 +1,241 @@
+use crate::backend::Backend;
+use crate::config::AutoConfig;
+use crate::error::Error;
+use crate::funcs::{ApplyOptions, BrightnessCommand, DeviceSelector, Report};
+use crate::transition::{Easing, Transition};
+use crate::MAX_BRIGHTNESS;
+use futures::executor;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// Directory of the IIO devices, relative to the mount point of sysfs.
+const IIO_DEVICES: &str = "bus/iio/devices";
+
+/// Largest difference (in percent) between the level set on a device and the level read back
+/// that is not taken for a correction by the user, as the levels are rounded by the scales.
+const CORRECTION_TOLERANCE: u32 = 1;
+
+/// Represents an ambient light sensor exposed by the IIO subsystem, such as
+/// `/sys/bus/iio/devices/iio:device0`.
+#[derive(Debug, Clone)]
+pub struct LightSensor {
+    path: PathBuf,
+}
+
+/// Represents the brightness of a set of devices following the ambient light.
+///
+/// Every step reads the sensor, smooths the illuminance, then sets the level given by the curve
+/// of the configuration on every selected device whose level differs from it by at least the
+/// hysteresis. A device whose level changed since the previous step was corrected by the user, so
+/// the curve of that device is shifted by the correction instead.
+#[derive(Debug)]
+pub struct AutoBrightness {
+    sensor: LightSensor,
+    config: AutoConfig,
+    selector: DeviceSelector,
+    lux: Option<f64>,
+    devices: HashMap<String, Learned>,
+}
+
+/// Represents what was learned about a device while following the ambient light.
+#[derive(Debug, Clone, Copy)]
+struct Learned {
+    /// Shift (in percent) of the curve, learned from the corrections of the user.
+    offset: i64,
+    /// Level (in percent) of the device after the previous step.
+    level: u32,
+}
+
+impl LightSensor {
+    /// Finds the light sensor named `name`, or the first IIO device measuring the illuminance.
+    ///
+    /// # Arguments
+    ///
+    /// * `sysfs_root`: The mount point of sysfs, usually `/sys`.
+    /// * `name`: The name of the IIO device, such as `iio:device0`, or `None` for any.
+    pub fn discover(sysfs_root: &Path, name: Option<&str>) -> Result<Self, Error> {
+        let directory = sysfs_root.join(IIO_DEVICES);
+        let io_error = |source| Error::IoError {
+            path: directory.clone(),
+            source,
+        };
+        if let Some(name) = name {
+            let sensor = LightSensor {
+                path: directory.join(name),
+            };
+            return match sensor.measures_illuminance() {
+                true => Ok(sensor),
+                false => Err(io_error(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{name} does not measure the illuminance"),
+                ))),
+            };
+        }
+        let mut paths = fs::read_dir(&directory)
+            .map_err(io_error)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+            .into_iter()
+            .map(|path| LightSensor { path })
+            .find(LightSensor::measures_illuminance)
+            .ok_or_else(|| {
+                io_error(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No ambient light sensor measures the illuminance",
+                ))
+            })
+    }
+
+    /// Returns the name of the IIO device, such as `iio:device0`.
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Reads the illuminance (in lux), from `in_illuminance_input` if the driver computes it,
+    /// and from `in_illuminance_raw` along with its scale and offset otherwise.
+    pub fn read(&self) -> Result<f64, Error> {
+        let input = self.path.join("in_illuminance_input");
+        if input.exists() {
+            return self.read_value(&input);
+        }
+        let raw = self.read_value(&self.path.join("in_illuminance_raw"))?;
+        let optional = |file: &str, default: f64| {
+            let path = self.path.join(file);
+            match path.exists() {
+                true => self.read_value(&path),
+                false => Ok(default),
+            }
+        };
+        let offset = optional("in_illuminance_offset", 0.0)?;
+        let scale = optional("in_illuminance_scale", 1.0)?;
+        Ok(((raw + offset) * scale).max(0.0))
+    }
+
+    fn measures_illuminance(&self) -> bool {
+        self.path.join("in_illuminance_input").exists()
+            || self.path.join("in_illuminance_raw").exists()
+    }
+
+    fn read_value(&self, path: &Path) -> Result<f64, Error> {
+        let io_error = |source| Error::IoError {
+            path: path.to_path_buf(),
+            source,
+        };
+        fs::read_to_string(path)
+            .map_err(io_error)?
+            .trim()
+            .parse::<f64>()
+            .map_err(|err| io_error(io::Error::new(io::ErrorKind::InvalidData, err)))
+    }
+}
+
+impl AutoBrightness {
+    /// Creates the automatic brightness of the devices chosen by `selector`, following the
+    /// readings of `sensor` as configured by `config`.
+    pub fn new(sensor: LightSensor, config: AutoConfig, selector: DeviceSelector) -> Self {
+        AutoBrightness {
+            sensor,
+            config,
+            selector,
+            lux: None,
+            devices: HashMap::new(),
+        }
+    }
+
+    /// Returns the smoothed illuminance (in lux), unless the sensor was never read.
+    pub fn lux(&self) -> Option<f64> {
+        self.lux
+    }
+
+    /// Reads the sensor, then adjusts the brightness of the selected devices to it.
+    ///
+    /// # Returns
+    ///
+    /// The state of the devices whose brightness was changed, or which could not be read, along
+    /// with the parts of the selector that match no device on the first step.
+    pub async fn step<B: Backend>(&mut self, backend: &B) -> Result<Report, Error> {
+        let reading = self.sensor.read()?;
+        let first = self.lux.is_none();
+        let lux = match self.lux {
+            Some(previous) => {
+                self.config.smoothing * previous + (1.0 - self.config.smoothing) * reading
+            }
+            None => reading,
+        };
+        self.lux = Some(lux);
+        let base = i64::from(self.config.level(lux));
+        let current = self.selector.report(backend).await;
+        let mut report = Report {
+            devices: Vec::new(),
+            unmatched: if first { current.unmatched } else { Vec::new() },
+        };
+        for record in current.devices {
+            let (Some(name), Some(percent)) = (record.name.clone(), record.percent) else {
+                report.devices.push(record);
+                continue;
+            };
+            let learned = self.devices.entry(name).or_insert(Learned {
+                offset: 0,
+                level: percent,
+            });
+            if percent.abs_diff(learned.level) > CORRECTION_TOLERANCE {
+                // Changed by someone else since the previous step: keep the level chosen by the
+                // user, and remember it for the other illuminances.
+                learned.offset = i64::from(percent) - base;
+                learned.level = percent;
+                continue;
+            }
+            let target = (base + learned.offset).clamp(0, i64::from(MAX_BRIGHTNESS)) as u32;
+            if target.abs_diff(percent) < self.config.hysteresis.max(1) {
+                learned.level = percent;
+                continue;
+            }
+            let options = ApplyOptions {
+                transition: (!self.config.transition.is_zero()).then_some(Transition {
+                    duration: self.config.transition,
+                    easing: Easing::EaseInOut,
+                }),
+                concurrency: None,
+                allow_missing: false,
+            };
+            let selector = DeviceSelector::ByIndex(HashSet::from([record.index]));
+            let changed = BrightnessCommand::Set { percent: target }
+                .apply_with(backend, &selector, &options)
+                .await?;
+            for record in changed.devices {
+                learned.level = record.percent.unwrap_or(target);
+                report.devices.push(record);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Adjusts the brightness every `interval` of the configuration until `on_step` breaks,
+    /// calling it with the report of every step. This function blocks the current thread.
+    ///
+    /// # Returns
+    ///
+    /// Returns an `rumos::Error` as soon as the sensor cannot be read.
+    pub fn run<B: Backend>(
+        &mut self,
+        backend: &B,
+        mut on_step: impl FnMut(&Report) -> ControlFlow<()>,
+    ) -> Result<(), Error> {
+        loop {
+            let report = executor::block_on(self.step(backend))?;
+            if on_step(&report).is_break() {
+                return Ok(());
+            }
+            thread::sleep(self.config.interval);
+        }
+    }
+}
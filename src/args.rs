@@ -1,27 +1,132 @@
-use crate::{MAX_BRIGHTNESS, MIN_BRIGHTNESS};
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use glob::Pattern;
+use regex::Regex;
+use rumos::backend::DEFAULT_SYSFS_ROOT;
+use rumos::{parse_duration, Date, Scale, Template, DEFAULT_UDEV_RULES_PATH, MAX_BRIGHTNESS};
+use std::path::PathBuf;
+use std::time::Duration;
 
-const BRIGHTNESS_PERCENT_RANGE: std::ops::RangeInclusive<i64> =
-    MIN_BRIGHTNESS as i64..=MAX_BRIGHTNESS as i64;
+const BRIGHTNESS_PERCENT_RANGE: std::ops::RangeInclusive<i64> = 0..=MAX_BRIGHTNESS as i64;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
 pub struct Cli {
+    /// Backend used to access the devices
+    #[arg(
+        short,
+        long,
+        global = true,
+        value_enum,
+        env = "RUMOS_BACKEND",
+        default_value_t = Backend::Auto
+    )]
+    pub backend: Backend,
+    /// Mount point of sysfs used by the sysfs backend
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        env = "RUMOS_SYSFS_ROOT",
+        default_value = DEFAULT_SYSFS_ROOT
+    )]
+    pub sysfs_root: PathBuf,
+    /// Socket of the daemon [default: $XDG_RUNTIME_DIR/rumos.sock, or a private directory in
+    /// the temporary directory]
+    #[arg(long, global = true, value_name = "PATH", env = "RUMOS_SOCKET")]
+    pub socket: Option<PathBuf>,
+    /// Access the devices directly even when the daemon is running
+    #[arg(long, global = true, env = "RUMOS_NO_DAEMON")]
+    pub no_daemon: bool,
+    /// Configuration file [default: ~/.config/rumos/config.toml]
+    #[arg(long, global = true, value_name = "PATH", env = "RUMOS_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Brightness scale used by every device: `linear`, `gamma`, `gamma:EXPONENT` or `cie`
+    #[arg(
+        long,
+        global = true,
+        value_name = "SCALE",
+        env = "RUMOS_SCALE",
+        value_parser = parse_scale
+    )]
+    pub scale: Option<Scale>,
+    /// Brightness scale used by a single device, such as `intel_backlight=cie`
+    #[arg(long, global = true, value_name = "NAME=SCALE", value_parser = parse_device_scale)]
+    pub device_scale: Vec<(String, Scale)>,
+    /// Lowest brightness level (in percent) that commands may set on every device
+    #[arg(
+        long,
+        global = true,
+        value_name = "PERCENT",
+        value_parser = clap::value_parser!(u32).range(BRIGHTNESS_PERCENT_RANGE)
+    )]
+    pub min_level: Option<u32>,
+    /// Highest brightness level (in percent) that commands may set on every device
+    #[arg(
+        long,
+        global = true,
+        value_name = "PERCENT",
+        value_parser = clap::value_parser!(u32).range(BRIGHTNESS_PERCENT_RANGE)
+    )]
+    pub max_level: Option<u32>,
+    /// Brightness limits of the devices matching a name, an alias or a kind, such as `led=0:100`
+    /// or `intel_backlight=10:`
+    #[arg(long, global = true, value_name = "NAME=MIN:MAX", value_parser = parse_device_limits)]
+    pub device_limits: Vec<DeviceLimits>,
+    /// Maximum number of devices changed at the same time, or 0 for no limit
+    #[arg(long, global = true, value_name = "COUNT")]
+    pub concurrency: Option<usize>,
     /// Command to execute
     #[command(subcommand)]
     pub command: Command,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Backend {
+    /// Devices provided by the system, followed by the LEDs and the external monitors reachable
+    /// over DDC/CI
+    Auto,
+    /// Devices provided by the system
+    Brightness,
+    /// Backlights and LEDs read and written directly in sysfs
+    Sysfs,
+    /// External monitors reachable over DDC/CI
+    Ddc,
+    /// Simulated devices described by `RUMOS_FAKE_STATE` or `RUMOS_FAKE_DEVICES`
+    Fake,
+}
+
 #[derive(Args, Debug)]
-#[group(required = false, multiple = false)]
+#[group(required = false, multiple = true)]
 pub struct DeviceSelector {
-    /// Names of devices that should be changed
-    #[arg(short, long, value_name = "DEVICES")]
+    /// Names, aliases or glob patterns (such as 'amdgpu_bl*') of devices that should be changed
+    #[arg(short, long, value_name = "DEVICES", value_parser = parse_device_pattern)]
     pub devices: Option<Vec<String>>,
     /// Indices of devices that should be changed
     #[arg(short, long, value_name = "INDICES")]
     pub indices: Option<Vec<usize>>,
+    /// Kinds of devices that should be changed
+    #[arg(short, long = "kind", value_name = "KINDS", value_enum)]
+    pub kinds: Option<Vec<DeviceKind>>,
+    /// Regular expressions matching the names or aliases of devices that should be changed
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    pub regex: Option<Vec<Regex>>,
+    /// Names, aliases or glob patterns of devices that should be left unchanged
+    #[arg(long, value_name = "DEVICES", value_parser = parse_device_pattern)]
+    pub exclude: Option<Vec<String>>,
+    /// Execute the command even though some of the requested devices do not exist
+    #[arg(long)]
+    pub allow_missing: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum DeviceKind {
+    /// Display backlights
+    Backlight,
+    /// LEDs, such as keyboard backlights
+    Led,
+    /// External monitors controlled over DDC/CI
+    Ddc,
 }
 
 #[derive(Args, Debug)]
@@ -33,6 +138,179 @@ pub struct BrightnessOutput {
     /// Print only brightness level(percentage)
     #[arg(short, long)]
     pub percent: bool,
+    /// Print the state of the devices as `json`, `json-lines` or using a template such as
+    /// '{name}: {percent}%' (placeholders: index, name, kind, percent, raw, max, bar)
+    #[arg(short, long, value_name = "FORMAT", value_parser = parse_output_format)]
+    pub format: Option<OutputFormat>,
+}
+
+#[derive(Args, Debug)]
+pub struct Transition {
+    /// Fade to the new brightness level over a duration such as `300ms` or `2s`
+    #[arg(short, long, value_name = "DURATION", value_parser = parse_duration)]
+    pub transition: Option<Duration>,
+    /// Curve followed by the brightness level during the transition
+    #[arg(long, value_enum, default_value_t = Easing::Linear, requires = "transition")]
+    pub easing: Easing,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Easing {
+    /// Change the level at a constant rate
+    Linear,
+    /// Start and end slowly
+    EaseInOut,
+    /// Start slowly and accelerate
+    Exponential,
+}
+
+#[derive(Clone, Debug)]
+pub enum OutputFormat {
+    /// A JSON array holding one object per device
+    Json,
+    /// One JSON object per device and per line
+    JsonLines,
+    /// One line per device, rendered from a template
+    Template(Template),
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceLimits {
+    /// Name, alias or kind of the devices
+    pub name: String,
+    /// Lowest brightness level, unless it is not changed
+    pub min: Option<u32>,
+    /// Highest brightness level, unless it is not changed
+    pub max: Option<u32>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the configuration merged with the command-line options
+    Show,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SetupCommand {
+    /// Install a udev rule granting the `video` group write access to the brightness of the
+    /// backlights and the LEDs
+    Udev {
+        /// Print the rule instead of installing it
+        #[arg(long)]
+        dry_run: bool,
+        /// File in which the rule is written
+        #[arg(short, long, value_name = "PATH", default_value = DEFAULT_UDEV_RULES_PATH)]
+        output: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ScheduleCommand {
+    /// Print the levels scheduled over a day, along with the times of sunrise and sunset
+    Preview {
+        /// Day of the schedule, such as `2024-06-21`, instead of today
+        #[arg(long, value_name = "DATE")]
+        date: Option<Date>,
+        /// Delay between two printed levels
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "30min")]
+        step: Duration,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ProfileCommand {
+    /// Save the brightness levels of the devices under a name, replacing the profile with that name
+    Save {
+        /// Name of the profile, such as `reading`
+        name: String,
+        #[command(flatten)]
+        selector: DeviceSelector,
+        #[command(flatten)]
+        output: BrightnessOutput,
+    },
+    /// Set the brightness levels saved under a name
+    Apply {
+        /// Name of the profile
+        name: String,
+        /// Set the levels even though some of the devices of the profile do not exist
+        #[arg(long)]
+        allow_missing: bool,
+        #[command(flatten)]
+        output: BrightnessOutput,
+        #[command(flatten)]
+        transition: Transition,
+    },
+    /// List the names of the saved profiles
+    List,
+    /// Delete the profile saved under a name
+    Delete {
+        /// Name of the profile
+        name: String,
+    },
+}
+
+fn parse_scale(value: &str) -> Result<Scale, String> {
+    value.parse()
+}
+
+fn parse_device_scale(value: &str) -> Result<(String, Scale), String> {
+    let (name, scale) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid device scale `{value}`, expected NAME=SCALE"))?;
+    Ok((name.to_string(), scale.parse()?))
+}
+
+fn parse_device_pattern(value: &str) -> Result<String, String> {
+    Pattern::new(value)
+        .map(|_| value.to_string())
+        .map_err(|err| format!("Invalid pattern `{value}`: {err}"))
+}
+
+fn parse_regex(value: &str) -> Result<Regex, String> {
+    Regex::new(value).map_err(|err| format!("Invalid regular expression `{value}`: {err}"))
+}
+
+fn parse_device_limits(value: &str) -> Result<DeviceLimits, String> {
+    let invalid = || format!("Invalid device limits `{value}`, expected NAME=MIN:MAX");
+    let (name, limits) = value.split_once('=').ok_or_else(invalid)?;
+    let (min, max) = limits.split_once(':').ok_or_else(invalid)?;
+    let parse = |level: &str| -> Result<Option<u32>, String> {
+        match level.trim() {
+            "" => Ok(None),
+            level => match level.parse::<u32>() {
+                Ok(level) if level <= MAX_BRIGHTNESS => Ok(Some(level)),
+                _ => Err(format!(
+                    "Invalid brightness level `{level}` in `{value}`, expected a value between 0 \
+                     and {MAX_BRIGHTNESS}"
+                )),
+            },
+        }
+    };
+    let (min, max) = (parse(min)?, parse(max)?);
+    if let (Some(min), Some(max)) = (min, max) {
+        if min > max {
+            return Err(format!(
+                "Invalid device limits `{value}`, the minimum exceeds the maximum"
+            ));
+        }
+    }
+    Ok(DeviceLimits {
+        name: name.to_string(),
+        min,
+        max,
+    })
+}
+
+pub fn parse_output_format(value: &str) -> Result<OutputFormat, String> {
+    match value {
+        "json" => Ok(OutputFormat::Json),
+        "json-lines" => Ok(OutputFormat::JsonLines),
+        template if template.contains('{') => template.parse().map(OutputFormat::Template),
+        other => Err(format!(
+            "Unknown format `{other}`, expected json, json-lines or a template containing \
+             placeholders such as {{percent}}"
+        )),
+    }
 }
 
 #[derive(Debug, Subcommand)]
@@ -46,30 +324,50 @@ pub enum Command {
     },
     /// Set brightness level (in percent)
     Set {
-        #[arg(value_parser = clap::value_parser!(u32).range(BRIGHTNESS_PERCENT_RANGE))]
+        #[arg(
+            id = "level",
+            value_name = "PERCENT",
+            value_parser = clap::value_parser!(u32).range(BRIGHTNESS_PERCENT_RANGE)
+        )]
         percent: u32,
         #[command(flatten)]
         selector: DeviceSelector,
         #[command(flatten)]
         output: BrightnessOutput,
+        #[command(flatten)]
+        transition: Transition,
     },
     /// Increase brightness level (in percent)
     Inc {
-        #[arg(value_parser = clap::value_parser!(u32).range(BRIGHTNESS_PERCENT_RANGE))]
-        percent: u32,
+        /// Step of the change [default: 10, or the step of the configuration file]
+        #[arg(
+            id = "level",
+            value_name = "PERCENT",
+            value_parser = clap::value_parser!(u32).range(BRIGHTNESS_PERCENT_RANGE)
+        )]
+        percent: Option<u32>,
         #[command(flatten)]
         selector: DeviceSelector,
         #[command(flatten)]
         output: BrightnessOutput,
+        #[command(flatten)]
+        transition: Transition,
     },
     /// Decrease brightness level (in percent)
     Dec {
-        #[arg(value_parser = clap::value_parser!(u32).range(BRIGHTNESS_PERCENT_RANGE))]
-        percent: u32,
+        /// Step of the change [default: 10, or the step of the configuration file]
+        #[arg(
+            id = "level",
+            value_name = "PERCENT",
+            value_parser = clap::value_parser!(u32).range(BRIGHTNESS_PERCENT_RANGE)
+        )]
+        percent: Option<u32>,
         #[command(flatten)]
         selector: DeviceSelector,
         #[command(flatten)]
         output: BrightnessOutput,
+        #[command(flatten)]
+        transition: Transition,
     },
     /// Set maximum brightness level
     Max {
@@ -77,6 +375,8 @@ pub enum Command {
         selector: DeviceSelector,
         #[command(flatten)]
         output: BrightnessOutput,
+        #[command(flatten)]
+        transition: Transition,
     },
     /// Set mininum brightness level
     Min {
@@ -84,7 +384,78 @@ pub enum Command {
         selector: DeviceSelector,
         #[command(flatten)]
         output: BrightnessOutput,
+        #[command(flatten)]
+        transition: Transition,
+    },
+    /// Print the state of the devices, then a line every time the brightness of one changes
+    Watch {
+        #[command(flatten)]
+        selector: DeviceSelector,
+        #[command(flatten)]
+        output: BrightnessOutput,
+        /// Delay between two readings of the devices whose changes cannot be watched
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "1s")]
+        interval: Duration,
+    },
+    /// Adjust the brightness of the backlights to the ambient light measured by a light sensor
+    Auto {
+        #[command(flatten)]
+        selector: DeviceSelector,
+        #[command(flatten)]
+        output: BrightnessOutput,
+        /// Name of the IIO light sensor, such as `iio:device0`, instead of the first one found
+        #[arg(long, value_name = "NAME")]
+        sensor: Option<String>,
+        /// Delay between two readings of the sensor, instead of the configured one
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        interval: Option<Duration>,
+        /// Adjust the brightness once, then exit
+        #[arg(long)]
+        once: bool,
+    },
+    /// Set the brightness of the backlights to the levels scheduled by the time of day
+    #[command(args_conflicts_with_subcommands = true)]
+    Schedule {
+        #[command(subcommand)]
+        command: Option<ScheduleCommand>,
+        #[command(flatten)]
+        selector: DeviceSelector,
+        #[command(flatten)]
+        output: BrightnessOutput,
+        /// Set the scheduled level once, then exit
+        #[arg(long)]
+        once: bool,
+    },
+    /// Save the brightness levels of the devices under a name, and set them again later
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommand,
+    },
+    /// Run as a daemon owning the devices and serving the requests of the other invocations
+    Daemon,
+    /// Serve the devices on the D-Bus session bus as `org.rumos.Brightness1`
+    Dbus {
+        /// Address of the bus to serve on instead of the session bus
+        #[arg(long, value_name = "ADDRESS")]
+        address: Option<String>,
+    },
+    /// Inspect the configuration file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Check the permissions and the environment needed to control the devices
+    Doctor,
+    /// Configure the system so that rumos works without root privileges
+    Setup {
+        #[command(subcommand)]
+        command: SetupCommand,
     },
     /// List the names of all the available devices
-    List,
+    List {
+        /// Print the state of the devices as `json`, `json-lines` or using a template such as
+        /// '{name}: {percent}%' (placeholders: index, name, kind, percent, raw, max, bar)
+        #[arg(short, long, value_name = "FORMAT", value_parser = parse_output_format)]
+        format: Option<OutputFormat>,
+    },
 }
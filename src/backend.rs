@@ -0,0 +1,350 @@
+mod configured;
+mod ddc;
+mod fake;
+mod shared;
+mod sysfs;
+
+use crate::{MAX_BRIGHTNESS, MIN_BRIGHTNESS};
+use async_trait::async_trait;
+use brightness::{Brightness, BrightnessDevice};
+use futures::{stream::BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+pub use configured::{Configured, ConfiguredDevice};
+pub use ddc::{DdcBackend, DEFAULT_DEV_ROOT};
+pub use fake::FakeBackend;
+pub use shared::{Shared, SharedDevice};
+pub use sysfs::{SysfsBackend, DEFAULT_SYSFS_ROOT};
+
+pub type BrightnessResult<T> = Result<T, brightness::Error>;
+
+/// Represents the kind of a brightness device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceKind {
+    /// A display backlight, such as the devices found in `/sys/class/backlight`.
+    Backlight,
+    /// A LED, such as a keyboard backlight, found in `/sys/class/leds`.
+    Led,
+    /// An external monitor controlled over DDC/CI.
+    Ddc,
+}
+
+impl DeviceKind {
+    /// Every kind of device.
+    pub const ALL: [DeviceKind; 3] = [DeviceKind::Backlight, DeviceKind::Led, DeviceKind::Ddc];
+}
+
+impl Display for DeviceKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceKind::Backlight => write!(f, "backlight"),
+            DeviceKind::Led => write!(f, "led"),
+            DeviceKind::Ddc => write!(f, "ddc"),
+        }
+    }
+}
+
+/// Represents the type of a backlight, which describes how the kernel controls it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BacklightType {
+    /// Controlled through a firmware interface, such as ACPI.
+    Firmware,
+    /// Controlled through a platform specific interface.
+    Platform,
+    /// Controlled by writing directly to the hardware registers.
+    Raw,
+}
+
+impl Display for BacklightType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BacklightType::Firmware => write!(f, "firmware"),
+            BacklightType::Platform => write!(f, "platform"),
+            BacklightType::Raw => write!(f, "raw"),
+        }
+    }
+}
+
+impl FromStr for BacklightType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "firmware" => Ok(BacklightType::Firmware),
+            "platform" => Ok(BacklightType::Platform),
+            "raw" => Ok(BacklightType::Raw),
+            other => Err(format!("Unknown backlight type {other}")),
+        }
+    }
+}
+
+/// Represents a brightness level expressed in the units of the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawBrightness {
+    /// The current level.
+    pub value: u32,
+    /// The maximum level supported by the device.
+    pub max: u32,
+}
+
+/// Represents the lowest and highest brightness levels (in percent) that can be set on a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limits {
+    /// The lowest level.
+    pub min: u32,
+    /// The highest level.
+    pub max: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            min: MIN_BRIGHTNESS,
+            max: MAX_BRIGHTNESS,
+        }
+    }
+}
+
+impl Limits {
+    /// Restricts `percent` to the limits.
+    pub fn clamp(&self, percent: u32) -> u32 {
+        percent.clamp(self.min, self.max.max(self.min))
+    }
+}
+
+/// Represents a device whose brightness can be retrieved and changed.
+#[async_trait]
+pub trait Device: Send + Sync {
+    /// Returns the name of the device.
+    async fn name(&self) -> BrightnessResult<String>;
+
+    /// Returns the kind of the device.
+    fn kind(&self) -> DeviceKind;
+
+    /// Returns the type of the backlight, if the device is a backlight exposing it.
+    fn backlight_type(&self) -> Option<BacklightType> {
+        None
+    }
+
+    /// Returns an identifier of the device that does not change across reboots, if the device
+    /// has one.
+    fn id(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns the other names given to the device.
+    fn aliases(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Returns the lowest and highest levels that commands may set on the device.
+    fn limits(&self) -> Limits {
+        Limits::default()
+    }
+
+    /// Returns the files whose modification signals a change of the brightness, which can be
+    /// watched instead of polling the device.
+    fn watched_files(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    /// Returns the current brightness level (in percent).
+    async fn get(&self) -> BrightnessResult<u32>;
+
+    /// Returns the current brightness level in the units of the device, if they are known.
+    async fn raw(&self) -> BrightnessResult<Option<RawBrightness>> {
+        Ok(None)
+    }
+
+    /// Sets the brightness level (in percent).
+    async fn set(&mut self, percent: u32) -> BrightnessResult<()>;
+
+    /// Sets the brightness level in the units of the device. Devices that do not expose their
+    /// units treat the level as a percentage.
+    async fn set_raw(&mut self, value: u32) -> BrightnessResult<()> {
+        match self.raw().await? {
+            Some(raw) => self.set(percent_from_raw(value, raw.max)).await,
+            None => self.set(value).await,
+        }
+    }
+}
+
+/// Represents a source of brightness devices.
+pub trait Backend: Send + Sync {
+    /// The type of the devices provided by the backend.
+    type Device: Device + 'static;
+
+    /// Returns a stream of all the devices provided by the backend.
+    fn devices(&self) -> BoxStream<'_, BrightnessResult<Self::Device>>;
+
+    /// Returns a stream of the devices provided by the backend that may be of one of `kinds`.
+    ///
+    /// A backend may leave out the devices it knows to be of another kind without opening them,
+    /// such as the monitors that `DdcBackend` would have to probe, as long as they come after all
+    /// the other devices, so that the indices of the devices do not change.
+    fn devices_of(
+        &self,
+        kinds: &HashSet<DeviceKind>,
+    ) -> BoxStream<'_, BrightnessResult<Self::Device>> {
+        let _ = kinds;
+        self.devices()
+    }
+}
+
+#[async_trait]
+impl Device for Box<dyn Device> {
+    async fn name(&self) -> BrightnessResult<String> {
+        self.as_ref().name().await
+    }
+
+    fn kind(&self) -> DeviceKind {
+        self.as_ref().kind()
+    }
+
+    fn backlight_type(&self) -> Option<BacklightType> {
+        self.as_ref().backlight_type()
+    }
+
+    fn id(&self) -> Option<String> {
+        self.as_ref().id()
+    }
+
+    fn aliases(&self) -> Vec<String> {
+        self.as_ref().aliases()
+    }
+
+    fn limits(&self) -> Limits {
+        self.as_ref().limits()
+    }
+
+    fn watched_files(&self) -> Vec<PathBuf> {
+        self.as_ref().watched_files()
+    }
+
+    async fn get(&self) -> BrightnessResult<u32> {
+        self.as_ref().get().await
+    }
+
+    async fn raw(&self) -> BrightnessResult<Option<RawBrightness>> {
+        self.as_ref().raw().await
+    }
+
+    async fn set(&mut self, percent: u32) -> BrightnessResult<()> {
+        self.as_mut().set(percent).await
+    }
+
+    async fn set_raw(&mut self, value: u32) -> BrightnessResult<()> {
+        self.as_mut().set_raw(value).await
+    }
+}
+
+/// Backend providing the devices of a first backend followed by those of a second one.
+#[derive(Debug, Default)]
+pub struct Chain<A, B>(pub A, pub B);
+
+impl<A: Backend, B: Backend> Backend for Chain<A, B> {
+    type Device = Box<dyn Device>;
+
+    fn devices(&self) -> BoxStream<'_, BrightnessResult<Self::Device>> {
+        self.0
+            .devices()
+            .map(boxed)
+            .chain(self.1.devices().map(boxed))
+            .boxed()
+    }
+
+    /// Leaves out devices of the second backend only, so that the devices of the first one keep
+    /// their indices.
+    fn devices_of(
+        &self,
+        kinds: &HashSet<DeviceKind>,
+    ) -> BoxStream<'_, BrightnessResult<Self::Device>> {
+        self.0
+            .devices()
+            .map(boxed)
+            .chain(self.1.devices_of(kinds).map(boxed))
+            .boxed()
+    }
+}
+
+/// Boxes a device of one of the backends of a `Chain`.
+fn boxed<D: Device + 'static>(device: BrightnessResult<D>) -> BrightnessResult<Box<dyn Device>> {
+    device.map(|device| Box::new(device) as Box<dyn Device>)
+}
+
+/// Backend that uses the devices provided by the `brightness` crate.
+#[derive(Debug, Default)]
+pub struct BrightnessBackend;
+
+/// A backlight provided by the `brightness` crate.
+#[derive(Debug)]
+pub struct BacklightDevice {
+    device: BrightnessDevice,
+    name: String,
+}
+
+impl Backend for BrightnessBackend {
+    type Device = BacklightDevice;
+
+    fn devices(&self) -> BoxStream<'_, BrightnessResult<Self::Device>> {
+        brightness::brightness_devices()
+            .then(|device| async move {
+                let device = device?;
+                let name = device.device_name().await?;
+                Ok(BacklightDevice { device, name })
+            })
+            .boxed()
+    }
+}
+
+#[async_trait]
+impl Device for BacklightDevice {
+    async fn name(&self) -> BrightnessResult<String> {
+        Ok(self.name.clone())
+    }
+
+    fn kind(&self) -> DeviceKind {
+        DeviceKind::Backlight
+    }
+
+    /// Returns the attributes of the backlight in `/sys/class/backlight`, which the `brightness`
+    /// crate reads on Linux.
+    fn watched_files(&self) -> Vec<PathBuf> {
+        if !cfg!(target_os = "linux") {
+            return Vec::new();
+        }
+        let path = PathBuf::from("/sys/class/backlight").join(&self.name);
+        ["brightness", "actual_brightness"]
+            .iter()
+            .map(|attribute| path.join(attribute))
+            .filter(|path| path.exists())
+            .collect()
+    }
+
+    async fn get(&self) -> BrightnessResult<u32> {
+        self.device.get().await
+    }
+
+    async fn set(&mut self, percent: u32) -> BrightnessResult<()> {
+        self.device.set(percent).await
+    }
+}
+
+/// Converts a raw brightness value to a percentage of `max`, rounding to the nearest integer.
+pub(crate) fn percent_from_raw(raw: u32, max: u32) -> u32 {
+    if max == 0 {
+        return 0;
+    }
+    ((u64::from(raw) * 100 + u64::from(max) / 2) / u64::from(max)) as u32
+}
+
+/// Converts a percentage to a raw brightness value in `0..=max`, rounding to the nearest integer.
+pub(crate) fn raw_from_percent(percent: u32, max: u32) -> u32 {
+    ((u64::from(percent.min(100)) * u64::from(max) + 50) / 100) as u32
+}
@@ -0,0 +1,147 @@
+use super::{Backend, BacklightType, BrightnessResult, Device, DeviceKind, Limits, RawBrightness};
+use crate::config::Config;
+use crate::scale::Scale;
+use async_trait::async_trait;
+use futures::{stream::BoxStream, StreamExt};
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Backend applying the scale and the limits of a `Config` to the devices of another backend.
+#[derive(Debug)]
+pub struct Configured<B> {
+    backend: B,
+    config: Config,
+}
+
+/// A device whose levels are converted with a `Scale` and restricted to `Limits`, and which can
+/// be selected by its aliases.
+#[derive(Debug)]
+pub struct ConfiguredDevice<D> {
+    device: D,
+    aliases: Vec<String>,
+    scale: Scale,
+    limits: Limits,
+}
+
+impl<B: Backend> Configured<B> {
+    /// Creates a backend providing the devices of `backend` with the settings of `config`.
+    pub fn new(backend: B, config: Config) -> Self {
+        Configured { backend, config }
+    }
+
+    /// Applies the settings of the configuration to the devices of `devices`.
+    fn configure<'a>(
+        &'a self,
+        devices: BoxStream<'a, BrightnessResult<B::Device>>,
+    ) -> BoxStream<'a, BrightnessResult<ConfiguredDevice<B::Device>>> {
+        let config = &self.config;
+        devices
+            .then(move |device| async move {
+                let device = device?;
+                let name = device.name().await.unwrap_or_default();
+                let kind = device.kind();
+                let aliases = config.aliases_of(&name, device.id().as_deref());
+                Ok(ConfiguredDevice {
+                    scale: config.scale(&name, &aliases, kind),
+                    limits: config.limits(&name, &aliases, kind),
+                    device,
+                    aliases,
+                })
+            })
+            .boxed()
+    }
+}
+
+impl<B: Backend> Backend for Configured<B> {
+    type Device = ConfiguredDevice<B::Device>;
+
+    fn devices(&self) -> BoxStream<'_, BrightnessResult<Self::Device>> {
+        self.configure(self.backend.devices())
+    }
+
+    fn devices_of(
+        &self,
+        kinds: &HashSet<DeviceKind>,
+    ) -> BoxStream<'_, BrightnessResult<Self::Device>> {
+        self.configure(self.backend.devices_of(kinds))
+    }
+}
+
+#[async_trait]
+impl<D: Device> Device for ConfiguredDevice<D> {
+    async fn name(&self) -> BrightnessResult<String> {
+        self.device.name().await
+    }
+
+    fn kind(&self) -> DeviceKind {
+        self.device.kind()
+    }
+
+    fn backlight_type(&self) -> Option<BacklightType> {
+        self.device.backlight_type()
+    }
+
+    fn id(&self) -> Option<String> {
+        self.device.id()
+    }
+
+    fn aliases(&self) -> Vec<String> {
+        self.aliases.clone()
+    }
+
+    fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    fn watched_files(&self) -> Vec<PathBuf> {
+        self.device.watched_files()
+    }
+
+    async fn get(&self) -> BrightnessResult<u32> {
+        if self.scale == Scale::Linear {
+            return self.device.get().await;
+        }
+        let linear = match self.device.raw().await? {
+            Some(raw) if raw.max > 0 => f64::from(raw.value) / f64::from(raw.max),
+            _ => f64::from(self.device.get().await?) / 100.0,
+        };
+        Ok((self.scale.to_perceived(linear) * 100.0).round() as u32)
+    }
+
+    async fn raw(&self) -> BrightnessResult<Option<RawBrightness>> {
+        self.device.raw().await
+    }
+
+    async fn set(&mut self, percent: u32) -> BrightnessResult<()> {
+        if self.scale == Scale::Linear {
+            return self.device.set(percent).await;
+        }
+        let linear = self.scale.to_linear(f64::from(percent) / 100.0);
+        match self.device.raw().await? {
+            Some(raw) => {
+                let mut value = (linear * f64::from(raw.max)).round() as u32;
+                // Move by at least one raw step towards the requested level, so that small steps
+                // are not lost to rounding on devices with few levels.
+                let current = self
+                    .scale
+                    .to_perceived(f64::from(raw.value) / f64::from(raw.max));
+                let current = (current * 100.0).round() as u32;
+                if percent > current && value <= raw.value {
+                    value = raw.value + 1;
+                } else if percent < current && value >= raw.value {
+                    value = raw.value.saturating_sub(1);
+                }
+                // Keep the device lit when a perceived level above zero maps below one raw step.
+                if percent > 0 {
+                    value = value.max(1);
+                }
+                self.device.set_raw(value.min(raw.max)).await
+            }
+            None => self.device.set((linear * 100.0).round() as u32).await,
+        }
+    }
+
+    async fn set_raw(&mut self, value: u32) -> BrightnessResult<()> {
+        self.device.set_raw(value).await
+    }
+}
@@ -0,0 +1,556 @@
+use super::{
+    percent_from_raw, raw_from_percent, Backend, BrightnessResult, Device, DeviceKind,
+    RawBrightness,
+};
+use async_trait::async_trait;
+use futures::lock::Mutex;
+use futures::{stream, stream::BoxStream, StreamExt};
+use futures_timer::Delay;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Default directory containing the `i2c-dev` character devices.
+pub const DEFAULT_DEV_ROOT: &str = "/dev";
+
+/// I2C address of the EDID EEPROM of a monitor.
+const EDID_ADDRESS: u16 = 0x50;
+/// I2C address of the DDC/CI interface of a monitor.
+const DDC_ADDRESS: u16 = 0x37;
+/// VCP feature code of the luminance (brightness) control.
+const VCP_LUMINANCE: u8 = 0x10;
+
+/// Address byte of the monitor, included in the checksum of the messages sent to it.
+const DISPLAY_ADDRESS: u8 = 0x6E;
+/// Address byte of the host, sent as the first byte of every message.
+const HOST_ADDRESS: u8 = 0x51;
+/// Virtual address byte included in the checksum of the replies of the monitor.
+const REPLY_ADDRESS: u8 = 0x50;
+
+const GET_VCP_REQUEST: u8 = 0x01;
+const GET_VCP_REPLY: u8 = 0x02;
+const SET_VCP_REQUEST: u8 = 0x03;
+
+const EDID_LENGTH: usize = 128;
+const EDID_HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+const EDID_MONITOR_NAME_TAG: u8 = 0xFC;
+
+/// Time the monitor needs to prepare a reply to a request.
+const REPLY_DELAY: Duration = Duration::from_millis(40);
+/// Time the monitor needs to process a change before accepting another request.
+const SET_DELAY: Duration = Duration::from_millis(50);
+/// Number of attempts made before giving up on a DDC/CI exchange.
+const ATTEMPTS: usize = 3;
+
+/// Beginnings of the names that the graphics drivers give to the I2C buses of the display
+/// connectors, such as `i915 gmbus dpb` or `AMDGPU DM i2c hw bus 0`.
+const DISPLAY_ADAPTER_NAMES: [&str; 8] = [
+    "i915 gmbus",
+    "DPDDC",
+    "AMDGPU DM",
+    "NVIDIA i2c adapter",
+    "nvkm-",
+    "Radeon i2c",
+    "radeon",
+    "nouveau",
+];
+
+/// Represents a transport used to exchange messages with the devices on an I2C bus.
+pub trait I2cTransport: Send {
+    /// Writes `data` to the device at `address`.
+    fn write(&mut self, address: u16, data: &[u8]) -> io::Result<()>;
+
+    /// Fills `buffer` with data read from the device at `address`.
+    fn read(&mut self, address: u16, buffer: &mut [u8]) -> io::Result<()>;
+}
+
+/// An I2C bus accessed through its `i2c-dev` character device.
+#[derive(Debug)]
+pub struct I2cDev {
+    file: File,
+}
+
+impl I2cDev {
+    /// Opens the `i2c-dev` character device at `path`.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(I2cDev { file })
+    }
+
+    /// Selects the device at `address` as the target of the following reads and writes.
+    #[cfg(target_os = "linux")]
+    fn select(&self, address: u16) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        const I2C_SLAVE: libc::c_ulong = 0x0703;
+        // SAFETY: I2C_SLAVE takes the address as an integer argument and does not retain it.
+        let result = unsafe {
+            libc::ioctl(
+                self.file.as_raw_fd(),
+                I2C_SLAVE as _,
+                libc::c_ulong::from(address),
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn select(&self, _address: u16) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "i2c-dev is only available on Linux",
+        ))
+    }
+}
+
+impl I2cTransport for I2cDev {
+    fn write(&mut self, address: u16, data: &[u8]) -> io::Result<()> {
+        self.select(address)?;
+        self.file.write_all(data)
+    }
+
+    fn read(&mut self, address: u16, buffer: &mut [u8]) -> io::Result<()> {
+        self.select(address)?;
+        self.file.read_exact(buffer)
+    }
+}
+
+/// Backend providing the external monitors reachable over DDC/CI.
+#[derive(Debug)]
+pub struct DdcBackend {
+    dev_root: PathBuf,
+    sysfs_root: PathBuf,
+}
+
+/// A monitor whose luminance is controlled over DDC/CI.
+#[derive(Debug)]
+pub struct DdcDevice<T = I2cDev> {
+    name: String,
+    id: Option<String>,
+    transport: Mutex<T>,
+}
+
+impl DdcBackend {
+    /// Creates a backend probing the `i2c-*` character devices found in `dev_root` that belong to
+    /// a display adapter, according to the sysfs tree mounted at `sysfs_root`.
+    pub fn new(dev_root: impl Into<PathBuf>, sysfs_root: impl Into<PathBuf>) -> Self {
+        DdcBackend {
+            dev_root: dev_root.into(),
+            sysfs_root: sysfs_root.into(),
+        }
+    }
+
+    /// Lists the I2C buses of the display adapters, sorted by bus number.
+    fn read_buses(&self) -> io::Result<Vec<(u32, PathBuf)>> {
+        let entries = match fs::read_dir(&self.dev_root) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut buses = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            if let Some(bus) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("i2c-"))
+                .and_then(|bus| bus.parse().ok())
+            {
+                buses.push((bus, entry.path()));
+            }
+        }
+        let connected = self.connector_buses();
+        buses.retain(|(bus, _)| connected.contains(bus) || self.is_display_adapter(*bus));
+        buses.sort();
+        Ok(buses)
+    }
+
+    /// Lists the I2C buses linked to a connector of a graphics card as its `ddc` bus.
+    fn connector_buses(&self) -> HashSet<u32> {
+        let Ok(connectors) = fs::read_dir(self.sysfs_root.join("class/drm")) else {
+            return HashSet::new();
+        };
+        connectors
+            .filter_map(|connector| fs::read_link(connector.ok()?.path().join("ddc")).ok())
+            .filter_map(|bus| {
+                bus.file_name()?
+                    .to_str()?
+                    .strip_prefix("i2c-")?
+                    .parse()
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Checks whether the I2C bus numbered `bus` belongs to a display adapter, according to the
+    /// name given by its driver.
+    ///
+    /// A bus missing from the sysfs tree is probed anyway, as nothing tells what it is.
+    fn is_display_adapter(&self, bus: u32) -> bool {
+        let path = self
+            .sysfs_root
+            .join(format!("bus/i2c/devices/i2c-{bus}/name"));
+        match fs::read_to_string(path) {
+            Ok(name) => DISPLAY_ADAPTER_NAMES
+                .iter()
+                .any(|prefix| name.trim().starts_with(prefix)),
+            Err(err) => err.kind() == io::ErrorKind::NotFound,
+        }
+    }
+
+    /// Opens the monitor connected to the bus at `path`.
+    ///
+    /// Returns `None` if the bus cannot be opened or if no monitor answers with an EDID, which is
+    /// the case for most of the buses of a machine.
+    fn probe(bus: u32, path: &Path) -> Option<DdcDevice> {
+        let mut transport = I2cDev::open(path).ok()?;
+        let edid = read_edid(&mut transport).ok()?;
+        let name = monitor_name(&edid).unwrap_or_else(|| format!("i2c-{bus}"));
+        let mut device = DdcDevice::new(name, transport);
+        device.id = Some(monitor_id(&edid));
+        Some(device)
+    }
+}
+
+impl Backend for DdcBackend {
+    type Device = DdcDevice;
+
+    fn devices(&self) -> BoxStream<'_, BrightnessResult<Self::Device>> {
+        match self.read_buses() {
+            Ok(buses) => stream::iter(buses)
+                .filter_map(|(bus, path)| async move { Self::probe(bus, &path).map(Ok) })
+                .boxed(),
+            Err(err) => {
+                stream::once(async { Err(brightness::Error::ListingDevicesFailed(Box::new(err))) })
+                    .boxed()
+            }
+        }
+    }
+
+    /// Leaves the buses unprobed unless monitors may be chosen.
+    fn devices_of(
+        &self,
+        kinds: &HashSet<DeviceKind>,
+    ) -> BoxStream<'_, BrightnessResult<Self::Device>> {
+        match kinds.contains(&DeviceKind::Ddc) {
+            true => self.devices(),
+            false => stream::empty().boxed(),
+        }
+    }
+}
+
+impl<T: I2cTransport> DdcDevice<T> {
+    /// Creates a device named `name` talking to its monitor over `transport`.
+    pub fn new(name: String, transport: T) -> Self {
+        DdcDevice {
+            name,
+            id: None,
+            transport: Mutex::new(transport),
+        }
+    }
+
+    /// Reads the current luminance and its maximum.
+    async fn read_luminance(&self) -> BrightnessResult<RawBrightness> {
+        let mut transport = self.transport.lock().await;
+        get_vcp(&mut *transport, VCP_LUMINANCE)
+            .await
+            .map_err(|err| brightness::Error::GettingDeviceInfoFailed {
+                device: self.name.clone(),
+                source: Box::new(err),
+            })
+    }
+}
+
+#[async_trait]
+impl<T: I2cTransport> Device for DdcDevice<T> {
+    async fn name(&self) -> BrightnessResult<String> {
+        Ok(self.name.clone())
+    }
+
+    fn kind(&self) -> DeviceKind {
+        DeviceKind::Ddc
+    }
+
+    fn id(&self) -> Option<String> {
+        self.id.clone()
+    }
+
+    async fn get(&self) -> BrightnessResult<u32> {
+        let luminance = self.read_luminance().await?;
+        Ok(percent_from_raw(luminance.value, luminance.max))
+    }
+
+    async fn raw(&self) -> BrightnessResult<Option<RawBrightness>> {
+        self.read_luminance().await.map(Some)
+    }
+
+    async fn set(&mut self, percent: u32) -> BrightnessResult<()> {
+        let max = self.read_luminance().await?.max;
+        self.set_raw(raw_from_percent(percent, max)).await
+    }
+
+    async fn set_raw(&mut self, value: u32) -> BrightnessResult<()> {
+        let value = value.min(u32::from(u16::MAX));
+        let transport = self.transport.get_mut();
+        set_vcp(transport, VCP_LUMINANCE, value as u16)
+            .await
+            .map_err(|err| brightness::Error::SettingBrightnessFailed {
+                device: self.name.clone(),
+                source: Box::new(err),
+            })
+    }
+}
+
+fn checksum(seed: u8, bytes: &[u8]) -> u8 {
+    bytes.iter().fold(seed, |acc, byte| acc ^ byte)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads the base block of the EDID of the monitor connected to the bus.
+fn read_edid(transport: &mut impl I2cTransport) -> io::Result<[u8; EDID_LENGTH]> {
+    let mut edid = [0; EDID_LENGTH];
+    transport.write(EDID_ADDRESS, &[0x00])?;
+    transport.read(EDID_ADDRESS, &mut edid)?;
+    if edid[..EDID_HEADER.len()] != EDID_HEADER {
+        return Err(invalid_data("Invalid EDID header"));
+    }
+    Ok(edid)
+}
+
+/// Extracts the monitor name from the display descriptors of an EDID.
+fn monitor_name(edid: &[u8; EDID_LENGTH]) -> Option<String> {
+    edid[54..126]
+        .chunks_exact(18)
+        .find(|descriptor| descriptor[..3] == [0, 0, 0] && descriptor[3] == EDID_MONITOR_NAME_TAG)
+        .map(|descriptor| {
+            let name = &descriptor[5..];
+            let end = name.iter().position(|&b| b == b'\n').unwrap_or(name.len());
+            String::from_utf8_lossy(&name[..end]).trim().to_string()
+        })
+        .filter(|name| !name.is_empty())
+}
+
+/// Builds an identifier of the monitor from the manufacturer, product code and serial number of
+/// its EDID, such as `edid:DEL-A0B4-12345678`, which does not depend on the bus it is connected
+/// to.
+fn monitor_id(edid: &[u8; EDID_LENGTH]) -> String {
+    let manufacturer = u16::from_be_bytes([edid[8], edid[9]]);
+    let manufacturer = [10, 5, 0]
+        .iter()
+        .map(|shift| char::from(b'@' + ((manufacturer >> shift) & 0x1f) as u8))
+        .collect::<String>();
+    let product = u16::from_le_bytes([edid[10], edid[11]]);
+    let serial = u32::from_le_bytes([edid[12], edid[13], edid[14], edid[15]]);
+    format!("edid:{manufacturer}-{product:04X}-{serial:08X}")
+}
+
+/// Reads the current and maximum values of the VCP feature `code`, trying again if the monitor
+/// did not answer properly.
+async fn get_vcp(transport: &mut impl I2cTransport, code: u8) -> io::Result<RawBrightness> {
+    let mut attempt = 1;
+    loop {
+        match try_get_vcp(transport, code).await {
+            Err(_) if attempt < ATTEMPTS => attempt += 1,
+            result => return result,
+        }
+    }
+}
+
+/// Sets the value of the VCP feature `code`, trying again if the monitor could not be reached.
+async fn set_vcp(transport: &mut impl I2cTransport, code: u8, value: u16) -> io::Result<()> {
+    let mut attempt = 1;
+    loop {
+        match try_set_vcp(transport, code, value).await {
+            Err(_) if attempt < ATTEMPTS => attempt += 1,
+            result => return result,
+        }
+    }
+}
+
+/// Reads the current and maximum values of the VCP feature `code` once.
+async fn try_get_vcp(transport: &mut impl I2cTransport, code: u8) -> io::Result<RawBrightness> {
+    let mut request = [HOST_ADDRESS, 0x82, GET_VCP_REQUEST, code, 0];
+    request[4] = checksum(DISPLAY_ADDRESS, &request[..4]);
+    transport.write(DDC_ADDRESS, &request)?;
+    Delay::new(REPLY_DELAY).await;
+
+    let mut reply = [0; 11];
+    transport.read(DDC_ADDRESS, &mut reply)?;
+    if checksum(REPLY_ADDRESS, &reply[..10]) != reply[10] {
+        return Err(invalid_data("Invalid DDC/CI reply checksum"));
+    }
+    if reply[1] != 0x88 || reply[2] != GET_VCP_REPLY || reply[4] != code {
+        return Err(invalid_data("Unexpected DDC/CI reply"));
+    }
+    if reply[3] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("VCP feature {code:#04x} is not supported by the monitor"),
+        ));
+    }
+    Ok(RawBrightness {
+        max: u32::from(u16::from_be_bytes([reply[6], reply[7]])),
+        value: u32::from(u16::from_be_bytes([reply[8], reply[9]])),
+    })
+}
+
+/// Sets the value of the VCP feature `code` once.
+async fn try_set_vcp(transport: &mut impl I2cTransport, code: u8, value: u16) -> io::Result<()> {
+    let [high, low] = value.to_be_bytes();
+    let mut request = [HOST_ADDRESS, 0x84, SET_VCP_REQUEST, code, high, low, 0];
+    request[6] = checksum(DISPLAY_ADDRESS, &request[..6]);
+    transport.write(DDC_ADDRESS, &request)?;
+    Delay::new(SET_DELAY).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    /// Simulates a monitor answering DDC/CI requests for the luminance feature.
+    struct MockMonitor {
+        edid: [u8; EDID_LENGTH],
+        luminance: u16,
+        max: u16,
+        pending: Vec<u8>,
+    }
+
+    impl MockMonitor {
+        fn new(name: &str, luminance: u16, max: u16) -> Self {
+            let mut edid = [0; EDID_LENGTH];
+            edid[..8].copy_from_slice(&EDID_HEADER);
+            let descriptor = &mut edid[72..90];
+            descriptor[3] = EDID_MONITOR_NAME_TAG;
+            descriptor[5..].fill(b' ');
+            descriptor[5..5 + name.len()].copy_from_slice(name.as_bytes());
+            descriptor[5 + name.len()] = b'\n';
+            MockMonitor {
+                edid,
+                luminance,
+                max,
+                pending: Vec::new(),
+            }
+        }
+    }
+
+    impl I2cTransport for MockMonitor {
+        fn write(&mut self, address: u16, data: &[u8]) -> io::Result<()> {
+            match address {
+                EDID_ADDRESS => self.pending = self.edid.to_vec(),
+                DDC_ADDRESS => {
+                    assert_eq!(data[0], HOST_ADDRESS);
+                    let last = data.len() - 1;
+                    assert_eq!(checksum(DISPLAY_ADDRESS, &data[..last]), data[last]);
+                    match data[2] {
+                        GET_VCP_REQUEST => {
+                            let [max_high, max_low] = self.max.to_be_bytes();
+                            let [high, low] = self.luminance.to_be_bytes();
+                            let mut reply = vec![
+                                DISPLAY_ADDRESS,
+                                0x88,
+                                GET_VCP_REPLY,
+                                0,
+                                data[3],
+                                0,
+                                max_high,
+                                max_low,
+                                high,
+                                low,
+                            ];
+                            reply.push(checksum(REPLY_ADDRESS, &reply));
+                            self.pending = reply;
+                        }
+                        SET_VCP_REQUEST => {
+                            self.luminance = u16::from_be_bytes([data[4], data[5]]);
+                        }
+                        opcode => panic!("Unexpected opcode {opcode:#04x}"),
+                    }
+                }
+                _ => return Err(io::Error::from(io::ErrorKind::NotFound)),
+            }
+            Ok(())
+        }
+
+        fn read(&mut self, _address: u16, buffer: &mut [u8]) -> io::Result<()> {
+            buffer.copy_from_slice(&self.pending[..buffer.len()]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reads_monitor_name_from_edid() {
+        let mut monitor = MockMonitor::new("DELL U2720Q", 30, 100);
+        let edid = read_edid(&mut monitor).unwrap();
+        assert_eq!(monitor_name(&edid).as_deref(), Some("DELL U2720Q"));
+    }
+
+    #[test]
+    fn reads_monitor_id_from_edid() {
+        let mut monitor = MockMonitor::new("DELL U2720Q", 30, 100);
+        monitor.edid[8..16].copy_from_slice(&[0x10, 0xac, 0xb4, 0xa0, 0x78, 0x56, 0x34, 0x12]);
+        let edid = read_edid(&mut monitor).unwrap();
+        assert_eq!(monitor_id(&edid), "edid:DEL-A0B4-12345678");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn probes_display_adapters_only() {
+        let root = tempfile::TempDir::new().unwrap();
+        let (dev_root, sysfs_root) = (root.path().join("dev"), root.path().join("sys"));
+        fs::create_dir_all(&dev_root).unwrap();
+        for (bus, name) in [
+            (0, Some("SMBus I801 adapter at efa0")),
+            (3, Some("i915 gmbus dpc")),
+            (5, Some("AUX B/DDI B/PHY B")),
+            (7, None),
+        ] {
+            fs::write(dev_root.join(format!("i2c-{bus}")), "").unwrap();
+            if let Some(name) = name {
+                let path = sysfs_root.join(format!("bus/i2c/devices/i2c-{bus}"));
+                fs::create_dir_all(&path).unwrap();
+                fs::write(path.join("name"), format!("{name}\n")).unwrap();
+            }
+        }
+        let connector = sysfs_root.join("class/drm/card1-DP-1");
+        fs::create_dir_all(&connector).unwrap();
+        std::os::unix::fs::symlink("../../../bus/i2c/devices/i2c-5", connector.join("ddc"))
+            .unwrap();
+        let backend = DdcBackend::new(&dev_root, &sysfs_root);
+        let buses = backend.read_buses().unwrap();
+        assert_eq!(
+            buses.iter().map(|(bus, _)| *bus).collect::<Vec<_>>(),
+            [3, 5, 7]
+        );
+    }
+
+    #[test]
+    fn gets_luminance() {
+        let device = DdcDevice::new("monitor".to_string(), MockMonitor::new("monitor", 60, 200));
+        assert_eq!(block_on(device.get()).unwrap(), 30);
+        assert_eq!(
+            block_on(device.raw()).unwrap(),
+            Some(RawBrightness {
+                value: 60,
+                max: 200
+            })
+        );
+    }
+
+    #[test]
+    fn sets_luminance() {
+        let mut device =
+            DdcDevice::new("monitor".to_string(), MockMonitor::new("monitor", 60, 200));
+        block_on(device.set(75)).unwrap();
+        assert_eq!(device.transport.get_mut().luminance, 150);
+    }
+}
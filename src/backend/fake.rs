@@ -0,0 +1,241 @@
+use super::{
+    percent_from_raw, raw_from_percent, Backend, BrightnessResult, Device, DeviceKind,
+    RawBrightness,
+};
+use crate::error::Error;
+use crate::transition::runtime_dir;
+use async_trait::async_trait;
+use futures::{stream, stream::BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::{env, fs, io, process};
+
+/// Environment variable holding the path of the file in which the fake devices are stored.
+pub const FAKE_STATE_ENV: &str = "RUMOS_FAKE_STATE";
+/// Environment variable holding a JSON description of the fake devices, used when no state file
+/// exists yet.
+pub const FAKE_DEVICES_ENV: &str = "RUMOS_FAKE_DEVICES";
+
+const DEFAULT_STATE_FILE: &str = "rumos-fake-state.json";
+
+/// Represents the persisted state of all the fake devices.
+#[derive(Debug, Serialize, Deserialize)]
+struct FakeState {
+    devices: Vec<FakeDeviceState>,
+}
+
+/// Represents the persisted state of a single fake device.
+#[derive(Debug, Serialize, Deserialize)]
+struct FakeDeviceState {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(default = "default_kind")]
+    kind: DeviceKind,
+    brightness: u32,
+    #[serde(default = "default_max_brightness")]
+    max_brightness: u32,
+    /// Failure simulated when the brightness of the device is changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fail: Option<FakeFailure>,
+}
+
+/// Represents the failures that fake devices can simulate.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum FakeFailure {
+    /// The device cannot be written by the user.
+    PermissionDenied,
+    /// The device does not answer.
+    Io,
+}
+
+fn default_kind() -> DeviceKind {
+    DeviceKind::Backlight
+}
+
+fn default_max_brightness() -> u32 {
+    100
+}
+
+impl Default for FakeState {
+    fn default() -> Self {
+        FakeState {
+            devices: vec![FakeDeviceState {
+                name: "fake_backlight".to_string(),
+                id: None,
+                kind: DeviceKind::Backlight,
+                brightness: 100,
+                max_brightness: default_max_brightness(),
+                fail: None,
+            }],
+        }
+    }
+}
+
+/// Backend providing in-memory devices whose state is persisted to a file between invocations.
+#[derive(Debug)]
+pub struct FakeBackend {
+    path: Arc<PathBuf>,
+    state: Arc<Mutex<FakeState>>,
+}
+
+/// A device provided by the `FakeBackend`.
+#[derive(Debug)]
+pub struct FakeDevice {
+    index: usize,
+    path: Arc<PathBuf>,
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeBackend {
+    /// Creates a fake backend from the environment.
+    ///
+    /// The devices are read from the file named by `RUMOS_FAKE_STATE`, or from a file in the
+    /// private runtime directory of the user if unset. If that file does not exist, the devices
+    /// are described by the JSON in `RUMOS_FAKE_DEVICES`, falling back to a single backlight at
+    /// full brightness.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Error::InvalidOptions` if `RUMOS_FAKE_DEVICES` is set without `RUMOS_FAKE_STATE`,
+    /// as the devices it describes would replace those of the other invocations sharing the
+    /// default file. Otherwise, returns an `rumos::Error` if the devices cannot be read.
+    pub fn from_env() -> Result<Self, Error> {
+        let devices = env::var(FAKE_DEVICES_ENV).ok();
+        let path = match env::var_os(FAKE_STATE_ENV) {
+            Some(path) => PathBuf::from(path),
+            None if devices.is_some() => {
+                return Err(Error::InvalidOptions(format!(
+                    "{FAKE_DEVICES_ENV} needs {FAKE_STATE_ENV} to name the file holding the state \
+                     of the devices"
+                )))
+            }
+            None => runtime_dir()
+                .ok_or_else(|| {
+                    Error::InvalidOptions(format!(
+                        "Cannot create a private runtime directory, set XDG_RUNTIME_DIR or \
+                         {FAKE_STATE_ENV}"
+                    ))
+                })?
+                .join(DEFAULT_STATE_FILE),
+        };
+        Ok(Self::open(path, devices.as_deref())?)
+    }
+
+    /// Creates a fake backend whose devices are stored in the file at `path`.
+    ///
+    /// If that file does not exist, the devices are described by the JSON in `devices`, such as
+    /// `{"devices": [{"name": "panel", "brightness": 50}]}`, falling back to a single backlight at
+    /// full brightness.
+    pub fn open(path: impl Into<PathBuf>, devices: Option<&str>) -> BrightnessResult<Self> {
+        let path = path.into();
+        let state = match fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => match devices {
+                Some(devices) => Self::parse(devices)?,
+                None => FakeState::default(),
+            },
+            Err(err) => return Err(brightness::Error::ListingDevicesFailed(Box::new(err))),
+        };
+        Ok(FakeBackend {
+            path: Arc::new(path),
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    fn parse(contents: &str) -> BrightnessResult<FakeState> {
+        serde_json::from_str(contents)
+            .map_err(|err| brightness::Error::ListingDevicesFailed(Box::new(err)))
+    }
+}
+
+impl Backend for FakeBackend {
+    type Device = FakeDevice;
+
+    fn devices(&self) -> BoxStream<'_, BrightnessResult<Self::Device>> {
+        let count = self.state.lock().unwrap().devices.len();
+        stream::iter((0..count).map(|index| {
+            Ok(FakeDevice {
+                index,
+                path: self.path.clone(),
+                state: self.state.clone(),
+            })
+        }))
+        .boxed()
+    }
+}
+
+impl FakeDevice {
+    /// Replaces the state file at once, so that concurrent invocations never read it partially
+    /// written.
+    fn write_state(path: &Path, state: &FakeState) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(state)?;
+        let partial = path.with_extension(format!("{}.tmp", process::id()));
+        fs::write(&partial, contents)?;
+        fs::rename(partial, path)
+    }
+}
+
+#[async_trait]
+impl Device for FakeDevice {
+    async fn name(&self) -> BrightnessResult<String> {
+        Ok(self.state.lock().unwrap().devices[self.index].name.clone())
+    }
+
+    fn kind(&self) -> DeviceKind {
+        self.state.lock().unwrap().devices[self.index].kind
+    }
+
+    fn id(&self) -> Option<String> {
+        self.state.lock().unwrap().devices[self.index].id.clone()
+    }
+
+    fn watched_files(&self) -> Vec<PathBuf> {
+        vec![self.path.as_ref().clone()]
+    }
+
+    async fn get(&self) -> BrightnessResult<u32> {
+        let state = self.state.lock().unwrap();
+        let device = &state.devices[self.index];
+        Ok(percent_from_raw(device.brightness, device.max_brightness))
+    }
+
+    async fn raw(&self) -> BrightnessResult<Option<RawBrightness>> {
+        let state = self.state.lock().unwrap();
+        let device = &state.devices[self.index];
+        Ok(Some(RawBrightness {
+            value: device.brightness,
+            max: device.max_brightness,
+        }))
+    }
+
+    async fn set(&mut self, percent: u32) -> BrightnessResult<()> {
+        let max = self.state.lock().unwrap().devices[self.index].max_brightness;
+        self.set_raw(raw_from_percent(percent, max)).await
+    }
+
+    async fn set_raw(&mut self, value: u32) -> BrightnessResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let device = &mut state.devices[self.index];
+        let name = device.name.clone();
+        if let Some(fail) = device.fail {
+            let kind = match fail {
+                FakeFailure::PermissionDenied => io::ErrorKind::PermissionDenied,
+                FakeFailure::Io => io::ErrorKind::Other,
+            };
+            return Err(brightness::Error::SettingBrightnessFailed {
+                device: name,
+                source: Box::new(io::Error::from(kind)),
+            });
+        }
+        device.brightness = value.min(device.max_brightness);
+        Self::write_state(&self.path, &state).map_err(|err| {
+            brightness::Error::SettingBrightnessFailed {
+                device: name,
+                source: Box::new(err),
+            }
+        })
+    }
+}
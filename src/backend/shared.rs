@@ -0,0 +1,157 @@
+use super::{Backend, BacklightType, BrightnessResult, Device, DeviceKind, Limits, RawBrightness};
+use async_trait::async_trait;
+use futures::lock::Mutex;
+use futures::{stream, stream::BoxStream, StreamExt};
+use std::error::Error as StdError;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Backend providing the devices of another backend, enumerated once and shared between the
+/// requests handled by the daemon.
+#[derive(Debug)]
+pub struct Shared<D> {
+    devices: Vec<Result<SharedDevice<D>, brightness::Error>>,
+}
+
+/// A device of a `Shared` backend, whose changes are made one at a time.
+#[derive(Debug)]
+pub struct SharedDevice<D> {
+    device: Arc<Mutex<D>>,
+    kind: DeviceKind,
+    backlight_type: Option<BacklightType>,
+    id: Option<String>,
+    aliases: Vec<String>,
+    limits: Limits,
+    watched_files: Vec<PathBuf>,
+}
+
+impl<D: Device + 'static> Shared<D> {
+    /// Enumerates the devices of `backend`, keeping the error of each device that cannot be
+    /// opened in its place, so that the indices of the devices do not change.
+    pub async fn new<B: Backend<Device = D>>(backend: &B) -> Self {
+        let devices = backend
+            .devices()
+            .map(|device| {
+                device.map(|device| SharedDevice {
+                    kind: device.kind(),
+                    backlight_type: device.backlight_type(),
+                    id: device.id(),
+                    aliases: device.aliases(),
+                    limits: device.limits(),
+                    watched_files: device.watched_files(),
+                    device: Arc::new(Mutex::new(device)),
+                })
+            })
+            .collect()
+            .await;
+        Shared { devices }
+    }
+}
+
+impl<D: Device + 'static> Backend for Shared<D> {
+    type Device = SharedDevice<D>;
+
+    fn devices(&self) -> BoxStream<'_, BrightnessResult<Self::Device>> {
+        stream::iter(self.devices.iter().map(|device| match device {
+            Ok(device) => Ok(device.clone()),
+            Err(err) => Err(copy_error(err)),
+        }))
+        .boxed()
+    }
+}
+
+/// Rebuilds `err`, which cannot be cloned, keeping the messages of the errors that caused it and
+/// the kind of the first I/O error among them.
+fn copy_error(err: &brightness::Error) -> brightness::Error {
+    let mut kind = None;
+    let mut messages = Vec::new();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        if let Some(cause) = cause.downcast_ref::<io::Error>() {
+            kind.get_or_insert(cause.kind());
+        }
+        messages.push(cause.to_string());
+        source = cause.source();
+    }
+    let source = Box::new(io::Error::new(
+        kind.unwrap_or(io::ErrorKind::Other),
+        messages.join(": "),
+    ));
+    match err {
+        brightness::Error::GettingDeviceInfoFailed { device, .. } => {
+            brightness::Error::GettingDeviceInfoFailed {
+                device: device.clone(),
+                source,
+            }
+        }
+        brightness::Error::SettingBrightnessFailed { device, .. } => {
+            brightness::Error::SettingBrightnessFailed {
+                device: device.clone(),
+                source,
+            }
+        }
+        _ => brightness::Error::ListingDevicesFailed(source),
+    }
+}
+
+impl<D> Clone for SharedDevice<D> {
+    fn clone(&self) -> Self {
+        SharedDevice {
+            device: self.device.clone(),
+            kind: self.kind,
+            backlight_type: self.backlight_type,
+            id: self.id.clone(),
+            aliases: self.aliases.clone(),
+            limits: self.limits,
+            watched_files: self.watched_files.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl<D: Device> Device for SharedDevice<D> {
+    async fn name(&self) -> BrightnessResult<String> {
+        self.device.lock().await.name().await
+    }
+
+    fn kind(&self) -> DeviceKind {
+        self.kind
+    }
+
+    fn backlight_type(&self) -> Option<BacklightType> {
+        self.backlight_type
+    }
+
+    fn id(&self) -> Option<String> {
+        self.id.clone()
+    }
+
+    fn aliases(&self) -> Vec<String> {
+        self.aliases.clone()
+    }
+
+    fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    fn watched_files(&self) -> Vec<PathBuf> {
+        self.watched_files.clone()
+    }
+
+    async fn get(&self) -> BrightnessResult<u32> {
+        self.device.lock().await.get().await
+    }
+
+    async fn raw(&self) -> BrightnessResult<Option<RawBrightness>> {
+        self.device.lock().await.raw().await
+    }
+
+    async fn set(&mut self, percent: u32) -> BrightnessResult<()> {
+        self.device.lock().await.set(percent).await
+    }
+
+    async fn set_raw(&mut self, value: u32) -> BrightnessResult<()> {
+        self.device.lock().await.set_raw(value).await
+    }
+}
@@ -0,0 +1,220 @@
+use super::{
+    percent_from_raw, raw_from_percent, Backend, BacklightType, BrightnessResult, Device,
+    DeviceKind, RawBrightness,
+};
+use async_trait::async_trait;
+use futures::{stream, stream::BoxStream, StreamExt};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// Default location of the sysfs mount point.
+pub const DEFAULT_SYSFS_ROOT: &str = "/sys";
+
+const BACKLIGHT_CLASS: &str = "class/backlight";
+const LEDS_CLASS: &str = "class/leds";
+
+/// Backend reading and writing the brightness attributes in sysfs directly.
+#[derive(Debug)]
+pub struct SysfsBackend {
+    root: PathBuf,
+    classes: Vec<(&'static str, DeviceKind)>,
+}
+
+/// A device found in `<sysfs root>/class/backlight` or `<sysfs root>/class/leds`.
+#[derive(Debug)]
+pub struct SysfsDevice {
+    name: String,
+    kind: DeviceKind,
+    id: Option<String>,
+    path: PathBuf,
+    max_brightness: u32,
+    backlight_type: Option<BacklightType>,
+}
+
+impl SysfsBackend {
+    /// Creates a backend providing the backlights and the LEDs of the sysfs tree mounted at
+    /// `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        SysfsBackend {
+            root: root.into(),
+            classes: vec![
+                (BACKLIGHT_CLASS, DeviceKind::Backlight),
+                (LEDS_CLASS, DeviceKind::Led),
+            ],
+        }
+    }
+
+    /// Creates a backend providing only the LEDs of the sysfs tree mounted at `root`.
+    pub fn leds(root: impl Into<PathBuf>) -> Self {
+        SysfsBackend {
+            root: root.into(),
+            classes: vec![(LEDS_CLASS, DeviceKind::Led)],
+        }
+    }
+
+    /// Lists the devices of the sysfs tree, sorted by class and by name.
+    fn read_devices(&self) -> io::Result<Vec<BrightnessResult<SysfsDevice>>> {
+        let mut devices = Vec::new();
+        let root = fs::canonicalize(&self.root).ok();
+        for (class, kind) in &self.classes {
+            let entries = match fs::read_dir(self.root.join(class)) {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            let mut paths = entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            paths.sort();
+            devices.extend(
+                paths
+                    .into_iter()
+                    .map(|path| SysfsDevice::open(path, *kind, root.as_deref())),
+            );
+        }
+        Ok(devices)
+    }
+}
+
+impl Backend for SysfsBackend {
+    type Device = SysfsDevice;
+
+    fn devices(&self) -> BoxStream<'_, BrightnessResult<Self::Device>> {
+        match self.read_devices() {
+            Ok(devices) => stream::iter(devices).boxed(),
+            Err(err) => {
+                stream::once(async { Err(brightness::Error::ListingDevicesFailed(Box::new(err))) })
+                    .boxed()
+            }
+        }
+    }
+}
+
+impl SysfsDevice {
+    /// Reads the static attributes of the device at `path`, in the sysfs tree mounted at `root`.
+    ///
+    /// The identifier of the device is the location of the device in the device tree without the
+    /// indices of the DRM cards, such as
+    /// `sysfs:devices/pci0000:00/0000:00:02.0/drm/card/card-eDP-1/intel_backlight`, which does not
+    /// depend on the order in which the drivers are loaded.
+    fn open(path: PathBuf, kind: DeviceKind, root: Option<&Path>) -> BrightnessResult<Self> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let max_brightness = read_u32(&path.join("max_brightness")).map_err(|err| {
+            brightness::Error::GettingDeviceInfoFailed {
+                device: name.clone(),
+                source: Box::new(err),
+            }
+        })?;
+        let backlight_type = fs::read_to_string(path.join("type"))
+            .ok()
+            .and_then(|contents| contents.trim().parse().ok());
+        let id = root
+            .zip(fs::canonicalize(&path).ok())
+            .and_then(|(root, path)| {
+                path.strip_prefix(root)
+                    .ok()
+                    .map(|path| format!("sysfs:{}", without_card_indices(path)))
+            });
+        Ok(SysfsDevice {
+            name,
+            kind,
+            id,
+            path,
+            max_brightness,
+            backlight_type,
+        })
+    }
+
+    /// Reads the current raw brightness, preferring the level reported by the hardware when the
+    /// device exposes it.
+    fn read_raw(&self) -> BrightnessResult<u32> {
+        read_u32(&self.path.join("actual_brightness"))
+            .or_else(|_| read_u32(&self.path.join("brightness")))
+            .map_err(|err| brightness::Error::GettingDeviceInfoFailed {
+                device: self.name.clone(),
+                source: Box::new(err),
+            })
+    }
+}
+
+#[async_trait]
+impl Device for SysfsDevice {
+    async fn name(&self) -> BrightnessResult<String> {
+        Ok(self.name.clone())
+    }
+
+    fn kind(&self) -> DeviceKind {
+        self.kind
+    }
+
+    fn backlight_type(&self) -> Option<BacklightType> {
+        self.backlight_type
+    }
+
+    fn id(&self) -> Option<String> {
+        self.id.clone()
+    }
+
+    fn watched_files(&self) -> Vec<PathBuf> {
+        ["brightness", "actual_brightness"]
+            .iter()
+            .map(|attribute| self.path.join(attribute))
+            .filter(|path| path.exists())
+            .collect()
+    }
+
+    async fn get(&self) -> BrightnessResult<u32> {
+        Ok(percent_from_raw(self.read_raw()?, self.max_brightness))
+    }
+
+    async fn raw(&self) -> BrightnessResult<Option<RawBrightness>> {
+        Ok(Some(RawBrightness {
+            value: self.read_raw()?,
+            max: self.max_brightness,
+        }))
+    }
+
+    async fn set(&mut self, percent: u32) -> BrightnessResult<()> {
+        self.set_raw(raw_from_percent(percent, self.max_brightness))
+            .await
+    }
+
+    async fn set_raw(&mut self, value: u32) -> BrightnessResult<()> {
+        let raw = value.min(self.max_brightness);
+        fs::write(self.path.join("brightness"), raw.to_string()).map_err(|err| {
+            brightness::Error::SettingBrightnessFailed {
+                device: self.name.clone(),
+                source: Box::new(err),
+            }
+        })
+    }
+}
+
+/// Returns `path` without the indices of the DRM cards, such as `card1` in
+/// `drm/card1/card1-eDP-1`, which are given in the order in which the drivers are loaded.
+fn without_card_indices(path: &Path) -> String {
+    path.iter()
+        .map(|component| {
+            let component = component.to_string_lossy();
+            match component.strip_prefix("card") {
+                Some(rest) => format!(
+                    "card{}",
+                    rest.trim_start_matches(|c: char| c.is_ascii_digit())
+                ),
+                None => component.into_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Reads a sysfs attribute holding a single unsigned integer.
+fn read_u32(path: &Path) -> io::Result<u32> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
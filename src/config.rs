@@ -0,0 +1,447 @@
+use crate::backend::{DeviceKind, Limits};
+use crate::error::Error;
+use crate::scale::Scale;
+use crate::schedule::TimeOfDay;
+use crate::transition::parse_duration;
+use crate::{MAX_BRIGHTNESS, MAX_CONCURRENCY, MIN_BRIGHTNESS};
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fs, io};
+
+/// Step (in percent) used by `inc` and `dec` when none is given.
+pub const DEFAULT_STEP: u32 = 10;
+
+/// Represents the settings read from the configuration file, such as
+/// `~/.config/rumos/config.toml`.
+///
+/// ```toml
+/// [defaults]
+/// format = "{name}: {percent}%"
+/// step = 5
+/// scale = "cie"
+///
+/// [devices."tpacpi::kbd_backlight"]
+/// min = 0
+/// scale = "linear"
+///
+/// [aliases]
+/// kbd = "tpacpi::kbd_backlight"
+///
+/// [auto]
+/// curve = [[0, 10], [100, 40], [1000, 80]]
+/// hysteresis = 3
+///
+/// [schedule]
+/// latitude = 48.85
+/// longitude = 2.35
+/// points = [
+///     { time = "sunrise", percent = 80 },
+///     { time = "sunset-30min", percent = 50 },
+///     { time = "23:00", percent = 20 },
+/// ]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Settings used by every command and every device.
+    pub defaults: Defaults,
+    /// Settings of the devices, by device name, alias or kind (`backlight`, `led` or `ddc`).
+    ///
+    /// The settings given for the name of a device take precedence over those given for one of
+    /// its aliases, which take precedence over those given for its kind.
+    pub devices: BTreeMap<String, DeviceConfig>,
+    /// Other names of the devices, mapped to a device name, a pattern such as `amdgpu_bl*` or the
+    /// identifier of a device.
+    pub aliases: BTreeMap<String, String>,
+    /// Settings of the brightness adjusted to the ambient light by `rumos auto`.
+    pub auto: AutoConfig,
+    /// Settings of the brightness following the time of day with `rumos schedule`.
+    pub schedule: ScheduleConfig,
+}
+
+/// Represents the settings used by every command and every device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Defaults {
+    /// Output format used when none is given: `json`, `json-lines` or a template.
+    pub format: Option<String>,
+    /// Step (in percent) used by `inc` and `dec` when none is given.
+    pub step: u32,
+    /// Brightness scale of the devices.
+    pub scale: Scale,
+    /// Lowest brightness level (in percent) that can be set.
+    pub min: u32,
+    /// Highest brightness level (in percent) that can be set.
+    pub max: u32,
+    /// Maximum number of devices changed at the same time, or 0 for no limit.
+    pub concurrency: usize,
+}
+
+/// Represents the settings of a single device, overriding the defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// Lowest brightness level (in percent) that can be set on the device.
+    pub min: Option<u32>,
+    /// Highest brightness level (in percent) that can be set on the device.
+    pub max: Option<u32>,
+    /// Brightness scale of the device.
+    pub scale: Option<Scale>,
+}
+
+/// Represents the settings of the brightness adjusted to the ambient light.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoConfig {
+    /// Points mapping an illuminance (in lux) to a brightness level (in percent), by increasing
+    /// illuminance. The levels between two points are interpolated linearly.
+    pub curve: Vec<(f64, u32)>,
+    /// Smallest difference (in percent) between the current and the new level for which the
+    /// brightness is changed.
+    pub hysteresis: u32,
+    /// Weight in `0..1` of the previous readings in the illuminance, `0` following the sensor
+    /// immediately.
+    pub smoothing: f64,
+    /// Delay between two readings of the sensor.
+    #[serde(with = "duration")]
+    pub interval: Duration,
+    /// Time taken to reach a new level.
+    #[serde(with = "duration")]
+    pub transition: Duration,
+    /// Name of the IIO device of the sensor, such as `iio:device0`, or `None` for the first
+    /// light sensor found.
+    pub sensor: Option<String>,
+}
+
+/// Represents the brightness levels scheduled over a day.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Latitude (in degrees, positive to the north) used to compute the sunrise and the sunset.
+    pub latitude: Option<f64>,
+    /// Longitude (in degrees, positive to the east) used to compute the sunrise and the sunset.
+    pub longitude: Option<f64>,
+    /// Time taken to fade from the level of a point to the level of the next one, ending at the
+    /// time of the next point.
+    #[serde(with = "duration")]
+    pub transition: Duration,
+    /// Delay between two checks of the schedule.
+    #[serde(with = "duration")]
+    pub interval: Duration,
+    /// Levels to reach at given times of the day.
+    pub points: Vec<SchedulePoint>,
+}
+
+/// Represents a brightness level to reach at a time of the day.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchedulePoint {
+    /// The time, such as `07:30`, `sunrise+30min` or `sunset-1h`.
+    pub time: TimeOfDay,
+    /// The brightness level (in percent).
+    pub percent: u32,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        ScheduleConfig {
+            latitude: None,
+            longitude: None,
+            transition: Duration::from_secs(30 * 60),
+            interval: Duration::from_secs(60),
+            points: Vec::new(),
+        }
+    }
+}
+
+impl Default for AutoConfig {
+    fn default() -> Self {
+        AutoConfig {
+            curve: vec![
+                (0.0, 10),
+                (10.0, 20),
+                (100.0, 40),
+                (1000.0, 70),
+                (10000.0, 100),
+            ],
+            hysteresis: 5,
+            smoothing: 0.5,
+            interval: Duration::from_secs(2),
+            transition: Duration::from_secs(1),
+            sensor: None,
+        }
+    }
+}
+
+impl Default for Defaults {
+    fn default() -> Self {
+        Defaults {
+            format: None,
+            step: DEFAULT_STEP,
+            scale: Scale::Linear,
+            min: MIN_BRIGHTNESS,
+            max: MAX_BRIGHTNESS,
+            concurrency: MAX_CONCURRENCY.unwrap_or(0),
+        }
+    }
+}
+
+impl Config {
+    /// Returns the location of the configuration file: `$XDG_CONFIG_HOME/rumos/config.toml`,
+    /// falling back to `~/.config/rumos/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("rumos").join("config.toml"))
+    }
+
+    /// Reads the configuration file at `path`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the default configuration if the file does not exist, and an `rumos::Error` if it
+    /// cannot be read or holds invalid settings.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let invalid = |explanation: String| Error::ConfigError {
+            path: path.to_path_buf(),
+            explanation,
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => return Err(invalid(err.to_string())),
+        };
+        let config: Config = toml::from_str(&contents).map_err(|err| invalid(err.to_string()))?;
+        config.validate().map_err(invalid)?;
+        Ok(config)
+    }
+
+    /// Checks that every limit lies in `0..=100`, that no minimum exceeds its maximum and that
+    /// every alias maps to a valid pattern.
+    pub fn validate(&self) -> Result<(), String> {
+        self.auto.validate()?;
+        self.schedule.validate()?;
+        for (alias, target) in &self.aliases {
+            Pattern::new(target).map_err(|err| {
+                format!("Invalid pattern `{target}` for the alias `{alias}`: {err}")
+            })?;
+        }
+        let devices = self
+            .devices
+            .iter()
+            .map(|(name, device)| (format!("devices.\"{name}\""), self.limits_of(device)));
+        for (section, limits) in
+            std::iter::once(("defaults".to_string(), self.default_limits())).chain(devices)
+        {
+            if limits.max > MAX_BRIGHTNESS || limits.min > limits.max {
+                return Err(format!(
+                    "Invalid limits in [{section}]: min {} and max {} must satisfy \
+                     0 <= min <= max <= {MAX_BRIGHTNESS}",
+                    limits.min, limits.max
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the maximum number of devices changed at the same time.
+    pub fn concurrency(&self) -> Option<usize> {
+        Some(self.defaults.concurrency).filter(|&concurrency| concurrency > 0)
+    }
+
+    /// Returns the aliases of the device named `name` with the identifier `id`.
+    ///
+    /// An alias belongs to the device if it maps to the name of the device, to a pattern matching
+    /// that name, or to the identifier of the device.
+    pub fn aliases_of(&self, name: &str, id: Option<&str>) -> Vec<String> {
+        self.aliases
+            .iter()
+            .filter(|(_, target)| {
+                Some(target.as_str()) == id
+                    || Pattern::new(target).map_or(*target == name, |pattern| pattern.matches(name))
+            })
+            .map(|(alias, _)| alias.clone())
+            .collect()
+    }
+
+    /// Returns the brightness scale of the device named `name` with the given `aliases` and
+    /// `kind`.
+    pub fn scale(&self, name: &str, aliases: &[String], kind: DeviceKind) -> Scale {
+        self.device_configs(name, aliases, kind)
+            .find_map(|device| device.scale)
+            .unwrap_or(self.defaults.scale)
+    }
+
+    /// Returns the brightness limits of the device named `name` with the given `aliases` and
+    /// `kind`.
+    pub fn limits(&self, name: &str, aliases: &[String], kind: DeviceKind) -> Limits {
+        Limits {
+            min: self
+                .device_configs(name, aliases, kind)
+                .find_map(|device| device.min)
+                .unwrap_or(self.defaults.min),
+            max: self
+                .device_configs(name, aliases, kind)
+                .find_map(|device| device.max)
+                .unwrap_or(self.defaults.max),
+        }
+    }
+
+    /// Returns the settings matching the device named `name` with the given `aliases` and `kind`,
+    /// from the most to the least specific.
+    fn device_configs<'a>(
+        &'a self,
+        name: &'a str,
+        aliases: &'a [String],
+        kind: DeviceKind,
+    ) -> impl Iterator<Item = &'a DeviceConfig> + 'a {
+        std::iter::once(name)
+            .chain(aliases.iter().map(String::as_str))
+            .filter_map(|key| self.devices.get(key))
+            .chain(self.devices.get(&kind.to_string()))
+    }
+
+    fn default_limits(&self) -> Limits {
+        Limits {
+            min: self.defaults.min,
+            max: self.defaults.max,
+        }
+    }
+
+    fn limits_of(&self, device: &DeviceConfig) -> Limits {
+        Limits {
+            min: device.min.unwrap_or(self.defaults.min),
+            max: device.max.unwrap_or(self.defaults.max),
+        }
+    }
+
+    /// Formats the configuration as it would be written in the configuration file.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("The configuration is always representable in TOML")
+    }
+}
+
+impl AutoConfig {
+    /// Checks that the curve has points by increasing illuminance with levels in `0..=100`, and
+    /// that the smoothing lies in `0..1`.
+    fn validate(&self) -> Result<(), String> {
+        if self.curve.is_empty() {
+            return Err("Invalid curve in [auto]: it needs at least one point".to_string());
+        }
+        if let Some((lux, percent)) = self
+            .curve
+            .iter()
+            .find(|(lux, percent)| !(lux.is_finite() && *lux >= 0.0) || *percent > MAX_BRIGHTNESS)
+        {
+            return Err(format!(
+                "Invalid curve in [auto]: the point [{lux}, {percent}] must have a non-negative \
+                 illuminance and a level in 0..={MAX_BRIGHTNESS}"
+            ));
+        }
+        if self
+            .curve
+            .windows(2)
+            .any(|points| points[0].0 >= points[1].0)
+        {
+            return Err(
+                "Invalid curve in [auto]: the points must be sorted by increasing illuminance"
+                    .to_string(),
+            );
+        }
+        if !(0.0..1.0).contains(&self.smoothing) {
+            return Err(format!(
+                "Invalid smoothing in [auto]: {} must lie in 0..1",
+                self.smoothing
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the brightness level (in percent) mapped to the illuminance `lux` by the curve,
+    /// the levels beyond the first and the last points being those of these points.
+    pub fn level(&self, lux: f64) -> u32 {
+        let Some(&(first_lux, first_percent)) = self.curve.first() else {
+            return MAX_BRIGHTNESS;
+        };
+        if lux <= first_lux {
+            return first_percent;
+        }
+        for points in self.curve.windows(2) {
+            let ((low_lux, low), (high_lux, high)) = (points[0], points[1]);
+            if lux <= high_lux {
+                let ratio = (lux - low_lux) / (high_lux - low_lux);
+                return (f64::from(low) + ratio * (f64::from(high) - f64::from(low))).round()
+                    as u32;
+            }
+        }
+        self.curve
+            .last()
+            .map_or(MAX_BRIGHTNESS, |&(_, percent)| percent)
+    }
+}
+
+impl ScheduleConfig {
+    /// Checks that the position lies on Earth, that the points relative to the sun come with a
+    /// position, and that every level lies in `0..=100`.
+    fn validate(&self) -> Result<(), String> {
+        if let Some(latitude) = self
+            .latitude
+            .filter(|latitude| !(-90.0..=90.0).contains(latitude))
+        {
+            return Err(format!(
+                "Invalid latitude in [schedule]: {latitude} must lie in -90..=90"
+            ));
+        }
+        if let Some(longitude) = self
+            .longitude
+            .filter(|longitude| !(-180.0..=180.0).contains(longitude))
+        {
+            return Err(format!(
+                "Invalid longitude in [schedule]: {longitude} must lie in -180..=180"
+            ));
+        }
+        for point in &self.points {
+            if point.percent > MAX_BRIGHTNESS {
+                return Err(format!(
+                    "Invalid point in [schedule]: the level {} of `{}` must lie in \
+                     0..={MAX_BRIGHTNESS}",
+                    point.percent, point.time
+                ));
+            }
+            if point.time.is_solar() && (self.latitude.is_none() || self.longitude.is_none()) {
+                return Err(format!(
+                    "Invalid point in [schedule]: `{}` needs the latitude and the longitude",
+                    point.time
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Serializes a `Duration` as a string such as `2s` or `300ms`.
+mod duration {
+    use super::parse_duration;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        let millis = duration.as_millis();
+        if !millis.is_multiple_of(1000) {
+            serializer.serialize_str(&format!("{millis}ms"))
+        } else if millis > 0 && millis.is_multiple_of(60_000) {
+            serializer.serialize_str(&format!("{}min", millis / 60_000))
+        } else {
+            serializer.serialize_str(&format!("{}s", millis / 1000))
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        parse_duration(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
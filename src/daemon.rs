@@ -0,0 +1,466 @@
+use crate::backend::{Backend, Shared};
+use crate::error::Error;
+use crate::funcs::{
+    ApplyOptions, BrightnessCommand, BrightnessOutput, Command, DeviceSelector, Report,
+};
+use crate::protocol::{
+    Request, RequestBody, Response, ResponseBody, Settings, Target, PROTOCOL_VERSION,
+};
+use crate::transition::Transition;
+use crate::watch::DEFAULT_WATCH_INTERVAL;
+use crate::MAX_BRIGHTNESS;
+use futures::channel::{mpsc, oneshot};
+use futures::executor;
+use futures::StreamExt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::ops::ControlFlow;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fs, thread};
+
+/// Represents a request waiting to be dispatched to a thread executing it.
+pub(crate) struct Job {
+    pub(crate) body: RequestBody,
+    pub(crate) reply: oneshot::Sender<ResponseBody>,
+}
+
+/// Represents a connection to a running daemon.
+#[derive(Debug)]
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    settings: Option<Settings>,
+}
+
+/// Serves the requests of the clients connecting to `socket`, using the devices of `backend`.
+///
+/// The devices are enumerated once, then the requests are executed concurrently, the latest change
+/// on a device cancelling the transition running on it before reading its level. This function
+/// only returns if the socket cannot be set up.
+///
+/// # Arguments
+///
+/// * `backend`: The backend providing the devices, usually configured with a `Config`.
+/// * `socket`: The location of the socket, replaced if no daemon listens on it anymore.
+/// * `concurrency`: The maximum number of devices changed at the same time, or `None` for no
+///   limit.
+/// * `settings`: The settings of `backend`, the requests sent with other settings being refused,
+///   or `None` to execute every request.
+pub fn serve<B: Backend>(
+    backend: B,
+    socket: &Path,
+    concurrency: Option<usize>,
+    settings: Option<Settings>,
+) -> Result<(), Error> {
+    let io_error = |source| Error::IoError {
+        path: socket.to_path_buf(),
+        source,
+    };
+    if UnixStream::connect(socket).is_ok() {
+        return Err(Error::DaemonError(format!(
+            "Another daemon listens on {}",
+            socket.display()
+        )));
+    }
+    match fs::remove_file(socket) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(io_error(err)),
+        _ => {}
+    }
+    let listener = UnixListener::bind(socket).map_err(io_error)?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600)).map_err(io_error)?;
+
+    let backend = share(&backend);
+    let jobs = spawn_worker(backend.clone(), concurrency);
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let jobs = jobs.clone();
+        let backend = backend.clone();
+        let settings = settings.clone();
+        thread::spawn(move || {
+            let _ = serve_connection(stream, &*backend, &jobs, settings.as_ref());
+        });
+    }
+    Ok(())
+}
+
+/// Enumerates the devices of `backend` once, the devices that cannot be opened being reported by
+/// every request selecting them.
+pub(crate) fn share<B: Backend>(backend: &B) -> Arc<Shared<B::Device>> {
+    Arc::new(executor::block_on(Shared::new(backend)))
+}
+
+/// Starts the thread dispatching the requests sent to the devices of `backend`.
+///
+/// # Returns
+///
+/// The queue of the requests other than subscriptions. Each request is executed by a thread of
+/// its own, so that a change made on a device stops the transition running on it, as a newer
+/// invocation would, and reading the levels never waits for the end of a transition.
+pub(crate) fn spawn_worker<B: Backend + 'static>(
+    backend: Arc<B>,
+    concurrency: Option<usize>,
+) -> mpsc::UnboundedSender<Job> {
+    let (jobs, mut queue) = mpsc::unbounded::<Job>();
+    thread::spawn(move || {
+        while let Some(job) = executor::block_on(queue.next()) {
+            let backend = backend.clone();
+            // The claims of the transitions lock files, so the jobs cannot share an executor.
+            thread::spawn(move || {
+                let response = executor::block_on(execute(&*backend, job.body, concurrency));
+                let _ = job.reply.send(response);
+            });
+        }
+    });
+    jobs
+}
+
+/// Answers the requests sent over `stream` until the client disconnects.
+fn serve_connection<B: Backend>(
+    stream: UnixStream,
+    backend: &B,
+    jobs: &mpsc::UnboundedSender<Job>,
+    settings: Option<&Settings>,
+) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let checked = parse_request(&line).and_then(|request| {
+            let expected = settings.zip(request.settings.as_ref());
+            match expected.and_then(|(ours, theirs)| ours.mismatch(theirs)) {
+                Some(message) => Err(message),
+                None => Ok(request),
+            }
+        });
+        let request = match checked {
+            Ok(request) => request,
+            Err(message) => {
+                send(
+                    &mut writer,
+                    ResponseBody::Error {
+                        message,
+                        unmatched: Vec::new(),
+                    },
+                )?;
+                continue;
+            }
+        };
+        if let RequestBody::Subscribe {
+            selector,
+            interval_ms,
+        } = request.body
+        {
+            let interval = interval_ms.map_or(DEFAULT_WATCH_INTERVAL, Duration::from_millis);
+            let mut result = Ok(());
+            let mut first = true;
+            selector.watch(backend, interval, |report| {
+                let report = report.clone();
+                let body = if first {
+                    ResponseBody::Report { report }
+                } else {
+                    ResponseBody::Changed { report }
+                };
+                first = false;
+                result = send(&mut writer, body);
+                match result {
+                    Ok(()) => ControlFlow::Continue(()),
+                    Err(_) => ControlFlow::Break(()),
+                }
+            });
+            return result;
+        }
+        let (reply, response) = oneshot::channel();
+        let body = request.body;
+        if jobs.unbounded_send(Job { body, reply }).is_err() {
+            return Ok(());
+        }
+        let Ok(response) = executor::block_on(response) else {
+            return Ok(());
+        };
+        send(&mut writer, response)?;
+    }
+    Ok(())
+}
+
+/// Parses a line sent by a client, rejecting the versions of the protocol that differ from
+/// ours.
+fn parse_request(line: &str) -> Result<Request, String> {
+    let value = serde_json::from_str::<serde_json::Value>(line)
+        .map_err(|err| format!("Invalid request: {err}"))?;
+    match value.get("version").and_then(serde_json::Value::as_u64) {
+        Some(version) if version == u64::from(PROTOCOL_VERSION) => {}
+        Some(version) => {
+            return Err(format!(
+                "Unsupported protocol version {version}, the daemon speaks version \
+                 {PROTOCOL_VERSION}"
+            ))
+        }
+        None => return Err("Invalid request: missing field `version`".to_string()),
+    }
+    serde_json::from_value(value).map_err(|err| format!("Invalid request: {err}"))
+}
+
+/// Writes a response as a single line.
+fn send(writer: &mut UnixStream, body: ResponseBody) -> io::Result<()> {
+    let mut line = serde_json::to_string(&Response::from(body))?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())?;
+    writer.flush()
+}
+
+/// Executes a request other than a subscription on the devices of `backend`.
+async fn execute<B: Backend>(
+    backend: &B,
+    body: RequestBody,
+    concurrency: Option<usize>,
+) -> ResponseBody {
+    let command = match body {
+        RequestBody::List => Command::List {
+            output: BrightnessOutput::Quiet,
+        },
+        RequestBody::Get { target } => {
+            brightness_command(BrightnessCommand::Get, target, None, concurrency)
+        }
+        RequestBody::Set {
+            percent,
+            target,
+            transition,
+        } => brightness_command(
+            BrightnessCommand::Set {
+                percent: percent.min(MAX_BRIGHTNESS),
+            },
+            target,
+            transition,
+            concurrency,
+        ),
+        RequestBody::Fade {
+            percent,
+            target,
+            transition,
+        } => brightness_command(
+            BrightnessCommand::Set {
+                percent: percent.min(MAX_BRIGHTNESS),
+            },
+            target,
+            Some(transition),
+            concurrency,
+        ),
+        RequestBody::Inc {
+            percent,
+            target,
+            transition,
+        } => brightness_command(
+            BrightnessCommand::Inc { percent },
+            target,
+            transition,
+            concurrency,
+        ),
+        RequestBody::Dec {
+            percent,
+            target,
+            transition,
+        } => brightness_command(
+            BrightnessCommand::Dec { percent },
+            target,
+            transition,
+            concurrency,
+        ),
+        RequestBody::Max { target, transition } => {
+            brightness_command(BrightnessCommand::Max, target, transition, concurrency)
+        }
+        RequestBody::Min { target, transition } => {
+            brightness_command(BrightnessCommand::Min, target, transition, concurrency)
+        }
+        RequestBody::Subscribe { .. } => {
+            unreachable!("The subscriptions are served by the thread of their connection")
+        }
+    };
+    match command.handle(backend).await {
+        Ok(report) => ResponseBody::Report { report },
+        Err(Error::UnmatchedDevices(unmatched)) => ResponseBody::Error {
+            message: Error::UnmatchedDevices(unmatched.clone()).to_string(),
+            unmatched,
+        },
+        Err(err) => ResponseBody::Error {
+            message: err.to_string(),
+            unmatched: Vec::new(),
+        },
+    }
+}
+
+fn brightness_command(
+    command: BrightnessCommand,
+    target: Target,
+    transition: Option<Transition>,
+    concurrency: Option<usize>,
+) -> Command {
+    Command::BrightnessCommand {
+        command,
+        selector: target.selector,
+        output: BrightnessOutput::Quiet,
+        options: ApplyOptions {
+            transition,
+            concurrency,
+            allow_missing: target.allow_missing,
+        },
+    }
+}
+
+impl Client {
+    /// Connects to the daemon listening on `socket`.
+    pub fn connect(socket: &Path) -> io::Result<Self> {
+        let writer = UnixStream::connect(socket)?;
+        Ok(Client {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+            settings: None,
+        })
+    }
+
+    /// Sends `settings` along with every request, so that the daemon refuses the requests if it
+    /// uses other settings.
+    pub fn with_settings(mut self, settings: Settings) -> Self {
+        self.settings = Some(settings);
+        self
+    }
+
+    /// Sends a request other than a subscription, then waits for its report.
+    pub fn request(&mut self, body: RequestBody) -> Result<Report, Error> {
+        self.send(body)?;
+        match self.receive()? {
+            ResponseBody::Report { report } | ResponseBody::Changed { report } => Ok(report),
+            ResponseBody::Error { unmatched, .. } if !unmatched.is_empty() => {
+                Err(Error::UnmatchedDevices(unmatched))
+            }
+            ResponseBody::Error { message, .. } => Err(Error::DaemonError(message)),
+        }
+    }
+
+    /// Subscribes to the changes of the devices chosen by `selector`, calling `on_change` with
+    /// the state of these devices, then with the state of the devices whose brightness changed,
+    /// until it breaks.
+    pub fn subscribe(
+        &mut self,
+        selector: DeviceSelector,
+        interval: Duration,
+        on_change: impl FnMut(&Report) -> ControlFlow<()>,
+    ) -> Result<(), Error> {
+        let command = Command::Watch {
+            selector,
+            output: BrightnessOutput::Quiet,
+            interval,
+        };
+        self.follow(command.request(), on_change)
+    }
+
+    /// Sends a subscription, then calls `on_change` with every report received until it breaks.
+    pub fn follow(
+        &mut self,
+        subscription: RequestBody,
+        mut on_change: impl FnMut(&Report) -> ControlFlow<()>,
+    ) -> Result<(), Error> {
+        self.send(subscription)?;
+        loop {
+            let report = match self.receive()? {
+                ResponseBody::Report { report } | ResponseBody::Changed { report } => report,
+                ResponseBody::Error { message, .. } => return Err(Error::DaemonError(message)),
+            };
+            if on_change(&report).is_break() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn send(&mut self, body: RequestBody) -> Result<(), Error> {
+        let request = Request {
+            settings: self.settings.clone(),
+            ..Request::from(body)
+        };
+        let mut line =
+            serde_json::to_string(&request).map_err(|err| Error::DaemonError(err.to_string()))?;
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .map_err(|err| Error::DaemonError(format!("Cannot reach the daemon: {err}")))
+    }
+
+    fn receive(&mut self) -> Result<ResponseBody, Error> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => {
+                return Err(Error::DaemonError(
+                    "The daemon closed the connection".to_string(),
+                ))
+            }
+            Ok(_) => {}
+            Err(err) => {
+                return Err(Error::DaemonError(format!(
+                    "Cannot reach the daemon: {err}"
+                )))
+            }
+        }
+        let response = serde_json::from_str::<Response>(&line)
+            .map_err(|err| Error::DaemonError(format!("Invalid response: {err}")))?;
+        if response.version != PROTOCOL_VERSION {
+            return Err(Error::DaemonError(format!(
+                "Unsupported protocol version {}, expected version {PROTOCOL_VERSION}",
+                response.version
+            )));
+        }
+        Ok(response.body)
+    }
+}
+
+impl Command {
+    /// Converts the command into the request executing it on the daemon.
+    pub fn request(&self) -> RequestBody {
+        match self {
+            Command::List { .. } => RequestBody::List,
+            Command::Watch {
+                selector, interval, ..
+            } => RequestBody::Subscribe {
+                selector: selector.clone(),
+                interval_ms: Some(interval.as_millis().min(u128::from(u64::MAX)) as u64),
+            },
+            Command::BrightnessCommand {
+                command,
+                selector,
+                options,
+                ..
+            } => {
+                let target = Target {
+                    selector: selector.clone(),
+                    allow_missing: options.allow_missing,
+                };
+                let transition = options.transition;
+                match *command {
+                    BrightnessCommand::Get => RequestBody::Get { target },
+                    BrightnessCommand::Set { percent } => RequestBody::Set {
+                        percent,
+                        target,
+                        transition,
+                    },
+                    BrightnessCommand::Inc { percent } => RequestBody::Inc {
+                        percent,
+                        target,
+                        transition,
+                    },
+                    BrightnessCommand::Dec { percent } => RequestBody::Dec {
+                        percent,
+                        target,
+                        transition,
+                    },
+                    BrightnessCommand::Max => RequestBody::Max { target, transition },
+                    BrightnessCommand::Min => RequestBody::Min { target, transition },
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use crate::backend::Backend;
+use crate::daemon::{share, spawn_worker, Job};
+use crate::error::Error;
+use crate::funcs::{DeviceRecord, DeviceSelector, ErrorKind, Report};
+use crate::protocol::{RequestBody, ResponseBody, Target};
+use crate::transition::Transition;
+use crate::watch::DEFAULT_WATCH_INTERVAL;
+use futures::channel::{mpsc, oneshot};
+use futures::executor;
+use futures::future::{BoxFuture, FutureExt};
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time::Duration;
+use zbus::names::InterfaceName;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, Value};
+use zbus::{dbus_interface, fdo, Connection, ConnectionBuilder, SignalContext};
+
+/// Well-known name of the service on the bus.
+pub const DBUS_NAME: &str = "org.rumos.Brightness1";
+
+/// Path of the object listing and changing the devices.
+pub const DBUS_PATH: &str = "/org/rumos/Brightness1";
+
+/// Name of the interface of the objects representing the devices.
+const DEVICE_INTERFACE: &str = "org.rumos.Brightness1.Device";
+
+/// Reads the state of the devices chosen by a selector from the shared devices, without waiting
+/// for the changes handed over to the worker.
+type Reader = Arc<dyn Fn(DeviceSelector) -> BoxFuture<'static, Report> + Send + Sync>;
+
+/// Represents the object at `DBUS_PATH`, implementing the `org.rumos.Brightness1` interface.
+struct Manager {
+    jobs: mpsc::UnboundedSender<Job>,
+    read: Reader,
+    devices: Vec<(u32, String, OwnedObjectPath)>,
+}
+
+/// Represents the object of a device, at `/org/rumos/Brightness1/devices/<index>`.
+struct DeviceObject {
+    read: Reader,
+    record: DeviceRecord,
+}
+
+/// Serves the devices of `backend` on a D-Bus bus under the name `org.rumos.Brightness1`.
+///
+/// The devices are enumerated once and changed as with the daemon, while their levels are read
+/// directly, without waiting for the transitions running on them. Every device is exposed as an
+/// object whose properties reflect its state, and a `PropertiesChanged` signal is emitted whenever
+/// its brightness changes, whoever changed it. This function only returns if the service cannot
+/// be set up.
+///
+/// # Arguments
+///
+/// * `backend`: The backend providing the devices, usually configured with a `Config`.
+/// * `address`: The address of the bus, or `None` for the session bus.
+/// * `concurrency`: The maximum number of devices changed at the same time, or `None` for no
+///   limit.
+pub fn serve_dbus<B: Backend>(
+    backend: B,
+    address: Option<&str>,
+    concurrency: Option<usize>,
+) -> Result<(), Error> {
+    let backend = share(&backend);
+    let jobs = spawn_worker(backend.clone(), concurrency);
+    let read: Reader = {
+        let backend = backend.clone();
+        Arc::new(move |selector| {
+            let backend = backend.clone();
+            async move { selector.report(&*backend).await }.boxed()
+        })
+    };
+    let report = executor::block_on(request(&jobs, RequestBody::List)).map_err(dbus_error)?;
+    let connection = executor::block_on(connect(address, &jobs, &read, report.devices))
+        .map_err(|err| Error::DaemonError(format!("Cannot serve on the D-Bus bus: {err}")))?;
+    let mut first = true;
+    DeviceSelector::All.watch(&*backend, DEFAULT_WATCH_INTERVAL, |report| {
+        // The first report holds the state of every device, not changes.
+        if !first {
+            for record in &report.devices {
+                let _ = executor::block_on(properties_changed(&connection, record));
+            }
+        }
+        first = false;
+        ControlFlow::Continue(())
+    });
+    Ok(())
+}
+
+/// Connects to the bus, exporting the objects of the manager and of `devices` before taking the
+/// name of the service.
+async fn connect(
+    address: Option<&str>,
+    jobs: &mpsc::UnboundedSender<Job>,
+    read: &Reader,
+    devices: Vec<DeviceRecord>,
+) -> zbus::Result<Connection> {
+    let mut builder = match address {
+        Some(address) => ConnectionBuilder::address(address)?,
+        None => ConnectionBuilder::session()?,
+    };
+    let mut listed = Vec::new();
+    for record in devices {
+        let path = device_path(record.index)?;
+        listed.push((
+            record.index as u32,
+            record.name.clone().unwrap_or_default(),
+            path.clone(),
+        ));
+        builder = builder.serve_at(
+            path,
+            DeviceObject {
+                read: read.clone(),
+                record,
+            },
+        )?;
+    }
+    let manager = Manager {
+        jobs: jobs.clone(),
+        read: read.clone(),
+        devices: listed,
+    };
+    builder
+        .serve_at(DBUS_PATH, manager)?
+        .name(DBUS_NAME)?
+        .build()
+        .await
+}
+
+/// Emits the `PropertiesChanged` signal of the object of a device whose brightness changed.
+async fn properties_changed(connection: &Connection, record: &DeviceRecord) -> zbus::Result<()> {
+    let path = device_path(record.index)?;
+    let context = SignalContext::new(connection, path)?;
+    let mut changed = HashMap::new();
+    let percent = record.percent.map(Value::from);
+    let raw = record.raw.map(Value::from);
+    if let Some(percent) = &percent {
+        changed.insert("Brightness", percent);
+    }
+    if let Some(raw) = &raw {
+        changed.insert("RawBrightness", raw);
+    }
+    if changed.is_empty() {
+        return Ok(());
+    }
+    fdo::Properties::properties_changed(
+        &context,
+        InterfaceName::from_static_str_unchecked(DEVICE_INTERFACE),
+        &changed,
+        &[],
+    )
+    .await
+}
+
+/// Returns the path of the object of the device at `index`.
+fn device_path(index: usize) -> zbus::Result<OwnedObjectPath> {
+    Ok(ObjectPath::try_from(format!("{DBUS_PATH}/devices/{index}"))?.into())
+}
+
+/// Hands a request over to the worker, then waits for its report.
+async fn request(jobs: &mpsc::UnboundedSender<Job>, body: RequestBody) -> fdo::Result<Report> {
+    let (reply, response) = oneshot::channel();
+    jobs.unbounded_send(Job { body, reply })
+        .map_err(|_| fdo::Error::Failed("The devices are not served anymore".to_string()))?;
+    match response.await {
+        Ok(ResponseBody::Report { report }) | Ok(ResponseBody::Changed { report }) => Ok(report),
+        Ok(ResponseBody::Error { message, unmatched }) if !unmatched.is_empty() => {
+            Err(fdo::Error::InvalidArgs(message))
+        }
+        Ok(ResponseBody::Error { message, .. }) => Err(fdo::Error::Failed(message)),
+        Err(_) => Err(fdo::Error::Failed(
+            "The devices are not served anymore".to_string(),
+        )),
+    }
+}
+
+/// Hands a request over to the worker, failing if a device failed.
+///
+/// # Returns
+///
+/// The name and the brightness level (in percent) of every selected device.
+async fn change(
+    jobs: &mpsc::UnboundedSender<Job>,
+    body: RequestBody,
+) -> fdo::Result<Vec<(String, u32)>> {
+    levels(request(jobs, body).await?)
+}
+
+/// Reads the devices chosen by `target` directly, failing if a device failed.
+///
+/// # Returns
+///
+/// The name and the brightness level (in percent) of every selected device.
+async fn read_levels(read: &Reader, target: Target) -> fdo::Result<Vec<(String, u32)>> {
+    let report = read(target.selector).await;
+    if !report.unmatched.is_empty() && !target.allow_missing {
+        let message = Error::UnmatchedDevices(report.unmatched).to_string();
+        return Err(fdo::Error::InvalidArgs(message));
+    }
+    levels(report)
+}
+
+/// Returns the name and the brightness level (in percent) of every device of `report`, failing
+/// if a device failed.
+fn levels(report: Report) -> fdo::Result<Vec<(String, u32)>> {
+    let failures = report
+        .devices
+        .iter()
+        .filter_map(|record| {
+            let error = record.error.as_ref()?;
+            let name = record
+                .name
+                .clone()
+                .unwrap_or_else(|| record.index.to_string());
+            Some((name, error, record.error_kind))
+        })
+        .collect::<Vec<_>>();
+    if let Some((_, _, kind)) = failures.first() {
+        let message = failures
+            .iter()
+            .map(|(name, error, _)| format!("{name}: {error}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        return Err(match kind {
+            Some(ErrorKind::PermissionDenied) => fdo::Error::AccessDenied(message),
+            _ => fdo::Error::Failed(message),
+        });
+    }
+    Ok(report
+        .devices
+        .into_iter()
+        .map(|record| {
+            (
+                record.name.unwrap_or_default(),
+                record.percent.unwrap_or_default(),
+            )
+        })
+        .collect())
+}
+
+/// Chooses the devices named by a method argument: the devices of `default` when it is empty,
+/// the device at an index when it is a number, and the devices with a name or alias otherwise.
+fn target(device: &str, default: DeviceSelector) -> Target {
+    let selector = if device.is_empty() {
+        default
+    } else if let Ok(index) = device.parse::<usize>() {
+        DeviceSelector::ByIndex(HashSet::from([index]))
+    } else {
+        DeviceSelector::ByName(Arc::new(HashSet::from([device.to_string()])))
+    };
+    Target {
+        selector,
+        allow_missing: false,
+    }
+}
+
+/// Converts a duration in milliseconds given to a method into a transition, `0` meaning none.
+fn transition(transition_ms: u32) -> Option<Transition> {
+    (transition_ms > 0).then(|| Transition {
+        duration: Duration::from_millis(u64::from(transition_ms)),
+        easing: Default::default(),
+    })
+}
+
+fn dbus_error(err: fdo::Error) -> Error {
+    Error::DaemonError(err.to_string())
+}
+
+#[dbus_interface(name = "org.rumos.Brightness1")]
+impl Manager {
+    /// Lists the index, the name and the object path of every device.
+    fn list(&self) -> Vec<(u32, String, OwnedObjectPath)> {
+        self.devices.clone()
+    }
+
+    /// Returns the name and the brightness level (in percent) of the selected devices.
+    async fn get(&self, device: &str) -> fdo::Result<Vec<(String, u32)>> {
+        read_levels(&self.read, target(device, DeviceSelector::All)).await
+    }
+
+    /// Sets the brightness level (in percent) of the selected devices, fading to it over
+    /// `transition_ms` milliseconds.
+    async fn set(
+        &self,
+        device: &str,
+        percent: u32,
+        transition_ms: u32,
+    ) -> fdo::Result<Vec<(String, u32)>> {
+        let body = RequestBody::Set {
+            percent,
+            target: target(device, DeviceSelector::default_for_changes()),
+            transition: transition(transition_ms),
+        };
+        change(&self.jobs, body).await
+    }
+
+    /// Increases, or decreases when `delta` is negative, the brightness level (in percent) of
+    /// the selected devices, fading to it over `transition_ms` milliseconds.
+    async fn adjust(
+        &self,
+        device: &str,
+        delta: i32,
+        transition_ms: u32,
+    ) -> fdo::Result<Vec<(String, u32)>> {
+        let target = target(device, DeviceSelector::default_for_changes());
+        let transition = transition(transition_ms);
+        let percent = delta.unsigned_abs();
+        let body = if delta < 0 {
+            RequestBody::Dec {
+                percent,
+                target,
+                transition,
+            }
+        } else {
+            RequestBody::Inc {
+                percent,
+                target,
+                transition,
+            }
+        };
+        change(&self.jobs, body).await
+    }
+}
+
+impl DeviceObject {
+    /// Reads the current state of the device.
+    async fn state(&self) -> fdo::Result<DeviceRecord> {
+        let selector = DeviceSelector::ByIndex(HashSet::from([self.record.index]));
+        let record = (self.read)(selector).await.devices.pop();
+        match record {
+            Some(DeviceRecord {
+                error: Some(error), ..
+            }) => Err(fdo::Error::Failed(error)),
+            Some(record) => Ok(record),
+            None => Err(fdo::Error::Failed("The device is gone".to_string())),
+        }
+    }
+}
+
+#[dbus_interface(name = "org.rumos.Brightness1.Device")]
+impl DeviceObject {
+    /// Index of the device among all the devices.
+    #[dbus_interface(property)]
+    fn index(&self) -> u32 {
+        self.record.index as u32
+    }
+
+    /// Name of the device.
+    #[dbus_interface(property)]
+    fn name(&self) -> String {
+        self.record.name.clone().unwrap_or_default()
+    }
+
+    /// Identifier of the device that does not change across reboots, empty if it has none.
+    #[dbus_interface(property)]
+    fn id(&self) -> String {
+        self.record.id.clone().unwrap_or_default()
+    }
+
+    /// Other names given to the device in the configuration.
+    #[dbus_interface(property)]
+    fn aliases(&self) -> Vec<String> {
+        self.record.aliases.clone()
+    }
+
+    /// Kind of the device, such as `backlight`.
+    #[dbus_interface(property)]
+    fn kind(&self) -> String {
+        self.record
+            .kind
+            .map(|kind| kind.to_string())
+            .unwrap_or_default()
+    }
+
+    /// Lowest level (in percent) that the methods may set.
+    #[dbus_interface(property)]
+    fn min_level(&self) -> u32 {
+        self.record.limits.unwrap_or_default().min
+    }
+
+    /// Highest level (in percent) that the methods may set.
+    #[dbus_interface(property)]
+    fn max_level(&self) -> u32 {
+        self.record.limits.unwrap_or_default().max
+    }
+
+    /// Brightness level (in percent).
+    #[dbus_interface(property)]
+    async fn brightness(&self) -> fdo::Result<u32> {
+        self.state()
+            .await?
+            .percent
+            .ok_or_else(|| fdo::Error::Failed("The level cannot be read".to_string()))
+    }
+
+    /// Brightness level in the units of the device, `0` if they are not known.
+    #[dbus_interface(property)]
+    async fn raw_brightness(&self) -> fdo::Result<u32> {
+        Ok(self.state().await?.raw.unwrap_or_default())
+    }
+
+    /// Maximum brightness level in the units of the device, `0` if they are not known.
+    #[dbus_interface(property)]
+    async fn max_raw_brightness(&self) -> fdo::Result<u32> {
+        Ok(self.state().await?.max.unwrap_or_default())
+    }
+}
@@ -0,0 +1,540 @@
+use crate::udev::UDEV_GROUP as VIDEO_GROUP;
+use colored::Colorize;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+
+/// Directories searched for udev rules, from the most to the least specific.
+pub const DEFAULT_UDEV_RULE_DIRS: [&str; 3] = [
+    "/etc/udev/rules.d",
+    "/run/udev/rules.d",
+    "/usr/lib/udev/rules.d",
+];
+/// Default location of the file listing the groups of the system.
+pub const DEFAULT_GROUP_FILE: &str = "/etc/group";
+/// Default location of the socket of the system D-Bus.
+pub const DEFAULT_SYSTEM_BUS_SOCKET: &str = "/run/dbus/system_bus_socket";
+
+/// Group usually granted access to the `i2c-dev` character devices.
+const I2C_GROUP: &str = "i2c";
+
+const BRIGHTNESS_CLASSES: [&str; 2] = ["class/backlight", "class/leds"];
+
+/// Represents the environment in which rumos runs, as seen by `rumos doctor`.
+///
+/// Every location and every property of the user can be replaced, so that the checks can be
+/// run against a fake sysfs tree.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    /// Mount point of sysfs.
+    pub sysfs_root: PathBuf,
+    /// Directory containing the device nodes, such as the `i2c-*` buses.
+    pub dev_root: PathBuf,
+    /// Directories searched for udev rules.
+    pub udev_rule_dirs: Vec<PathBuf>,
+    /// File listing the groups of the system, in the format of `/etc/group`.
+    pub group_file: PathBuf,
+    /// Socket of the system D-Bus, used unless `DBUS_SYSTEM_BUS_ADDRESS` names another one.
+    pub system_bus_socket: PathBuf,
+    /// User ID of the user running rumos.
+    pub uid: u32,
+    /// Group IDs of the user running rumos.
+    pub gids: Vec<u32>,
+    /// Environment variables of the user running rumos.
+    pub vars: HashMap<String, String>,
+}
+
+/// Represents the result of a single check made by `rumos doctor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    /// Topic of the check, such as `sysfs` or `i2c-dev`.
+    pub topic: String,
+    /// Whether the check passed.
+    pub status: Status,
+    /// Description of what was found.
+    pub summary: String,
+    /// Steps fixing the problem, if the check did not pass.
+    pub remediation: Vec<String>,
+}
+
+/// Represents the outcome of a `Check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    /// Nothing needs to be done.
+    Ok,
+    /// Some features may not work.
+    Warning,
+    /// Some devices cannot be controlled.
+    Error,
+}
+
+/// Represents a group of the system.
+struct Group {
+    name: String,
+    gid: u32,
+    members: Vec<String>,
+}
+
+impl Environment {
+    /// Describes the environment of the current process, with sysfs mounted at `sysfs_root` and
+    /// the device nodes found in `dev_root`.
+    pub fn current(sysfs_root: impl Into<PathBuf>, dev_root: impl Into<PathBuf>) -> Self {
+        let (uid, gids) = current_ids();
+        Environment {
+            sysfs_root: sysfs_root.into(),
+            dev_root: dev_root.into(),
+            udev_rule_dirs: DEFAULT_UDEV_RULE_DIRS.iter().map(PathBuf::from).collect(),
+            group_file: PathBuf::from(DEFAULT_GROUP_FILE),
+            system_bus_socket: PathBuf::from(DEFAULT_SYSTEM_BUS_SOCKET),
+            uid,
+            gids,
+            vars: env::vars().collect(),
+        }
+    }
+
+    /// Runs every check, in the order in which they are printed.
+    pub fn diagnose(&self) -> Vec<Check> {
+        let groups = self.read_groups();
+        let logind = self.session().status == Status::Ok && self.system_bus().status == Status::Ok;
+        let mut checks = self.check_devices(&groups, logind);
+        checks.push(self.check_video_group(&groups));
+        checks.push(self.session());
+        checks.push(self.system_bus());
+        checks.push(self.check_udev_rules());
+        checks.push(self.check_i2c(&groups));
+        checks
+    }
+
+    /// Checks that the brightness of every backlight and every LED can be written.
+    fn check_devices(&self, groups: &[Group], logind: bool) -> Vec<Check> {
+        let mut checks = Vec::new();
+        for class in BRIGHTNESS_CLASSES {
+            let mut paths = match fs::read_dir(self.sysfs_root.join(class)) {
+                Ok(entries) => entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .collect::<Vec<_>>(),
+                Err(_) => continue,
+            };
+            paths.sort();
+            for path in paths {
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                checks.push(self.check_device(&name, &path, groups, logind));
+            }
+        }
+        if checks.is_empty() {
+            checks.push(Check {
+                topic: "sysfs".to_string(),
+                status: Status::Warning,
+                summary: format!(
+                    "No backlight or LED found in {}",
+                    self.sysfs_root.join("class").display()
+                ),
+                remediation: vec![
+                    "Laptop panels appear in /sys/class/backlight once the graphics driver is \
+                     loaded; external monitors are controlled over DDC/CI instead"
+                        .to_string(),
+                ],
+            });
+        }
+        checks
+    }
+
+    /// Checks that the brightness of the device at `path` can be written.
+    fn check_device(&self, name: &str, path: &Path, groups: &[Group], logind: bool) -> Check {
+        let topic = format!("sysfs {name}");
+        let brightness = path.join("brightness");
+        let metadata = match fs::metadata(&brightness) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                return Check {
+                    topic,
+                    status: Status::Error,
+                    summary: format!("Cannot read {}: {err}", brightness.display()),
+                    remediation: vec!["Check that sysfs is mounted and readable".to_string()],
+                }
+            }
+        };
+        if self.can_access(&metadata, false, true) {
+            return Check {
+                topic,
+                status: Status::Ok,
+                summary: "The brightness is writable".to_string(),
+                remediation: Vec::new(),
+            };
+        }
+        let group = group_name(groups, file_gid(&metadata));
+        let mut remediation = Vec::new();
+        if group_writable(&metadata) && group != "root" {
+            remediation.push(format!(
+                "Add your user to the `{group}` group with `sudo usermod -aG {group} $USER`, \
+                 then log in again"
+            ));
+        } else {
+            remediation.push(format!(
+                "Install a udev rule giving the `{VIDEO_GROUP}` group write access to the \
+                 brightness with `sudo rumos setup udev`, then add your user to the \
+                 `{VIDEO_GROUP}` group"
+            ));
+        }
+        if logind {
+            return Check {
+                topic,
+                status: Status::Warning,
+                summary: format!(
+                    "The brightness is not writable by the user (group {group}), but the \
+                     brightness backend can change it through logind"
+                ),
+                remediation,
+            };
+        }
+        Check {
+            topic,
+            status: Status::Error,
+            summary: format!("The brightness is not writable by the user (group {group})"),
+            remediation,
+        }
+    }
+
+    /// Checks that the user belongs to the group usually owning the brightness files.
+    fn check_video_group(&self, groups: &[Group]) -> Check {
+        let topic = "groups".to_string();
+        match groups.iter().find(|group| group.name == VIDEO_GROUP) {
+            None => Check {
+                topic,
+                status: Status::Warning,
+                summary: format!("There is no `{VIDEO_GROUP}` group"),
+                remediation: vec![format!("Create it with `sudo groupadd {VIDEO_GROUP}`")],
+            },
+            Some(group) if self.in_group(group) => Check {
+                topic,
+                status: Status::Ok,
+                summary: format!("The user belongs to the `{VIDEO_GROUP}` group"),
+                remediation: Vec::new(),
+            },
+            Some(_) => Check {
+                topic,
+                status: Status::Warning,
+                summary: format!("The user does not belong to the `{VIDEO_GROUP}` group"),
+                remediation: vec![format!(
+                    "Add your user to it with `sudo usermod -aG {VIDEO_GROUP} $USER`, then log \
+                     in again"
+                )],
+            },
+        }
+    }
+
+    /// Checks that rumos runs in a logind session, through which backlights can be changed
+    /// without write access to sysfs.
+    fn session(&self) -> Check {
+        let topic = "logind".to_string();
+        match self.vars.get("XDG_SESSION_ID").filter(|id| !id.is_empty()) {
+            Some(id) => Check {
+                topic,
+                status: Status::Ok,
+                summary: format!("Running in the logind session {id}"),
+                remediation: Vec::new(),
+            },
+            None => Check {
+                topic,
+                status: Status::Warning,
+                summary: "Not running in a logind session".to_string(),
+                remediation: vec![
+                    "Run rumos from a login session, or grant write access to sysfs with udev \
+                     rules"
+                        .to_string(),
+                ],
+            },
+        }
+    }
+
+    /// Checks that the system D-Bus, through which logind is reached, is running.
+    fn system_bus(&self) -> Check {
+        let topic = "d-bus".to_string();
+        let socket = self
+            .vars
+            .get("DBUS_SYSTEM_BUS_ADDRESS")
+            .and_then(|address| address.strip_prefix("unix:path="))
+            .map(|path| PathBuf::from(path.split(',').next().unwrap_or(path)))
+            .unwrap_or_else(|| self.system_bus_socket.clone());
+        if socket.exists() {
+            Check {
+                topic,
+                status: Status::Ok,
+                summary: format!("The system bus listens on {}", socket.display()),
+                remediation: Vec::new(),
+            }
+        } else {
+            Check {
+                topic,
+                status: Status::Warning,
+                summary: format!(
+                    "The system bus is not running ({} is missing)",
+                    socket.display()
+                ),
+                remediation: vec!["Start it with `sudo systemctl start dbus`".to_string()],
+            }
+        }
+    }
+
+    /// Checks that a udev rule grants access to the brightness files.
+    fn check_udev_rules(&self) -> Check {
+        let topic = "udev".to_string();
+        let mut rules = Vec::new();
+        for dir in &self.udev_rule_dirs {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            let mut paths = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|extension| extension == "rules")
+                })
+                .collect::<Vec<_>>();
+            paths.sort();
+            rules.extend(paths.into_iter().filter(|path| {
+                fs::read_to_string(path).is_ok_and(|contents| {
+                    contents.lines().any(|line| {
+                        !line.trim_start().starts_with('#')
+                            && (line.contains("backlight") || line.contains("leds"))
+                            && line.contains("brightness")
+                    })
+                })
+            }));
+        }
+        if rules.is_empty() {
+            Check {
+                topic,
+                status: Status::Warning,
+                summary: "No udev rule grants access to the brightness files".to_string(),
+                remediation: vec![format!(
+                    "Install a rule giving the `{VIDEO_GROUP}` group write access to the \
+                     brightness of every backlight and LED with `sudo rumos setup udev`"
+                )],
+            }
+        } else {
+            let rules = rules
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>();
+            Check {
+                topic,
+                status: Status::Ok,
+                summary: format!("Brightness rules found in {}", rules.join(", ")),
+                remediation: Vec::new(),
+            }
+        }
+    }
+
+    /// Checks that the `i2c-dev` buses, through which external monitors are reached, exist and
+    /// can be opened.
+    fn check_i2c(&self, groups: &[Group]) -> Check {
+        let topic = "i2c-dev".to_string();
+        let mut buses = fs::read_dir(&self.dev_root)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| {
+                        path.file_name()
+                            .and_then(|name| name.to_str())
+                            .and_then(|name| name.strip_prefix("i2c-"))
+                            .is_some_and(|bus| bus.parse::<u32>().is_ok())
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        buses.sort();
+        if buses.is_empty() {
+            return Check {
+                topic,
+                status: Status::Warning,
+                summary: format!(
+                    "No I2C bus found in {}, external monitors cannot be controlled over DDC/CI",
+                    self.dev_root.display()
+                ),
+                remediation: vec![
+                    "Load the module with `sudo modprobe i2c-dev`, and add `i2c-dev` to \
+                     /etc/modules-load.d/i2c-dev.conf to load it at boot"
+                        .to_string(),
+                ],
+            };
+        }
+        let denied = buses
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok().map(|metadata| (path, metadata)))
+            .filter(|(_, metadata)| !self.can_access(metadata, true, true))
+            .collect::<Vec<_>>();
+        let Some((_, metadata)) = denied.first() else {
+            return Check {
+                topic,
+                status: Status::Ok,
+                summary: format!("{} I2C buses can be opened", buses.len()),
+                remediation: Vec::new(),
+            };
+        };
+        let group = group_name(groups, file_gid(metadata));
+        let remediation = if group_writable(metadata) && group != "root" {
+            format!(
+                "Add your user to the `{group}` group with `sudo usermod -aG {group} $USER`, \
+                 then log in again"
+            )
+        } else {
+            format!(
+                "Install a udev rule such as: KERNEL==\"i2c-[0-9]*\", GROUP=\"{I2C_GROUP}\", \
+                 MODE=\"0660\", then add your user to the `{I2C_GROUP}` group"
+            )
+        };
+        Check {
+            topic,
+            status: Status::Error,
+            summary: format!(
+                "{} of {} I2C buses cannot be opened by the user (group {group})",
+                denied.len(),
+                buses.len()
+            ),
+            remediation: vec![remediation],
+        }
+    }
+
+    /// Reads the groups of the system, ignoring the malformed lines.
+    fn read_groups(&self) -> Vec<Group> {
+        fs::read_to_string(&self.group_file)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(':');
+                let name = fields.next()?;
+                let gid = fields.nth(1)?.parse().ok()?;
+                let members = fields
+                    .next()
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter(|member| !member.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                Some(Group {
+                    name: name.to_string(),
+                    gid,
+                    members,
+                })
+            })
+            .collect()
+    }
+
+    /// Checks whether the user belongs to `group`, either now or after logging in again.
+    fn in_group(&self, group: &Group) -> bool {
+        self.gids.contains(&group.gid)
+            || self
+                .vars
+                .get("USER")
+                .is_some_and(|user| group.members.contains(user))
+    }
+
+    /// Checks whether the user can read (if `read`) and write (if `write`) the file described by
+    /// `metadata`.
+    fn can_access(&self, metadata: &Metadata, read: bool, write: bool) -> bool {
+        if self.uid == 0 {
+            return true;
+        }
+        let mode = file_mode(metadata);
+        let shift = if file_uid(metadata) == self.uid {
+            6
+        } else if self.gids.contains(&file_gid(metadata)) {
+            3
+        } else {
+            0
+        };
+        let bits = (mode >> shift) & 0o7;
+        (!read || bits & 0o4 != 0) && (!write || bits & 0o2 != 0)
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let status = match self.status {
+            Status::Ok => "ok".green().bold(),
+            Status::Warning => "warning".yellow().bold(),
+            Status::Error => "error".red().bold(),
+        };
+        write!(f, "[{status}] {}: {}", self.topic.bold(), self.summary)?;
+        for step in &self.remediation {
+            write!(f, "\n    - {step}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the name of the group `gid`, or the ID itself if the group is unknown.
+fn group_name(groups: &[Group], gid: u32) -> String {
+    groups
+        .iter()
+        .find(|group| group.gid == gid)
+        .map_or_else(|| gid.to_string(), |group| group.name.clone())
+}
+
+/// Checks whether the members of the group owning a file can write it.
+fn group_writable(metadata: &Metadata) -> bool {
+    file_mode(metadata) & 0o020 != 0
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &Metadata) -> u32 {
+    std::os::unix::fs::MetadataExt::mode(metadata)
+}
+
+#[cfg(unix)]
+fn file_uid(metadata: &Metadata) -> u32 {
+    std::os::unix::fs::MetadataExt::uid(metadata)
+}
+
+#[cfg(unix)]
+fn file_gid(metadata: &Metadata) -> u32 {
+    std::os::unix::fs::MetadataExt::gid(metadata)
+}
+
+#[cfg(not(unix))]
+fn file_mode(metadata: &Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o666
+    }
+}
+
+#[cfg(not(unix))]
+fn file_uid(_metadata: &Metadata) -> u32 {
+    0
+}
+
+#[cfg(not(unix))]
+fn file_gid(_metadata: &Metadata) -> u32 {
+    0
+}
+
+/// Returns the user ID and the group IDs of the current process.
+#[cfg(target_os = "linux")]
+fn current_ids() -> (u32, Vec<u32>) {
+    // SAFETY: These functions only read the credentials of the process, and `getgroups` writes
+    // at most `count` IDs to a buffer of that size.
+    unsafe {
+        let mut gids = vec![libc::getegid()];
+        let count = libc::getgroups(0, std::ptr::null_mut());
+        if count > 0 {
+            let mut groups = vec![0; count as usize];
+            let count = libc::getgroups(count, groups.as_mut_ptr());
+            groups.truncate(count.max(0) as usize);
+            gids.extend(groups);
+        }
+        (libc::geteuid(), gids)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn current_ids() -> (u32, Vec<u32>) {
+    (0, Vec::new())
+}
@@ -1,24 +1,46 @@
+use crate::funcs::Unmatched;
 use std::error::Error as StdError;
 use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub enum Error {
     BrightnessError(brightness::Error),
-    PrintError {
-        explanation: String,
-        source: brightness::Error,
-    },
+    ConfigError { path: PathBuf, explanation: String },
+    InvalidOptions(String),
+    UnmatchedDevices(Vec<Unmatched>),
+    EmptySchedule,
+    IoError { path: PathBuf, source: io::Error },
+    DaemonError(String),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::BrightnessError(err) => write!(f, "Brightness error: {err}"),
-            Error::PrintError {
-                explanation,
-                source,
-            } => write!(f, "{explanation}. Reason: {source}"),
-            // Handle other error variants here if needed
+            Error::ConfigError { path, explanation } => {
+                write!(
+                    f,
+                    "Invalid configuration file {}: {explanation}",
+                    path.display()
+                )
+            }
+            Error::InvalidOptions(explanation) => {
+                write!(f, "Invalid command-line options: {explanation}")
+            }
+            Error::UnmatchedDevices(unmatched) => {
+                let messages = unmatched.iter().map(ToString::to_string);
+                write!(f, "{}", messages.collect::<Vec<_>>().join("\n"))
+            }
+            Error::EmptySchedule => write!(
+                f,
+                "No brightness level is scheduled in the [schedule] section of the configuration"
+            ),
+            Error::DaemonError(message) => write!(f, "Daemon error: {message}"),
+            Error::IoError { path, source } => {
+                write!(f, "Cannot access {}: {source}", path.display())
+            }
         }
     }
 }
@@ -27,7 +49,12 @@ impl StdError for Error {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match self {
             Error::BrightnessError(err) => Some(err),
-            Error::PrintError { source, .. } => Some(source), // Return the source of other error variants here if needed
+            Error::IoError { source, .. } => Some(source),
+            Error::ConfigError { .. }
+            | Error::InvalidOptions(_)
+            | Error::UnmatchedDevices(_)
+            | Error::EmptySchedule
+            | Error::DaemonError(_) => None,
         }
     }
 }
@@ -1,29 +1,164 @@
+use crate::backend::{Backend, BacklightType, BrightnessResult, Device, DeviceKind, Limits};
 use crate::error::Error;
-use crate::{MAX_BRIGHTNESS, MAX_CONCURRENCY, MIN_BRIGHTNESS};
-use brightness::{Brightness, BrightnessDevice};
-use colored::Colorize;
-use futures::{future::join, future::ready, stream::BoxStream, StreamExt, TryStreamExt};
-use std::{collections::HashSet, sync::Arc};
+use crate::protocol::SelectorSpec;
+use crate::template::Template;
+use crate::transition::{Claim, Handover, Transition};
+use crate::{MAX_BRIGHTNESS, MAX_CONCURRENCY};
+use futures::{future::join3, stream, stream::BoxStream, StreamExt};
+use glob::Pattern;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::error::Error as StdError;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use std::{collections::HashSet, io, sync::Arc};
 
-type BrightnessResult<T> = Result<T, brightness::Error>;
+/// Lowest similarity between a requested name and the name of a device suggested in its place.
+const SUGGESTION_SIMILARITY: f64 = 0.8;
+/// Number of device names suggested in place of a name matching no device.
+const MAX_SUGGESTIONS: usize = 3;
 
+/// A device along with its index among all the devices of the backend.
+type IndexedDevice<D> = (usize, BrightnessResult<D>);
+
+/// Represents the way the report of a command is rendered.
+#[derive(Debug, Clone)]
 pub enum BrightnessOutput {
     Default,
     Percent,
+    Json,
+    JsonLines,
+    Template(Template),
     Quiet,
 }
 
-/// Represents various commands to be executed.
+/// Represents the outcome of a command on every selected device.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Report {
+    /// The state of the selected devices, in the order of the backend.
+    pub devices: Vec<DeviceRecord>,
+    /// The parts of the selector that match no device.
+    pub unmatched: Vec<Unmatched>,
+}
+
+/// Represents a part of a `DeviceSelector` that matches no device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unmatched {
+    /// A name, alias, pattern or regular expression matching no device, along with the names of
+    /// the devices that look alike.
+    Name {
+        name: String,
+        suggestions: Vec<String>,
+    },
+    /// An index beyond the last device.
+    Index { index: usize, count: usize },
+}
+
+/// Represents the state of a device after the execution of a command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceRecord {
+    /// Index of the device among all the devices of the backend.
+    pub index: usize,
+    /// Name of the device, unless it could not be retrieved.
+    pub name: Option<String>,
+    /// Identifier of the device that does not change across reboots, if the device has one.
+    pub id: Option<String>,
+    /// Other names given to the device in the configuration.
+    pub aliases: Vec<String>,
+    /// Kind of the device, unless the device could not be retrieved.
+    pub kind: Option<DeviceKind>,
+    /// Type of the backlight, if the device is a backlight exposing it.
+    pub backlight_type: Option<BacklightType>,
+    /// Lowest and highest levels (in percent) that commands may set, unless the device could not
+    /// be retrieved.
+    pub limits: Option<Limits>,
+    /// Brightness level (in percent) before the execution of a command changing it.
+    pub before: Option<u32>,
+    /// Brightness level (in percent), unless it could not be retrieved.
+    pub percent: Option<u32>,
+    /// Brightness level in the units of the device, if they are known.
+    pub raw: Option<u32>,
+    /// Maximum brightness level in the units of the device, if they are known.
+    pub max: Option<u32>,
+    /// Description of the errors that occurred while changing the device or retrieving its
+    /// state.
+    pub error: Option<String>,
+    /// Category of the first of these errors.
+    pub error_kind: Option<ErrorKind>,
+}
+
+/// Represents the category of an error that occurred on a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    /// The device could not be accessed with the permissions of the user.
+    PermissionDenied,
+    /// The backend could not list its devices.
+    BackendUnavailable,
+    /// Any other failure of the device.
+    DeviceFailed,
+}
+
+/// Represents the overall result of a command, from which the exit code of the command-line
+/// utility is derived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Every selected device was handled.
+    Success,
+    /// Some of the selected devices failed, or all of them failed for other reasons than
+    /// permissions.
+    PartialFailure,
+    /// No device matched the selector.
+    NoMatch,
+    /// Every selected device failed because of missing permissions.
+    PermissionDenied,
+    /// The devices could not be listed.
+    BackendUnavailable,
+}
+
+/// Represents various commands to be executed by the command-line utility.
 pub enum Command {
     BrightnessCommand {
         command: BrightnessCommand,
         selector: DeviceSelector,
         output: BrightnessOutput,
+        options: ApplyOptions,
+    },
+    List {
+        output: BrightnessOutput,
+    },
+    Watch {
+        selector: DeviceSelector,
+        output: BrightnessOutput,
+        /// Delay between two readings of the devices whose changes cannot be watched.
+        interval: Duration,
     },
-    List,
+}
+
+/// Represents the way a `BrightnessCommand` changes the devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApplyOptions {
+    /// Transition used to reach the new levels, or `None` to set them at once.
+    pub transition: Option<Transition>,
+    /// Maximum number of devices changed at the same time, or `None` for no limit.
+    pub concurrency: Option<usize>,
+    /// Whether the command is executed even though some of the requested devices do not exist.
+    pub allow_missing: bool,
+}
+
+impl Default for ApplyOptions {
+    fn default() -> Self {
+        ApplyOptions {
+            transition: None,
+            concurrency: MAX_CONCURRENCY,
+            allow_missing: false,
+        }
+    }
 }
 
 /// Represents a command to be executed on a brightness device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BrightnessCommand {
     Get,
     Set { percent: u32 },
@@ -34,255 +169,515 @@ pub enum BrightnessCommand {
 }
 
 /// Represents a device selector used to choose a set of brightness devices.
+///
+/// Names, patterns and regular expressions match the name of a device or one of its aliases.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(into = "SelectorSpec", try_from = "SelectorSpec")]
 pub enum DeviceSelector {
+    #[default]
     All,
     ByName(Arc<HashSet<String>>),
     ByIndex(HashSet<usize>),
+    ByKind(HashSet<DeviceKind>),
+    /// Devices matching one of the glob patterns, such as `amdgpu_bl*`.
+    ByPattern(Vec<Pattern>),
+    /// Devices matching one of the regular expressions, anywhere in their names.
+    ByRegex(Vec<Regex>),
+    /// Devices chosen by every one of the selectors.
+    AllOf(Vec<DeviceSelector>),
+    /// Devices chosen by at least one of the selectors.
+    AnyOf(Vec<DeviceSelector>),
+    /// Devices not chosen by the selector.
+    Not(Box<DeviceSelector>),
+}
+
+/// Represents the properties of a device that selectors are matched against.
+struct Candidate {
+    index: usize,
+    name: Option<String>,
+    aliases: Vec<String>,
+    kind: DeviceKind,
+}
+
+/// Represents the devices chosen by a selector.
+pub(crate) struct Selection<D> {
+    pub(crate) devices: Vec<IndexedDevice<D>>,
+    pub(crate) unmatched: Vec<Unmatched>,
 }
 
 impl Command {
-    /// Handles the execution of a `Command`.
+    /// Handles the execution of a `Command` using the devices provided by `backend`.
     ///
     /// # Returns
     ///
-    /// Returns `Ok(())` if the command is executed successfully. Otherwise, returns an `rumos::Error`.
-    pub async fn handle(&self) -> Result<(), Error> {
+    /// Returns the `Report` of the command if it is executed successfully. Otherwise, returns an
+    /// `rumos::Error`.
+    pub async fn handle<B: Backend>(&self, backend: &B) -> Result<Report, Error> {
         match self {
             Command::BrightnessCommand {
                 command,
                 selector,
-                output,
-            } => {
-                command
-                    .handle(Self::stream_selected_devices(selector))
-                    .await?;
-                Self::print_device_brightnessess(Self::stream_selected_devices(selector), output)
-                    .await;
-            }
-            Command::List => {
-                println!("Available devices:");
-                Self::print_device_names(Self::stream_selected_devices(&DeviceSelector::All)).await;
-            }
+                options,
+                ..
+            } => command.apply_with(backend, selector, options).await,
+            Command::List { .. } => Ok(DeviceSelector::All.report(backend).await),
+            Command::Watch { selector, .. } => Ok(selector.report(backend).await),
         }
-        Ok(())
     }
 
-    /// Retrieves a stream of brightness devices based on the provided device selector.
+    /// Retrieves the brightness devices chosen by the provided device selector.
     ///
     /// # Arguments
     ///
+    /// * `backend`: The backend providing the devices.
     /// * `selector`: The device selector specifying which devices to retrieve.
     ///
     /// # Returns
     ///
-    /// Returns a stream of brightness devices wrapped in a `BoxStream`.
-    fn stream_selected_devices(
+    /// Returns the chosen devices and their indices, along with the names, patterns and indices
+    /// of the selector that match no device.
+    pub(crate) async fn select_devices<B: Backend>(
+        backend: &B,
+        selector: &DeviceSelector,
+    ) -> Selection<B::Device> {
+        Self::select_devices_of(backend, selector, &selector.kinds()).await
+    }
+
+    /// Retrieves the brightness devices chosen by the provided device selector, as
+    /// `select_devices` does, among the devices that `backend` provides for `kinds`.
+    pub(crate) async fn select_devices_of<B: Backend>(
+        backend: &B,
         selector: &DeviceSelector,
-    ) -> BoxStream<BrightnessResult<BrightnessDevice>> {
-        async fn filter_by_name(
-            device_names: Arc<HashSet<String>>,
-            device: BrightnessResult<BrightnessDevice>,
-        ) -> Option<BrightnessResult<BrightnessDevice>> {
-            if let Ok(device) = device {
-                if device
-                    .device_name()
-                    .await
-                    .is_ok_and(|name| device_names.contains(&name))
-                {
-                    return Some(Ok(device));
+        kinds: &HashSet<DeviceKind>,
+    ) -> Selection<B::Device> {
+        let mut devices = Vec::new();
+        let mut candidates = Vec::new();
+        let mut count = 0;
+        let mut failed = false;
+        let mut stream = backend.devices_of(kinds).enumerate();
+        while let Some((index, dev)) = stream.next().await {
+            count += 1;
+            match dev {
+                Ok(device) => {
+                    let candidate = Candidate {
+                        index,
+                        name: device.name().await.ok(),
+                        aliases: device.aliases(),
+                        kind: device.kind(),
+                    };
+                    if selector.matches(&candidate) {
+                        devices.push((index, Ok(device)));
+                    }
+                    candidates.push(candidate);
+                }
+                Err(err) => {
+                    // Only the index of a device that cannot be opened is known, so its failure
+                    // is reported unless the index rules it out.
+                    if selector.matches_index(index) != Some(false) {
+                        devices.push((index, Err(err)));
+                        failed = true;
+                    }
                 }
             }
-            None
         }
-        let stream = brightness::brightness_devices();
-        match selector {
-            DeviceSelector::All => stream.boxed(),
-            DeviceSelector::ByName(device_names) => stream
-                .filter_map(move |dev| {
-                    let device_names = device_names.clone();
-                    async move { filter_by_name(device_names, dev).await }
-                })
-                .boxed(),
-            DeviceSelector::ByIndex(device_indices) => stream
-                .enumerate()
-                .filter_map(|(index, device)| {
-                    if device.is_ok() && device_indices.contains(&index) {
-                        ready(Some(device))
-                    } else {
-                        ready(None)
-                    }
-                })
-                .boxed(),
+        let mut unmatched = selector.unmatched(&candidates, count);
+        if failed {
+            // The devices that cannot be opened may bear the names that matched nothing.
+            unmatched.retain(|unmatched| matches!(unmatched, Unmatched::Index { .. }));
         }
+        Selection { devices, unmatched }
     }
 
-    /// Prints the names of available brightness devices.
-    async fn print_device_names(devices: BoxStream<'_, BrightnessResult<BrightnessDevice>>) {
-        devices
-            .map(|dev| async move {
-                match dev {
-                    Ok(dev) => dev.device_name().await.map_err(|err| Error::PrintError {
-                        explanation: "Error while retrieving monitor name".to_string(),
-                        source: err,
-                    }),
-                    Err(err) => Err(Error::PrintError {
-                        explanation: "Error while retrieving monitor information".to_string(),
-                        source: err,
-                    }),
-                }
-            })
-            .enumerate()
-            .for_each(|(index, res)| async move {
-                match res.await {
-                    Ok(name) => println!("{}: {}", index, name.blue().bold()),
-                    Err(Error::PrintError { explanation, .. }) => {
-                        println!("{}: <{}>", index, explanation.red().bold());
-                    }
-                    Err(err) => println!(
-                        "{}: Unknown error <{}>",
-                        index,
-                        err.to_string().red().bold()
-                    ),
-                }
-            })
-            .await;
+    /// Retrieves the state of a device.
+    pub(crate) async fn device_record<D: Device>(
+        index: usize,
+        dev: BrightnessResult<D>,
+    ) -> DeviceRecord {
+        let mut record = DeviceRecord {
+            index,
+            name: None,
+            id: None,
+            aliases: Vec::new(),
+            kind: None,
+            backlight_type: None,
+            limits: None,
+            before: None,
+            percent: None,
+            raw: None,
+            max: None,
+            error: None,
+            error_kind: None,
+        };
+        let device = match dev {
+            Ok(device) => device,
+            Err(err) => {
+                record.error = Some(Self::error_message(&err));
+                record.error_kind = Some(ErrorKind::of(&err));
+                return record;
+            }
+        };
+        record.id = device.id();
+        record.aliases = device.aliases();
+        record.kind = Some(device.kind());
+        record.backlight_type = device.backlight_type();
+        record.limits = Some(device.limits());
+        let (name, percent, raw) = join3(device.name(), device.get(), device.raw()).await;
+        let mut errors = Vec::new();
+        match name {
+            Ok(name) => record.name = Some(name),
+            Err(err) => errors.push(err),
+        }
+        match percent {
+            Ok(percent) => record.percent = Some(percent),
+            Err(err) => errors.push(err),
+        }
+        match raw {
+            Ok(raw) => {
+                record.raw = raw.map(|raw| raw.value);
+                record.max = raw.map(|raw| raw.max);
+            }
+            Err(err) => errors.push(err),
+        }
+        record.error_kind = errors.first().map(ErrorKind::of);
+        let mut messages = errors.iter().map(Self::error_message).collect::<Vec<_>>();
+        messages.dedup();
+        if !messages.is_empty() {
+            record.error = Some(messages.join("; "));
+        }
+        record
     }
 
-    /// Prints the brightness levels of selected devices, their index and their names.
-    async fn print_device_brightnessess_default(
-        devices: BoxStream<'_, BrightnessResult<BrightnessDevice>>,
-    ) {
-        devices
-            .map(|dev| async move {
-                match dev {
-                    Ok(device) => match join(device.device_name(), device.get()).await {
-                        (Ok(name), Ok(brightness)) => Ok((name, brightness)),
-                        (Ok(name), Err(err)) => Err(Error::PrintError {
-                            explanation: format!(
-                                "Unable to retrieve brightness for device {}",
-                                name.blue().bold()
-                            ),
-                            source: err,
-                        }),
-                        (Err(err), _) => Err(Error::PrintError {
-                            explanation: "Unable to retrieve name for device".to_string(),
-                            source: err,
-                        }),
-                    },
-                    Err(err) => Err(Error::PrintError {
-                        explanation: "Unable to retrieve information for device".to_string(),
-                        source: err,
-                    }),
-                }
-            })
-            .enumerate()
-            .for_each(move |(index, result)| async move {
-                match result.await {
-                    Ok((name, brightness)) => {
-                        let name_str = format!("{}: {} brightness:", index, name.blue().bold());
-                        let brightness_str = format!("{brightness}%").bold();
-                        if brightness >= MAX_BRIGHTNESS {
-                            println!(
-                                "{} {} [{} brightness level reached]",
-                                name_str,
-                                brightness_str.green(),
-                                "Maximum".green().bold(),
-                            );
-                        } else if brightness <= MIN_BRIGHTNESS {
-                            println!(
-                                "{} {} [{} brightness level reached]",
-                                name_str,
-                                brightness_str.green().red(),
-                                "Minimum".red().bold(),
-                            );
-                        } else {
-                            println!("{} {}", name_str, brightness_str.yellow(),);
-                        }
-                    }
-                    Err(Error::PrintError { explanation, .. }) => {
-                        println!("{}: Error {}", index, explanation.red().underline());
-                    }
-                    Err(err) => println!(
-                        "{}: Unknown error <{}>",
-                        index,
-                        err.to_string().red().underline()
-                    ),
-                }
-            })
-            .await;
+    /// Formats an error along with the errors that caused it.
+    fn error_message(err: &brightness::Error) -> String {
+        let mut message = err.to_string();
+        let mut source = err.source();
+        while let Some(err) = source {
+            message.push_str(&format!(": {err}"));
+            source = err.source();
+        }
+        message
     }
+}
 
-    /// Prints only the brightness levels of selected devices.
-    async fn print_device_brightnessess_percent(
-        devices: BoxStream<'_, BrightnessResult<BrightnessDevice>>,
-    ) {
-        devices
-            .map(|dev| async move {
-                match dev {
-                    Ok(device) => device.get().await.map_err(|err| Error::PrintError {
-                        explanation: "Unable to retrieve brightness for device".to_string(),
-                        source: err,
-                    }),
-                    Err(err) => Err(Error::PrintError {
-                        explanation: "Unable to retrieve information for device".to_string(),
-                        source: err,
-                    }),
-                }
-            })
-            .for_each(|result| async {
-                match result.await {
-                    Ok(percent) => println!("{}", format!("{percent}%").yellow().bold()),
-                    Err(Error::PrintError { explanation, .. }) => {
-                        println!("{}", explanation.red().underline());
-                    }
-                    Err(err) => println!("Unknown error: {}", err.to_string().red().underline()),
+impl DeviceSelector {
+    /// Returns the selector of the devices changed when none is named: every device but the LEDs,
+    /// as writing the brightness of an indicator such as `input3::capslock` turns it on and
+    /// clears its trigger.
+    pub fn default_for_changes() -> Self {
+        DeviceSelector::Not(Box::new(DeviceSelector::ByKind(HashSet::from([
+            DeviceKind::Led,
+        ]))))
+    }
+
+    /// Restricts the selector to the devices chosen by `default` unless it names the devices to
+    /// choose, that is when it chooses every device but the excluded ones.
+    pub fn with_default(self, default: DeviceSelector) -> Self {
+        match self {
+            DeviceSelector::All => default,
+            DeviceSelector::Not(_) => DeviceSelector::AllOf(vec![default, self]),
+            DeviceSelector::AllOf(mut selectors)
+                if selectors
+                    .iter()
+                    .all(|selector| matches!(selector, DeviceSelector::Not(_))) =>
+            {
+                selectors.insert(0, default);
+                DeviceSelector::AllOf(selectors)
+            }
+            selector => selector,
+        }
+    }
+
+    /// Checks whether the selector chooses `candidate`.
+    fn matches(&self, candidate: &Candidate) -> bool {
+        let mut names = candidate
+            .name
+            .as_deref()
+            .into_iter()
+            .chain(candidate.aliases.iter().map(String::as_str));
+        match self {
+            DeviceSelector::All => true,
+            DeviceSelector::ByName(device_names) => names.any(|name| device_names.contains(name)),
+            DeviceSelector::ByIndex(device_indices) => device_indices.contains(&candidate.index),
+            DeviceSelector::ByKind(device_kinds) => device_kinds.contains(&candidate.kind),
+            DeviceSelector::ByPattern(patterns) => {
+                names.any(|name| patterns.iter().any(|pattern| pattern.matches(name)))
+            }
+            DeviceSelector::ByRegex(regexes) => {
+                names.any(|name| regexes.iter().any(|regex| regex.is_match(name)))
+            }
+            DeviceSelector::AllOf(selectors) => {
+                selectors.iter().all(|selector| selector.matches(candidate))
+            }
+            DeviceSelector::AnyOf(selectors) => {
+                selectors.iter().any(|selector| selector.matches(candidate))
+            }
+            DeviceSelector::Not(selector) => !selector.matches(candidate),
+        }
+    }
+
+    /// Checks whether the selector chooses the device at `index`, knowing nothing else about it.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` when the answer depends on the name, the aliases or the kind of the device.
+    fn matches_index(&self, index: usize) -> Option<bool> {
+        match self {
+            DeviceSelector::All => Some(true),
+            DeviceSelector::ByIndex(device_indices) => Some(device_indices.contains(&index)),
+            DeviceSelector::ByName(_)
+            | DeviceSelector::ByKind(_)
+            | DeviceSelector::ByPattern(_)
+            | DeviceSelector::ByRegex(_) => None,
+            DeviceSelector::AllOf(selectors) | DeviceSelector::AnyOf(selectors) => {
+                // An answer equal to `decisive` settles the answer of the whole selector.
+                let decisive = matches!(self, DeviceSelector::AnyOf(_));
+                let answers = selectors
+                    .iter()
+                    .map(|selector| selector.matches_index(index))
+                    .collect::<Vec<_>>();
+                if answers.contains(&Some(decisive)) {
+                    Some(decisive)
+                } else if answers.contains(&None) {
+                    None
+                } else {
+                    Some(!decisive)
                 }
-            })
-            .await;
+            }
+            DeviceSelector::Not(selector) => selector.matches_index(index).map(|matches| !matches),
+        }
     }
 
-    /// Prints the brightness levels of selected devices.
-    async fn print_device_brightnessess(
-        devices: BoxStream<'_, BrightnessResult<BrightnessDevice>>,
-        output: &BrightnessOutput,
-    ) {
-        match output {
-            BrightnessOutput::Default => {
-                Self::print_device_brightnessess_default(devices).await;
+    /// Returns the kinds of the devices that the selector may choose.
+    pub(crate) fn kinds(&self) -> HashSet<DeviceKind> {
+        match self {
+            DeviceSelector::ByKind(kinds) => kinds.clone(),
+            DeviceSelector::AllOf(selectors) => selectors
+                .iter()
+                .fold(HashSet::from(DeviceKind::ALL), |kinds, selector| {
+                    &kinds & &selector.kinds()
+                }),
+            DeviceSelector::AnyOf(selectors) => selectors
+                .iter()
+                .flat_map(|selector| selector.kinds())
+                .collect(),
+            DeviceSelector::Not(selector) => match &**selector {
+                DeviceSelector::ByKind(kinds) => &HashSet::from(DeviceKind::ALL) - kinds,
+                _ => HashSet::from(DeviceKind::ALL),
+            },
+            DeviceSelector::All
+            | DeviceSelector::ByName(_)
+            | DeviceSelector::ByIndex(_)
+            | DeviceSelector::ByPattern(_)
+            | DeviceSelector::ByRegex(_) => HashSet::from(DeviceKind::ALL),
+        }
+    }
+
+    /// Lists the names, patterns and indices of the selector that match none of the
+    /// `candidates`, out of the `count` devices of the backend.
+    fn unmatched(&self, candidates: &[Candidate], count: usize) -> Vec<Unmatched> {
+        let names = candidates
+            .iter()
+            .flat_map(|candidate| candidate.name.iter().chain(&candidate.aliases))
+            .map(String::as_str);
+        let unmatched_name = |name: &str, matches: &dyn Fn(&str) -> bool| {
+            if names.clone().any(matches) {
+                return None;
+            }
+            Some(Unmatched::Name {
+                name: name.to_string(),
+                suggestions: suggestions(name, names.clone()),
+            })
+        };
+        match self {
+            DeviceSelector::All | DeviceSelector::ByKind(_) => Vec::new(),
+            DeviceSelector::Not(selector) => selector.unmatched(candidates, count),
+            DeviceSelector::ByName(device_names) => {
+                let mut device_names = device_names.iter().collect::<Vec<_>>();
+                device_names.sort();
+                device_names
+                    .into_iter()
+                    .filter_map(|name| unmatched_name(name, &|other| other == name))
+                    .collect()
             }
-            BrightnessOutput::Percent => {
-                Self::print_device_brightnessess_percent(devices).await;
+            DeviceSelector::ByIndex(device_indices) => {
+                let mut device_indices = device_indices
+                    .iter()
+                    .filter(|&&index| index >= count)
+                    .collect::<Vec<_>>();
+                device_indices.sort();
+                device_indices
+                    .into_iter()
+                    .map(|&index| Unmatched::Index { index, count })
+                    .collect()
             }
-            BrightnessOutput::Quiet => {}
+            DeviceSelector::ByPattern(patterns) => patterns
+                .iter()
+                .filter_map(|pattern| {
+                    unmatched_name(pattern.as_str(), &|name| pattern.matches(name))
+                })
+                .collect(),
+            DeviceSelector::ByRegex(regexes) => regexes
+                .iter()
+                .filter_map(|regex| unmatched_name(regex.as_str(), &|name| regex.is_match(name)))
+                .collect(),
+            DeviceSelector::AllOf(selectors) | DeviceSelector::AnyOf(selectors) => selectors
+                .iter()
+                .flat_map(|selector| selector.unmatched(candidates, count))
+                .collect(),
+        }
+    }
+
+    /// Retrieves the state of the devices of `backend` chosen by the selector.
+    pub async fn report<B: Backend>(&self, backend: &B) -> Report {
+        let selection = Command::select_devices(backend, self).await;
+        Report {
+            devices: stream::iter(selection.devices)
+                .then(|(index, dev)| Command::device_record(index, dev))
+                .collect()
+                .await,
+            unmatched: selection.unmatched,
         }
     }
 }
 
 impl BrightnessCommand {
+    /// Executes the `BrightnessCommand` on the devices of `backend` chosen by `selector`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Report` holding the state of the selected devices before and after the
+    /// execution of the command. Otherwise, returns an `rumos::Error`.
+    pub async fn apply<B: Backend>(
+        &self,
+        backend: &B,
+        selector: &DeviceSelector,
+    ) -> Result<Report, Error> {
+        self.apply_with(backend, selector, &ApplyOptions::default())
+            .await
+    }
+
+    /// Executes the `BrightnessCommand` on the devices of `backend` chosen by `selector`, fading
+    /// each device to its new level with `transition`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Report` holding the state of the selected devices before and after the
+    /// execution of the command. Otherwise, returns an `rumos::Error`.
+    pub async fn fade<B: Backend>(
+        &self,
+        backend: &B,
+        selector: &DeviceSelector,
+        transition: &Transition,
+    ) -> Result<Report, Error> {
+        let options = ApplyOptions {
+            transition: Some(*transition),
+            ..ApplyOptions::default()
+        };
+        self.apply_with(backend, selector, &options).await
+    }
+
+    /// Executes the `BrightnessCommand` on the devices of `backend` chosen by `selector`, changing
+    /// them as described by `options`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Report` holding the state of the selected devices before and after the
+    /// execution of the command. Otherwise, returns an `rumos::Error`.
+    pub async fn apply_with<B: Backend>(
+        &self,
+        backend: &B,
+        selector: &DeviceSelector,
+        options: &ApplyOptions,
+    ) -> Result<Report, Error> {
+        let Selection {
+            mut devices,
+            unmatched,
+        } = Command::select_devices(backend, selector).await;
+        if !unmatched.is_empty() && !options.allow_missing {
+            return Err(Error::UnmatchedDevices(unmatched));
+        }
+        let before = if let BrightnessCommand::Get = self {
+            vec![None; devices.len()]
+        } else {
+            stream::iter(&devices)
+                .then(|(_, device)| async move {
+                    match device {
+                        Ok(device) => device.get().await.ok(),
+                        Err(_) => None,
+                    }
+                })
+                .collect()
+                .await
+        };
+        let mut results = self
+            .handle(
+                stream::iter(
+                    devices
+                        .iter_mut()
+                        .filter_map(|(_, device)| device.as_mut().ok()),
+                )
+                .boxed(),
+                options,
+            )
+            .await
+            .into_iter();
+        let failures = devices
+            .iter()
+            .map(|(_, device)| match device {
+                Ok(_) => results.next().and_then(Result::err),
+                Err(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let mut report = Report {
+            devices: stream::iter(devices)
+                .then(|(index, dev)| Command::device_record(index, dev))
+                .collect()
+                .await,
+            unmatched,
+        };
+        for ((record, before), failure) in report.devices.iter_mut().zip(before).zip(failures) {
+            record.before = before;
+            if let Some(err) = failure {
+                let message = Command::error_message(&err);
+                record.error = Some(match record.error.take() {
+                    Some(error) => format!("{message}; {error}"),
+                    None => message,
+                });
+                record.error_kind = Some(ErrorKind::of(&err));
+            }
+        }
+        Ok(report)
+    }
+
     /// Handles the execution of the `BrightnessCommand` on a set of devices.
     ///
     /// # Arguments
     ///
     /// * `devices`: A stream of brightness devices on which the command will be executed.
+    /// * `options`: The way the devices are changed.
     ///
     /// # Returns
     ///
-    /// Returns `Ok(())` if the command is executed successfully. Otherwise, returns a `brightness::Error`.
-    pub async fn handle(
+    /// Returns the outcome of the command on each device, in the order of the stream. A device
+    /// that fails does not prevent the command from being executed on the other devices.
+    pub async fn handle<D: Device>(
         &self,
-        devices: BoxStream<'_, BrightnessResult<BrightnessDevice>>,
-    ) -> BrightnessResult<()> {
+        devices: BoxStream<'_, &mut D>,
+        options: &ApplyOptions,
+    ) -> Vec<BrightnessResult<()>> {
         match self {
-            BrightnessCommand::Get => Ok(()),
-            BrightnessCommand::Set { percent } => Self::set_brightness(devices, *percent).await,
+            BrightnessCommand::Get => devices.map(|_| Ok(())).collect().await,
+            BrightnessCommand::Set { percent } => {
+                Self::set_brightness(devices, *percent, options).await
+            }
             BrightnessCommand::Inc { percent } => {
-                Self::increase_brightness(devices, *percent).await
+                Self::increase_brightness(devices, *percent, options).await
             }
             BrightnessCommand::Dec { percent } => {
-                Self::decrease_brightness(devices, *percent).await
+                Self::decrease_brightness(devices, *percent, options).await
             }
-            BrightnessCommand::Max => Self::set_brightness(devices, MAX_BRIGHTNESS).await,
-            BrightnessCommand::Min => Self::set_brightness(devices, MIN_BRIGHTNESS).await,
+            BrightnessCommand::Max => Self::set_brightness(devices, MAX_BRIGHTNESS, options).await,
+            BrightnessCommand::Min => Self::set_brightness(devices, 0, options).await,
         }
     }
 
@@ -293,58 +688,164 @@ impl BrightnessCommand {
     /// * `devices`: The stream of devices
     /// * `percentage`: The percentage used for the change
     /// * `adjust_fn`: A function that takes the current brightness value and the `percentage` and returns the new brightness value
+    /// * `options`: The way the devices are changed
     ///
     /// # Returns
     ///
-    /// Returns `Ok(())` if the command is executed successfully. Otherwise, returns a `brightness::Error`.
-    async fn adjust_brightness<F>(
-        devices: BoxStream<'_, BrightnessResult<BrightnessDevice>>,
+    /// Returns the outcome of the change on each device, in the order of the stream.
+    async fn adjust_brightness<D, F>(
+        devices: BoxStream<'_, &mut D>,
         percentage: u32,
         adjust_fn: Arc<F>,
-    ) -> BrightnessResult<()>
+        options: &ApplyOptions,
+    ) -> Vec<BrightnessResult<()>>
     where
+        D: Device,
         F: Fn(u32, u32) -> u32 + Send + Sync,
     {
+        let concurrency = options
+            .concurrency
+            .filter(|&concurrency| concurrency > 0)
+            .unwrap_or(usize::MAX);
         devices
-            .try_for_each_concurrent(MAX_CONCURRENCY, |mut device| {
+            .map(|device| {
                 let adjust_fn = adjust_fn.clone();
                 async move {
-                    let current_level = device.get().await?;
-                    let new_level = adjust_fn(current_level, percentage);
-                    let clamped_level = if new_level < MIN_BRIGHTNESS {
-                        MIN_BRIGHTNESS
-                    } else if new_level > MAX_BRIGHTNESS {
-                        MAX_BRIGHTNESS
-                    } else {
-                        new_level
-                    };
-                    device.set(clamped_level).await
+                    let name = device.name().await?;
+                    match options.transition {
+                        Some(transition) => {
+                            let claim = Claim::acquire(&name);
+                            let current_level = device.get().await?;
+                            let new_level =
+                                device.limits().clamp(adjust_fn(current_level, percentage));
+                            transition
+                                .run(device, current_level, new_level, claim.as_ref())
+                                .await
+                        }
+                        None => {
+                            let _handover = Handover::take(&name);
+                            let current_level = device.get().await?;
+                            let new_level =
+                                device.limits().clamp(adjust_fn(current_level, percentage));
+                            device.set(new_level).await
+                        }
+                    }
                 }
             })
+            .buffered(concurrency)
+            .collect()
             .await
     }
 
     /// Sets the brightness of multiple devices to the given percentage.
-    async fn set_brightness(
-        devices: BoxStream<'_, BrightnessResult<BrightnessDevice>>,
+    async fn set_brightness<D: Device>(
+        devices: BoxStream<'_, &mut D>,
         percentage: u32,
-    ) -> BrightnessResult<()> {
-        Self::adjust_brightness(devices, percentage, Arc::new(|_, p| p)).await
+        options: &ApplyOptions,
+    ) -> Vec<BrightnessResult<()>> {
+        Self::adjust_brightness(devices, percentage, Arc::new(|_, p| p), options).await
     }
 
     /// Increases the brightness of multiple devices by the given percentage.
-    async fn increase_brightness(
-        devices: BoxStream<'_, BrightnessResult<BrightnessDevice>>,
+    async fn increase_brightness<D: Device>(
+        devices: BoxStream<'_, &mut D>,
         percentage: u32,
-    ) -> BrightnessResult<()> {
-        Self::adjust_brightness(devices, percentage, Arc::new(u32::saturating_add)).await
+        options: &ApplyOptions,
+    ) -> Vec<BrightnessResult<()>> {
+        Self::adjust_brightness(devices, percentage, Arc::new(u32::saturating_add), options).await
     }
 
     /// Decreases the brightness of multiple devices by the given percentage.
-    async fn decrease_brightness(
-        devices: BoxStream<'_, BrightnessResult<BrightnessDevice>>,
+    async fn decrease_brightness<D: Device>(
+        devices: BoxStream<'_, &mut D>,
         percentage: u32,
-    ) -> BrightnessResult<()> {
-        Self::adjust_brightness(devices, percentage, Arc::new(u32::saturating_sub)).await
+        options: &ApplyOptions,
+    ) -> Vec<BrightnessResult<()>> {
+        Self::adjust_brightness(devices, percentage, Arc::new(u32::saturating_sub), options).await
+    }
+}
+
+impl Report {
+    /// Summarizes the outcome of the command.
+    ///
+    /// The outcome is a failure of a specific kind only when every selected device failed with
+    /// that kind of error; devices failing for different reasons, or next to devices that were
+    /// handled, make a partial failure.
+    pub fn outcome(&self) -> Outcome {
+        let mut kinds = self.devices.iter().map(|record| record.error_kind);
+        if self.devices.is_empty() {
+            return Outcome::NoMatch;
+        }
+        let first = kinds.next().flatten();
+        if kinds.any(|kind| kind != first) {
+            return Outcome::PartialFailure;
+        }
+        match first {
+            None => Outcome::Success,
+            Some(ErrorKind::PermissionDenied) => Outcome::PermissionDenied,
+            Some(ErrorKind::BackendUnavailable) => Outcome::BackendUnavailable,
+            Some(ErrorKind::DeviceFailed) => Outcome::PartialFailure,
+        }
+    }
+}
+
+impl ErrorKind {
+    /// Categorizes `err`, looking for the errors that caused it.
+    pub fn of(err: &brightness::Error) -> Self {
+        let mut source: Option<&(dyn StdError + 'static)> = Some(err);
+        while let Some(err) = source {
+            if let Some(err) = err.downcast_ref::<io::Error>() {
+                if err.kind() == io::ErrorKind::PermissionDenied {
+                    return ErrorKind::PermissionDenied;
+                }
+            }
+            source = err.source();
+        }
+        match err {
+            brightness::Error::ListingDevicesFailed(_) => ErrorKind::BackendUnavailable,
+            _ => ErrorKind::DeviceFailed,
+        }
     }
 }
+
+impl Display for Unmatched {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Unmatched::Name { name, suggestions } => {
+                write!(f, "No device matches `{name}`")?;
+                if !suggestions.is_empty() {
+                    let suggestions = suggestions
+                        .iter()
+                        .map(|suggestion| format!("`{suggestion}`"))
+                        .collect::<Vec<_>>();
+                    write!(f, ", did you mean {}?", suggestions.join(" or "))?;
+                }
+                Ok(())
+            }
+            Unmatched::Index { index, count: 0 } => {
+                write!(f, "No device has the index {index}, no device was found")
+            }
+            Unmatched::Index { index, count } => write!(
+                f,
+                "No device has the index {index}, the indices go from 0 to {}",
+                count - 1
+            ),
+        }
+    }
+}
+
+/// Returns the names that look like `name`, from the most to the least similar.
+fn suggestions<'a>(name: &str, names: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut suggestions = names
+        .map(|other| (strsim::jaro_winkler(name, other), other))
+        .filter(|(similarity, _)| *similarity >= SUGGESTION_SIMILARITY)
+        .collect::<Vec<_>>();
+    suggestions.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(b.1)));
+    let mut names = suggestions
+        .into_iter()
+        .map(|(_, other)| other.to_string())
+        .collect::<Vec<_>>();
+    names.dedup();
+    names.truncate(MAX_SUGGESTIONS);
+    names
+}
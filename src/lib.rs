@@ -0,0 +1,80 @@
+//! Library for controlling screen brightness, used by the `rumos` command-line utility.
+//!
+//! Devices are provided by a [`backend::Backend`], selected with a [`DeviceSelector`] and changed
+//! with a [`BrightnessCommand`]. The commands return the state of the devices before and after
+//! their execution as a [`Report`], which can be turned into text with [`render`].
+//!
+//! # Example
+//!
+//! ```no_run
+//! use futures::executor::block_on;
+//! use rumos::backend::SysfsBackend;
+//! use rumos::{BrightnessCommand, DeviceSelector};
+//!
+//! let backend = SysfsBackend::new("/sys");
+//! let command = BrightnessCommand::Inc { percent: 10 };
+//! let report = block_on(command.apply(&backend, &DeviceSelector::All))?;
+//! for record in report.devices {
+//!     println!("{:?}: {:?}% -> {:?}%", record.name, record.before, record.percent);
+//! }
+//! # Ok::<(), rumos::Error>(())
+//! ```
+
+mod ambient;
+pub mod backend;
+mod config;
+#[cfg(unix)]
+mod daemon;
+#[cfg(target_os = "linux")]
+mod dbus;
+mod doctor;
+mod error;
+mod funcs;
+mod profile;
+mod protocol;
+mod render;
+mod scale;
+mod schedule;
+mod task;
+mod template;
+mod transition;
+mod udev;
+mod watch;
+
+pub use ambient::{AutoBrightness, LightSensor};
+pub use config::{
+    AutoConfig, Config, Defaults, DeviceConfig, ScheduleConfig, SchedulePoint, DEFAULT_STEP,
+};
+#[cfg(unix)]
+pub use daemon::{serve, Client};
+#[cfg(target_os = "linux")]
+pub use dbus::{serve_dbus, DBUS_NAME, DBUS_PATH};
+pub use doctor::{
+    Check, Environment, Status, DEFAULT_GROUP_FILE, DEFAULT_SYSTEM_BUS_SOCKET,
+    DEFAULT_UDEV_RULE_DIRS,
+};
+pub use error::Error;
+pub use funcs::{
+    ApplyOptions, BrightnessCommand, BrightnessOutput, Command, DeviceRecord, DeviceSelector,
+    ErrorKind, Outcome, Report, Unmatched,
+};
+pub use profile::{Profile, ProfileDevice, Profiles};
+pub use protocol::{
+    default_socket_path, Request, RequestBody, Response, ResponseBody, Settings, Target,
+    PROTOCOL_VERSION, SOCKET_NAME,
+};
+pub use render::render;
+pub use scale::{Scale, DEFAULT_GAMMA};
+pub use schedule::{format_minutes, Date, DaySchedule, Scheduler, TimeOfDay};
+pub use task::Task;
+pub use template::Template;
+pub use transition::{parse_duration, Easing, Transition};
+pub use udev::{UdevRules, DEFAULT_UDEV_RULES_PATH, UDEV_GROUP};
+pub use watch::DEFAULT_WATCH_INTERVAL;
+
+/// Highest brightness level (in percent) that can be set, unless configured otherwise.
+pub const MAX_BRIGHTNESS: u32 = 100;
+/// Lowest brightness level (in percent) that can be set, unless configured otherwise.
+pub const MIN_BRIGHTNESS: u32 = 5;
+/// Maximum number of devices changed at the same time, unless configured otherwise.
+pub const MAX_CONCURRENCY: Option<usize> = Some(5);
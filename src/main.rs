@@ -1,26 +1,31 @@
 mod args;
-mod error;
-mod funcs;
 
 use std::collections::HashSet;
+use std::io::{self, Stdout, Write};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::process::ExitCode;
 use std::sync::Arc;
 
-use clap::Parser;
-use futures::executor;
+use clap::{Parser, ValueEnum};
+use glob::Pattern;
 
 use args::{
-    BrightnessOutput as CliBrightnessOutput, Cli, Command as CliCommand,
-    DeviceSelector as CliDeviceSelector,
+    parse_output_format, Backend as CliBackend, BrightnessOutput as CliBrightnessOutput, Cli,
+    Command as CliCommand, ConfigCommand, DeviceKind as CliDeviceKind,
+    DeviceSelector as CliDeviceSelector, Easing as CliEasing, OutputFormat as CliOutputFormat,
+    ProfileCommand, ScheduleCommand, SetupCommand, Transition as CliTransition,
 };
-pub use error::Error;
-use funcs::{
-    BrightnessCommand, BrightnessOutput as FuncsBrightnessOutput, Command as FuncsCommand,
-    DeviceSelector as FuncsDeviceSelector,
+use rumos::backend::{
+    Backend, BrightnessBackend, Chain, DdcBackend, DeviceKind, FakeBackend, SysfsBackend,
+    DEFAULT_DEV_ROOT,
+};
+use rumos::{
+    default_socket_path, render, ApplyOptions, BrightnessCommand,
+    BrightnessOutput as FuncsBrightnessOutput, Command as FuncsCommand, Config, Date,
+    DeviceSelector as FuncsDeviceSelector, Easing, Environment, Error, ErrorKind, LightSensor,
+    Outcome, Profiles, Report, Settings, Status, Task, Transition, UdevRules, UDEV_GROUP,
 };
-
-const MAX_BRIGHTNESS: u32 = 100;
-const MIN_BRIGHTNESS: u32 = 5;
-const MAX_CONCURRENCY: Option<usize> = Some(5);
 
 impl From<CliBrightnessOutput> for FuncsBrightnessOutput {
     fn from(value: CliBrightnessOutput) -> Self {
@@ -28,93 +33,580 @@ impl From<CliBrightnessOutput> for FuncsBrightnessOutput {
             CliBrightnessOutput {
                 quiet: false,
                 percent: false,
+                format: None,
             } => FuncsBrightnessOutput::Default,
             CliBrightnessOutput {
                 quiet: true,
                 percent: false,
+                format: None,
             } => FuncsBrightnessOutput::Quiet,
             CliBrightnessOutput {
                 quiet: false,
                 percent: true,
+                format: None,
             } => FuncsBrightnessOutput::Percent,
+            CliBrightnessOutput {
+                quiet: false,
+                percent: false,
+                format: Some(format),
+            } => format.into(),
             CliBrightnessOutput { .. } => unreachable!("The variables are mutually exclusive"),
         }
     }
 }
 
-impl From<CliDeviceSelector> for FuncsDeviceSelector {
-    fn from(value: CliDeviceSelector) -> Self {
+impl From<CliOutputFormat> for FuncsBrightnessOutput {
+    fn from(value: CliOutputFormat) -> Self {
         match value {
-            CliDeviceSelector {
-                devices: None,
-                indices: None,
-            } => FuncsDeviceSelector::All,
-            CliDeviceSelector {
-                devices: Some(devices),
-                indices: None,
-            } => FuncsDeviceSelector::ByName(Arc::<HashSet<String>>::new(
-                devices.into_iter().collect(),
-            )),
-            CliDeviceSelector {
-                devices: None,
-                indices: Some(indices),
-            } => FuncsDeviceSelector::ByIndex(indices.into_iter().collect()),
-            CliDeviceSelector { .. } => unreachable!("The variables are mutually exclusive"),
-        }
-    }
-}
-
-impl From<CliCommand> for FuncsCommand {
-    fn from(value: CliCommand) -> Self {
+            CliOutputFormat::Json => FuncsBrightnessOutput::Json,
+            CliOutputFormat::JsonLines => FuncsBrightnessOutput::JsonLines,
+            CliOutputFormat::Template(template) => FuncsBrightnessOutput::Template(template),
+        }
+    }
+}
+
+impl From<CliDeviceKind> for DeviceKind {
+    fn from(value: CliDeviceKind) -> Self {
         match value {
-            CliCommand::Get { selector, output } => FuncsCommand::BrightnessCommand {
-                command: BrightnessCommand::Get,
-                selector: selector.into(),
-                output: output.into(),
-            },
-            CliCommand::Set {
-                percent,
-                selector,
-                output,
-            } => FuncsCommand::BrightnessCommand {
-                command: BrightnessCommand::Set { percent },
-                selector: selector.into(),
-                output: output.into(),
-            },
-            CliCommand::Inc {
-                percent,
-                selector,
-                output,
-            } => FuncsCommand::BrightnessCommand {
-                command: BrightnessCommand::Inc { percent },
-                selector: selector.into(),
-                output: output.into(),
-            },
-            CliCommand::Dec {
-                percent,
-                selector,
-                output,
-            } => FuncsCommand::BrightnessCommand {
-                command: BrightnessCommand::Dec { percent },
-                selector: selector.into(),
-                output: output.into(),
+            CliDeviceKind::Backlight => DeviceKind::Backlight,
+            CliDeviceKind::Led => DeviceKind::Led,
+            CliDeviceKind::Ddc => DeviceKind::Ddc,
+        }
+    }
+}
+
+impl From<CliEasing> for Easing {
+    fn from(value: CliEasing) -> Self {
+        match value {
+            CliEasing::Linear => Easing::Linear,
+            CliEasing::EaseInOut => Easing::EaseInOut,
+            CliEasing::Exponential => Easing::Exponential,
+        }
+    }
+}
+
+/// Converts the transition arguments, returning `None` when the level should be set at once.
+fn transition(value: CliTransition) -> Option<Transition> {
+    value.transition.map(|duration| Transition {
+        duration,
+        easing: value.easing.into(),
+    })
+}
+
+/// Converts names, aliases and glob patterns to a selector choosing the devices matching any of
+/// them.
+fn names_selector(names: Vec<String>) -> FuncsDeviceSelector {
+    let (patterns, names): (Vec<_>, Vec<_>) = names
+        .into_iter()
+        .partition(|name| name.contains(['*', '?', '[']));
+    let patterns = patterns
+        .iter()
+        .map(|pattern| Pattern::new(pattern).expect("Patterns are validated while parsing"))
+        .collect::<Vec<_>>();
+    match (names.is_empty(), patterns.is_empty()) {
+        (false, true) => FuncsDeviceSelector::ByName(Arc::new(names.into_iter().collect())),
+        (true, false) => FuncsDeviceSelector::ByPattern(patterns),
+        _ => FuncsDeviceSelector::AnyOf(vec![
+            FuncsDeviceSelector::ByName(Arc::<HashSet<String>>::new(names.into_iter().collect())),
+            FuncsDeviceSelector::ByPattern(patterns),
+        ]),
+    }
+}
+
+impl From<CliDeviceSelector> for FuncsDeviceSelector {
+    fn from(value: CliDeviceSelector) -> Self {
+        let mut selectors = Vec::new();
+        if let Some(devices) = value.devices {
+            selectors.push(names_selector(devices));
+        }
+        if let Some(indices) = value.indices {
+            selectors.push(FuncsDeviceSelector::ByIndex(indices.into_iter().collect()));
+        }
+        if let Some(kinds) = value.kinds {
+            selectors.push(FuncsDeviceSelector::ByKind(
+                kinds.into_iter().map(DeviceKind::from).collect(),
+            ));
+        }
+        if let Some(regexes) = value.regex {
+            selectors.push(FuncsDeviceSelector::ByRegex(regexes));
+        }
+        if let Some(excluded) = value.exclude {
+            selectors.push(FuncsDeviceSelector::Not(Box::new(names_selector(excluded))));
+        }
+        match selectors.len() {
+            0 => FuncsDeviceSelector::All,
+            1 => selectors.remove(0),
+            _ => FuncsDeviceSelector::AllOf(selectors),
+        }
+    }
+}
+
+/// Converts a command of the command-line utility, using the settings of `config` for the
+/// options that are not given.
+fn command(
+    value: CliCommand,
+    config: &Config,
+    default_output: FuncsBrightnessOutput,
+) -> FuncsCommand {
+    let output = |output: CliBrightnessOutput| match output.into() {
+        FuncsBrightnessOutput::Default => default_output.clone(),
+        output => output,
+    };
+    let brightness_command = |command: BrightnessCommand,
+                              args: CliDeviceSelector,
+                              output_args: CliBrightnessOutput,
+                              transition: Option<Transition>| {
+        let options = ApplyOptions {
+            transition,
+            concurrency: config.concurrency(),
+            allow_missing: args.allow_missing,
+        };
+        let selector = FuncsDeviceSelector::from(args);
+        FuncsCommand::BrightnessCommand {
+            selector: match command {
+                BrightnessCommand::Get => selector,
+                _ => selector.with_default(FuncsDeviceSelector::default_for_changes()),
             },
-            CliCommand::Max { selector, output } => FuncsCommand::BrightnessCommand {
-                command: BrightnessCommand::Max,
-                selector: selector.into(),
-                output: output.into(),
+            command,
+            options,
+            output: output(output_args),
+        }
+    };
+    let step = config.defaults.step;
+    match value {
+        CliCommand::Get {
+            selector,
+            output: args,
+        } => brightness_command(BrightnessCommand::Get, selector, args, None),
+        CliCommand::Set {
+            percent,
+            selector,
+            output: args,
+            transition: transition_args,
+        } => brightness_command(
+            BrightnessCommand::Set { percent },
+            selector,
+            args,
+            transition(transition_args),
+        ),
+        CliCommand::Inc {
+            percent,
+            selector,
+            output: args,
+            transition: transition_args,
+        } => brightness_command(
+            BrightnessCommand::Inc {
+                percent: percent.unwrap_or(step),
             },
-            CliCommand::Min { selector, output } => FuncsCommand::BrightnessCommand {
-                command: BrightnessCommand::Min,
-                selector: selector.into(),
-                output: output.into(),
+            selector,
+            args,
+            transition(transition_args),
+        ),
+        CliCommand::Dec {
+            percent,
+            selector,
+            output: args,
+            transition: transition_args,
+        } => brightness_command(
+            BrightnessCommand::Dec {
+                percent: percent.unwrap_or(step),
             },
-            CliCommand::List => FuncsCommand::List,
+            selector,
+            args,
+            transition(transition_args),
+        ),
+        CliCommand::Max {
+            selector,
+            output: args,
+            transition: transition_args,
+        } => brightness_command(
+            BrightnessCommand::Max,
+            selector,
+            args,
+            transition(transition_args),
+        ),
+        CliCommand::Min {
+            selector,
+            output: args,
+            transition: transition_args,
+        } => brightness_command(
+            BrightnessCommand::Min,
+            selector,
+            args,
+            transition(transition_args),
+        ),
+        CliCommand::List { format } => FuncsCommand::List {
+            output: format.map_or(default_output.clone(), Into::into),
+        },
+        CliCommand::Watch {
+            selector,
+            output: args,
+            interval,
+        } => FuncsCommand::Watch {
+            selector: selector.into(),
+            output: output(args),
+            interval,
+        },
+        CliCommand::Auto { .. }
+        | CliCommand::Config { .. }
+        | CliCommand::Daemon
+        | CliCommand::Dbus { .. }
+        | CliCommand::Doctor
+        | CliCommand::Profile { .. }
+        | CliCommand::Schedule { .. }
+        | CliCommand::Setup { .. } => {
+            unreachable!(
+                "The automatic, daemon, configuration, diagnostic, profile, schedule and setup \
+                 commands are not commands"
+            )
         }
     }
 }
 
-fn main() -> Result<(), Error> {
+/// Reads the configuration file and overrides its settings with the command-line options.
+fn config(cli: &Cli, path: Option<&Path>) -> Result<Config, Error> {
+    let mut config = match path {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    if let Some(scale) = cli.scale {
+        config.defaults.scale = scale;
+    }
+    for (name, scale) in &cli.device_scale {
+        config.devices.entry(name.clone()).or_default().scale = Some(*scale);
+    }
+    if let Some(min) = cli.min_level {
+        config.defaults.min = min;
+    }
+    if let Some(max) = cli.max_level {
+        config.defaults.max = max;
+    }
+    for limits in &cli.device_limits {
+        let device = config.devices.entry(limits.name.clone()).or_default();
+        device.min = limits.min.or(device.min);
+        device.max = limits.max.or(device.max);
+    }
+    if let Some(concurrency) = cli.concurrency {
+        config.defaults.concurrency = concurrency;
+    }
+    config.validate().map_err(Error::InvalidOptions)?;
+    Ok(config)
+}
+
+/// Returns the output used by the commands when none is given.
+fn default_output(config: &Config, path: Option<&Path>) -> Result<FuncsBrightnessOutput, Error> {
+    match &config.defaults.format {
+        None => Ok(FuncsBrightnessOutput::Default),
+        Some(format) => parse_output_format(format)
+            .map(Into::into)
+            .map_err(|explanation| Error::ConfigError {
+                path: path.map(Path::to_path_buf).unwrap_or_default(),
+                explanation,
+            }),
+    }
+}
+
+/// Exit code of a command that failed for a reason not listed below, such as an invalid
+/// configuration file. Invalid arguments exit with the code 2.
+const EXIT_FAILURE: u8 = 1;
+/// Exit code of a command that failed on some or all of the selected devices.
+const EXIT_PARTIAL_FAILURE: u8 = 3;
+/// Exit code of a command whose selector matches no device.
+const EXIT_NO_MATCH: u8 = 4;
+/// Exit code of a command that failed on every selected device for lack of permissions.
+const EXIT_PERMISSION_DENIED: u8 = 5;
+/// Exit code of a command whose backend could not list its devices.
+const EXIT_BACKEND_UNAVAILABLE: u8 = 6;
+
+/// Executes `task` on the devices of `backend`, configured with `config`, printing its reports.
+///
+/// # Returns
+///
+/// Returns the exit code describing the outcome of the task.
+fn run<B: Backend>(task: Task, backend: B, config: Config) -> Result<ExitCode, Error> {
+    let mut stdout = io::stdout();
+    let finished = task.run(backend, config, |command, report| {
+        print_changes(command, report, &mut stdout)
+    })?;
+    Ok(finished.map_or(ExitCode::SUCCESS, |(command, report)| {
+        print_report(&command, &report)
+    }))
+}
+
+/// Prints the changes noticed while watching the devices, stopping once the standard output is
+/// closed.
+fn print_changes(command: &FuncsCommand, report: &Report, stdout: &mut Stdout) -> ControlFlow<()> {
+    for unmatched in &report.unmatched {
+        eprintln!("Warning: {unmatched}");
+    }
+    match write!(stdout, "{}", render(command, report)).and_then(|_| stdout.flush()) {
+        Ok(()) => ControlFlow::Continue(()),
+        Err(_) => ControlFlow::Break(()),
+    }
+}
+
+/// Prints the report of `command`.
+///
+/// # Returns
+///
+/// Returns the exit code describing the outcome of the command.
+fn print_report(command: &FuncsCommand, report: &Report) -> ExitCode {
+    for unmatched in &report.unmatched {
+        eprintln!("Warning: {unmatched}");
+    }
+    print!("{}", render(command, report));
+    let (quiet, allow_missing) = match command {
+        FuncsCommand::BrightnessCommand {
+            output, options, ..
+        } => (
+            matches!(output, FuncsBrightnessOutput::Quiet),
+            options.allow_missing,
+        ),
+        FuncsCommand::List { .. } | FuncsCommand::Watch { .. } => (false, true),
+    };
+    if quiet {
+        for record in &report.devices {
+            if let Some(error) = &record.error {
+                eprintln!("Error: {}: {error}", record.index);
+            }
+        }
+    }
+    match report.outcome() {
+        Outcome::Success => ExitCode::SUCCESS,
+        Outcome::NoMatch if allow_missing => ExitCode::SUCCESS,
+        Outcome::NoMatch => {
+            eprintln!("Error: No device matches the selection");
+            ExitCode::from(EXIT_NO_MATCH)
+        }
+        Outcome::PartialFailure => ExitCode::from(EXIT_PARTIAL_FAILURE),
+        Outcome::PermissionDenied => ExitCode::from(EXIT_PERMISSION_DENIED),
+        Outcome::BackendUnavailable => ExitCode::from(EXIT_BACKEND_UNAVAILABLE),
+    }
+}
+
+fn main() -> ExitCode {
+    match try_main() {
+        Ok(code) => code,
+        Err(err @ Error::UnmatchedDevices(_)) => {
+            eprintln!("Error: {err}");
+            eprintln!("Nothing was changed, use --allow-missing to ignore the missing devices");
+            ExitCode::from(EXIT_NO_MATCH)
+        }
+        Err(err) => {
+            eprintln!("Error: {err}");
+            ExitCode::from(match &err {
+                Error::BrightnessError(err) => match ErrorKind::of(err) {
+                    ErrorKind::PermissionDenied => EXIT_PERMISSION_DENIED,
+                    ErrorKind::BackendUnavailable => EXIT_BACKEND_UNAVAILABLE,
+                    ErrorKind::DeviceFailed => EXIT_FAILURE,
+                },
+                Error::IoError { source, .. }
+                    if source.kind() == std::io::ErrorKind::PermissionDenied =>
+                {
+                    EXIT_PERMISSION_DENIED
+                }
+                _ => EXIT_FAILURE,
+            })
+        }
+    }
+}
+
+/// Prints the result of every check of the environment, failing if a device cannot be controlled.
+fn doctor(environment: &Environment) -> ExitCode {
+    let checks = environment.diagnose();
+    for check in &checks {
+        println!("{check}");
+    }
+    if checks.iter().any(|check| check.status == Status::Error) {
+        ExitCode::from(EXIT_FAILURE)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Prints or installs the udev rules covering the devices found in the sysfs tree.
+fn setup_udev(sysfs_root: &Path, dry_run: bool, output: &Path) -> Result<ExitCode, Error> {
+    let rules = UdevRules::discover(sysfs_root).map_err(|source| Error::IoError {
+        path: sysfs_root.to_path_buf(),
+        source,
+    })?;
+    if dry_run {
+        print!("{rules}");
+        return Ok(ExitCode::SUCCESS);
+    }
+    rules.install(output).map_err(|source| Error::IoError {
+        path: output.to_path_buf(),
+        source,
+    })?;
+    println!(
+        "Wrote the rules to {}, apply them with `sudo udevadm control --reload && sudo udevadm \
+         trigger --subsystem-match=backlight --subsystem-match=leds`, then make sure your user \
+         belongs to the `{UDEV_GROUP}` group",
+        output.display()
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Executes the command given on the command line.
+fn try_main() -> Result<ExitCode, Error> {
     let cli = Cli::parse();
-    executor::block_on(FuncsCommand::from(cli.command).handle())
+    if let CliCommand::Doctor = cli.command {
+        return Ok(doctor(&Environment::current(
+            &cli.sysfs_root,
+            DEFAULT_DEV_ROOT,
+        )));
+    }
+    if let CliCommand::Setup {
+        command: SetupCommand::Udev { dry_run, output },
+    } = &cli.command
+    {
+        return setup_udev(&cli.sysfs_root, *dry_run, output);
+    }
+    let path = cli.config.clone().or_else(Config::default_path);
+    let config = config(&cli, path.as_deref())?;
+    if let CliCommand::Config {
+        command: ConfigCommand::Show,
+    } = cli.command
+    {
+        print!("{}", config.to_toml());
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let CliCommand::Schedule {
+        command: Some(ScheduleCommand::Preview { date, step }),
+        ..
+    } = cli.command
+    {
+        let date = date.unwrap_or_else(|| Date::now().0);
+        print!("{}", config.schedule.preview(date, step));
+        return Ok(ExitCode::SUCCESS);
+    }
+    let profiles = || Profiles::locate(path.as_deref());
+    match &cli.command {
+        CliCommand::Profile {
+            command: ProfileCommand::List,
+        } => {
+            for name in profiles()?.names()? {
+                println!("{name}");
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        CliCommand::Profile {
+            command: ProfileCommand::Delete { name },
+        } => {
+            profiles()?.delete(name)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        _ => {}
+    }
+    let socket = cli.socket.clone().or_else(default_socket_path);
+    let backend_name = cli
+        .backend
+        .to_possible_value()
+        .expect("The backends have a name");
+    let settings = Settings::new(backend_name.get_name(), &cli.sysfs_root, &config);
+    let task = match cli.command {
+        CliCommand::Daemon => Task::Daemon {
+            socket: socket.ok_or_else(|| {
+                Error::DaemonError(
+                    "Cannot create a private runtime directory, set XDG_RUNTIME_DIR or --socket"
+                        .to_string(),
+                )
+            })?,
+            settings,
+        },
+        CliCommand::Dbus { address } => Task::Dbus(address),
+        CliCommand::Auto {
+            selector,
+            output,
+            sensor,
+            interval,
+            once,
+        } => {
+            let mut settings = config.auto.clone();
+            settings.sensor = sensor.or(settings.sensor);
+            settings.interval = interval.unwrap_or(settings.interval);
+            let sensor = LightSensor::discover(&cli.sysfs_root, settings.sensor.as_deref())?;
+            let output = match output.into() {
+                FuncsBrightnessOutput::Default => default_output(&config, path.as_deref())?,
+                output => output,
+            };
+            Task::auto(sensor, settings, selector.into(), output, once)
+        }
+        CliCommand::Profile {
+            command:
+                ProfileCommand::Save {
+                    name,
+                    selector,
+                    output,
+                },
+        } => {
+            let allow_missing = selector.allow_missing;
+            let output = match output.into() {
+                FuncsBrightnessOutput::Default => default_output(&config, path.as_deref())?,
+                output => output,
+            };
+            Task::save_profile(profiles()?, name, selector.into(), allow_missing, output)
+        }
+        CliCommand::Profile {
+            command:
+                ProfileCommand::Apply {
+                    name,
+                    allow_missing,
+                    output,
+                    transition: transition_args,
+                },
+        } => {
+            let options = ApplyOptions {
+                transition: transition(transition_args),
+                concurrency: config.concurrency(),
+                allow_missing,
+            };
+            let output = match output.into() {
+                FuncsBrightnessOutput::Default => default_output(&config, path.as_deref())?,
+                output => output,
+            };
+            Task::apply_profile(profiles()?.load(&name)?, options, output)
+        }
+        CliCommand::Profile { .. } => unreachable!("The other profile commands need no backend"),
+        CliCommand::Schedule {
+            selector,
+            output,
+            once,
+            ..
+        } => {
+            let output = match output.into() {
+                FuncsBrightnessOutput::Default => default_output(&config, path.as_deref())?,
+                output => output,
+            };
+            Task::schedule(config.schedule.clone(), selector.into(), output, once)?
+        }
+        command_args => Task::Command {
+            command: command(
+                command_args,
+                &config,
+                default_output(&config, path.as_deref())?,
+            ),
+            daemon: socket
+                .filter(|_| !cli.no_daemon)
+                .map(|socket| (socket, settings)),
+        },
+    };
+    match cli.backend {
+        CliBackend::Auto => run(
+            task,
+            Chain(
+                Chain(BrightnessBackend, SysfsBackend::leds(&cli.sysfs_root)),
+                DdcBackend::new(DEFAULT_DEV_ROOT, &cli.sysfs_root),
+            ),
+            config,
+        ),
+        CliBackend::Brightness => run(task, BrightnessBackend, config),
+        CliBackend::Sysfs => run(task, SysfsBackend::new(cli.sysfs_root), config),
+        CliBackend::Ddc => run(
+            task,
+            DdcBackend::new(DEFAULT_DEV_ROOT, &cli.sysfs_root),
+            config,
+        ),
+        CliBackend::Fake => run(task, FakeBackend::from_env()?, config),
+    }
 }
@@ -0,0 +1,286 @@
+use crate::backend::Backend;
+use crate::error::Error;
+use crate::funcs::{ApplyOptions, BrightnessCommand, DeviceSelector, Report, Unmatched};
+use crate::MAX_BRIGHTNESS;
+use futures::future;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Extension of the files holding the profiles.
+const PROFILE_EXTENSION: &str = "toml";
+
+/// Represents a set of brightness levels saved under a name, such as `reading`, one per device.
+///
+/// A device is recognized by its identifier when it has one, so that the levels follow the
+/// devices when their indices or names change, and by its name otherwise. The identifier of a
+/// sysfs device is its location in the device tree, and that of a monitor comes from its EDID:
+///
+/// ```toml
+/// [[devices]]
+/// id = "sysfs:devices/pci0000:00/0000:00:02.0/drm/card/card-eDP-1/intel_backlight"
+/// name = "intel_backlight"
+/// percent = 40
+///
+/// [[devices]]
+/// id = "edid:DEL-A0B4-12345678"
+/// name = "DELL U2720Q"
+/// percent = 70
+///
+/// [[devices]]
+/// name = "tpacpi::kbd_backlight"
+/// percent = 0
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// Level of every device of the profile.
+    pub devices: Vec<ProfileDevice>,
+}
+
+/// Represents the level of a device in a profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileDevice {
+    /// Identifier of the device that does not change across reboots, if the device has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Name of the device, used to find it when it has no identifier.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Brightness level (in percent) of the device.
+    pub percent: u32,
+}
+
+/// Represents the directory holding the profiles, one file per profile such as `reading.toml`.
+#[derive(Debug, Clone)]
+pub struct Profiles {
+    directory: PathBuf,
+}
+
+impl Profile {
+    /// Creates the profile holding the level of every device of `report`, leaving out the devices
+    /// whose level or name could not be retrieved.
+    pub fn from_report(report: &Report) -> Self {
+        Profile {
+            devices: report
+                .devices
+                .iter()
+                .filter(|record| record.error.is_none() && record.name.is_some())
+                .filter_map(|record| {
+                    Some(ProfileDevice {
+                        id: record.id.clone(),
+                        name: record.name.clone(),
+                        percent: record.percent?,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Sets the level of every device of the profile on the devices of `backend`, as
+    /// `BrightnessCommand::Set` would, changing the devices with the same level together.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Report` holding the state of the devices before and after the change, along
+    /// with the devices of the profile that were not found. Otherwise, returns an
+    /// `rumos::Error`, such as `Error::UnmatchedDevices` when some devices of the profile were
+    /// not found and `options` does not allow it.
+    pub async fn apply<B: Backend>(
+        &self,
+        backend: &B,
+        options: &ApplyOptions,
+    ) -> Result<Report, Error> {
+        let available = DeviceSelector::All.report(backend).await.devices;
+        let mut levels = BTreeMap::<u32, HashSet<usize>>::new();
+        let mut unmatched = Vec::new();
+        for device in &self.devices {
+            let found = available.iter().find(|record| match &device.id {
+                Some(id) => record.id.as_ref() == Some(id),
+                None => record.name.is_some() && record.name == device.name,
+            });
+            match found {
+                Some(record) => {
+                    levels
+                        .entry(device.percent)
+                        .or_default()
+                        .insert(record.index);
+                }
+                None => unmatched.push(Unmatched::Name {
+                    name: device.label().to_string(),
+                    suggestions: Vec::new(),
+                }),
+            }
+        }
+        if !unmatched.is_empty() && !options.allow_missing {
+            return Err(Error::UnmatchedDevices(unmatched));
+        }
+        let options = ApplyOptions {
+            allow_missing: true,
+            ..*options
+        };
+        let reports = future::try_join_all(levels.into_iter().map(|(percent, indices)| {
+            let selector = DeviceSelector::ByIndex(indices);
+            let options = &options;
+            async move {
+                BrightnessCommand::Set { percent }
+                    .apply_with(backend, &selector, options)
+                    .await
+            }
+        }))
+        .await?;
+        let mut devices = reports
+            .into_iter()
+            .flat_map(|report| report.devices)
+            .collect::<Vec<_>>();
+        devices.sort_by_key(|record| record.index);
+        Ok(Report { devices, unmatched })
+    }
+
+    /// Checks that every device has an identifier or a name, and a level in `0..=100`.
+    fn validate(&self) -> Result<(), String> {
+        for device in &self.devices {
+            if device.id.is_none() && device.name.is_none() {
+                return Err("Invalid device in the profile: it needs an id or a name".to_string());
+            }
+            if device.percent > MAX_BRIGHTNESS {
+                return Err(format!(
+                    "Invalid level {} for `{}` in the profile: it must lie in 0..={MAX_BRIGHTNESS}",
+                    device.percent,
+                    device.label(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ProfileDevice {
+    /// Returns the name of the device, or its identifier if the name is unknown.
+    pub fn label(&self) -> &str {
+        self.name.as_deref().or(self.id.as_deref()).unwrap_or("")
+    }
+}
+
+impl Profiles {
+    /// Creates the profiles stored in `directory`.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Profiles {
+            directory: directory.into(),
+        }
+    }
+
+    /// Creates the profiles stored in the `profiles` directory next to the configuration file at
+    /// `config_path`.
+    pub fn beside(config_path: &Path) -> Self {
+        let directory = config_path.parent().unwrap_or(Path::new(""));
+        Profiles::new(directory.join("profiles"))
+    }
+
+    /// Creates the profiles stored next to the configuration file at `config_path`, as `beside`
+    /// does.
+    ///
+    /// # Returns
+    ///
+    /// Returns an `rumos::Error` if the location of the configuration file is unknown.
+    pub fn locate(config_path: Option<&Path>) -> Result<Self, Error> {
+        config_path
+            .map(Profiles::beside)
+            .ok_or_else(|| Error::IoError {
+                path: PathBuf::from("profiles"),
+                source: io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "Cannot locate the configuration directory, set RUMOS_CONFIG or --config",
+                ),
+            })
+    }
+
+    /// Returns the directory holding the profiles.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Returns the names of the saved profiles, in alphabetical order.
+    pub fn names(&self) -> Result<Vec<String>, Error> {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(source) => {
+                return Err(Error::IoError {
+                    path: self.directory.clone(),
+                    source,
+                })
+            }
+        };
+        let mut names = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == PROFILE_EXTENSION))
+            .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+
+    /// Reads the profile named `name`.
+    ///
+    /// # Returns
+    ///
+    /// Returns an `rumos::Error` if no profile has this name, or if its file cannot be read or
+    /// holds invalid levels.
+    pub fn load(&self, name: &str) -> Result<Profile, Error> {
+        let path = self.path(name)?;
+        let contents = fs::read_to_string(&path).map_err(|source| Error::IoError {
+            path: path.clone(),
+            source: match source.kind() {
+                io::ErrorKind::NotFound => io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No profile is named `{name}`"),
+                ),
+                _ => source,
+            },
+        })?;
+        let invalid = |explanation: String| Error::ConfigError {
+            path: path.clone(),
+            explanation,
+        };
+        let profile: Profile = toml::from_str(&contents).map_err(|err| invalid(err.to_string()))?;
+        profile.validate().map_err(invalid)?;
+        Ok(profile)
+    }
+
+    /// Writes `profile` under the name `name`, replacing the profile that had this name.
+    pub fn save(&self, name: &str, profile: &Profile) -> Result<(), Error> {
+        let path = self.path(name)?;
+        let io_error = |source| Error::IoError {
+            path: path.clone(),
+            source,
+        };
+        fs::create_dir_all(&self.directory).map_err(io_error)?;
+        let contents = toml::to_string(profile).expect("Profiles can be serialized");
+        fs::write(&path, contents).map_err(io_error)
+    }
+
+    /// Deletes the profile named `name`.
+    pub fn delete(&self, name: &str) -> Result<(), Error> {
+        let path = self.path(name)?;
+        fs::remove_file(&path).map_err(|source| Error::IoError { path, source })
+    }
+
+    /// Returns the location of the file of the profile named `name`, which must be a valid file
+    /// name.
+    fn path(&self, name: &str) -> Result<PathBuf, Error> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(Error::IoError {
+                path: self.directory.join(name),
+                source: io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid profile name `{name}`"),
+                ),
+            });
+        }
+        Ok(self.directory.join(format!("{name}.{PROFILE_EXTENSION}")))
+    }
+}
@@ -0,0 +1,341 @@
+use crate::backend::DeviceKind;
+use crate::config::{Config, DeviceConfig};
+use crate::funcs::{DeviceSelector, Report, Unmatched};
+use crate::scale::Scale;
+use crate::transition::{runtime_dir, Transition};
+use glob::Pattern;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Version of the protocol spoken over the socket of the daemon, increased on every incompatible
+/// change.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Name of the socket of the daemon, in the runtime directory.
+pub const SOCKET_NAME: &str = "rumos.sock";
+
+/// Returns the default location of the socket of the daemon, `$XDG_RUNTIME_DIR/rumos.sock`.
+///
+/// # Returns
+///
+/// Returns `rumos-<uid>/rumos.sock` in the temporary directory when `XDG_RUNTIME_DIR` is not
+/// set, or `None` if this directory cannot be created or may be reached by other users.
+pub fn default_socket_path() -> Option<PathBuf> {
+    Some(runtime_dir()?.join(SOCKET_NAME))
+}
+
+/// Represents a request sent to the daemon, written as a single line of JSON such as
+/// `{"version": 1, "request": "inc", "percent": 10, "selector": {"names": ["intel_backlight"]}}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    /// Version of the protocol spoken by the client.
+    pub version: u32,
+    /// Settings expected by the client, the daemon refusing the request if its own differ.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<Settings>,
+    #[serde(flatten)]
+    pub body: RequestBody,
+}
+
+/// Represents the settings deciding which devices are available and how their levels are
+/// converted, which must be the same for the daemon and for the clients whose requests it
+/// executes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// Name of the backend providing the devices, such as `auto` or `sysfs`.
+    pub backend: String,
+    /// Mount point of sysfs.
+    pub sysfs_root: PathBuf,
+    /// Brightness scale used by the devices without their own.
+    pub scale: Scale,
+    /// Lowest brightness level (in percent) of the devices without their own.
+    pub min: u32,
+    /// Highest brightness level (in percent) of the devices without their own.
+    pub max: u32,
+    /// Settings of the devices, by device name, alias or kind.
+    pub devices: BTreeMap<String, DeviceConfig>,
+    /// Other names of the devices.
+    pub aliases: BTreeMap<String, String>,
+}
+
+/// Represents the requests understood by the daemon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "kebab-case")]
+pub enum RequestBody {
+    /// Lists every device.
+    List,
+    /// Retrieves the state of the selected devices.
+    Get {
+        #[serde(flatten)]
+        target: Target,
+    },
+    /// Sets the brightness level (in percent) of the selected devices.
+    Set {
+        percent: u32,
+        #[serde(flatten)]
+        target: Target,
+        #[serde(default)]
+        transition: Option<Transition>,
+    },
+    /// Increases the brightness level (in percent) of the selected devices.
+    Inc {
+        percent: u32,
+        #[serde(flatten)]
+        target: Target,
+        #[serde(default)]
+        transition: Option<Transition>,
+    },
+    /// Decreases the brightness level (in percent) of the selected devices.
+    Dec {
+        percent: u32,
+        #[serde(flatten)]
+        target: Target,
+        #[serde(default)]
+        transition: Option<Transition>,
+    },
+    /// Sets the maximum brightness level on the selected devices.
+    Max {
+        #[serde(flatten)]
+        target: Target,
+        #[serde(default)]
+        transition: Option<Transition>,
+    },
+    /// Sets the minimum brightness level on the selected devices.
+    Min {
+        #[serde(flatten)]
+        target: Target,
+        #[serde(default)]
+        transition: Option<Transition>,
+    },
+    /// Fades the selected devices to a brightness level (in percent), cancelling the transitions
+    /// running on them.
+    Fade {
+        percent: u32,
+        #[serde(flatten)]
+        target: Target,
+        transition: Transition,
+    },
+    /// Streams the state of the selected devices, then their changes, until the connection is
+    /// closed.
+    Subscribe {
+        #[serde(default)]
+        selector: DeviceSelector,
+        /// Delay between two readings of the devices whose changes cannot be watched.
+        #[serde(default)]
+        interval_ms: Option<u64>,
+    },
+}
+
+/// Represents the devices a request applies to.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Target {
+    /// The selected devices, every device by default.
+    #[serde(default)]
+    pub selector: DeviceSelector,
+    /// Whether the request is executed even though some of the requested devices do not exist.
+    #[serde(default)]
+    pub allow_missing: bool,
+}
+
+/// Represents a response of the daemon, written as a single line of JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    /// Version of the protocol spoken by the daemon.
+    pub version: u32,
+    #[serde(flatten)]
+    pub body: ResponseBody,
+}
+
+/// Represents the responses sent by the daemon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "kebab-case")]
+pub enum ResponseBody {
+    /// The state of the devices after a request, or when a subscription starts.
+    Report {
+        #[serde(flatten)]
+        report: Report,
+    },
+    /// The state of the devices whose brightness changed, sent to the subscribers.
+    Changed {
+        #[serde(flatten)]
+        report: Report,
+    },
+    /// The request failed as a whole.
+    Error {
+        message: String,
+        /// The parts of the selector that match no device, if the request failed because of
+        /// them.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        unmatched: Vec<Unmatched>,
+    },
+}
+
+impl From<RequestBody> for Request {
+    fn from(body: RequestBody) -> Self {
+        Request {
+            version: PROTOCOL_VERSION,
+            settings: None,
+            body,
+        }
+    }
+}
+
+impl Settings {
+    /// Creates the settings of the devices of `backend`, read from `sysfs_root` and configured
+    /// with `config`.
+    pub fn new(backend: &str, sysfs_root: impl Into<PathBuf>, config: &Config) -> Self {
+        Settings {
+            backend: backend.to_string(),
+            sysfs_root: sysfs_root.into(),
+            scale: config.defaults.scale,
+            min: config.defaults.min,
+            max: config.defaults.max,
+            devices: config.devices.clone(),
+            aliases: config.aliases.clone(),
+        }
+    }
+
+    /// Describes the first setting that differs between the daemon, using `self`, and a client
+    /// using `other`, if any.
+    pub(crate) fn mismatch(&self, other: &Settings) -> Option<String> {
+        let setting = if self.backend != other.backend {
+            format!("the `{}` backend", self.backend)
+        } else if self.sysfs_root != other.sysfs_root {
+            format!("the sysfs root {}", self.sysfs_root.display())
+        } else if self.scale != other.scale {
+            format!("the `{}` scale", self.scale)
+        } else if (self.min, self.max) != (other.min, other.max) {
+            format!("the limits {}..={}", self.min, self.max)
+        } else if self.devices != other.devices || self.aliases != other.aliases {
+            "other device settings or aliases".to_string()
+        } else {
+            return None;
+        };
+        Some(format!(
+            "The daemon uses {setting}, access the devices directly to use the settings of this \
+             invocation"
+        ))
+    }
+}
+
+impl From<ResponseBody> for Response {
+    fn from(body: ResponseBody) -> Self {
+        Response {
+            version: PROTOCOL_VERSION,
+            body,
+        }
+    }
+}
+
+/// Represents a `DeviceSelector` on the wire, such as `"all"`, `{"names": ["intel_backlight"]}`
+/// or `{"not": {"kinds": ["led"]}}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum SelectorSpec {
+    All,
+    Names(Vec<String>),
+    Indices(Vec<usize>),
+    Kinds(Vec<DeviceKind>),
+    Patterns(Vec<String>),
+    Regexes(Vec<String>),
+    AllOf(Vec<SelectorSpec>),
+    AnyOf(Vec<SelectorSpec>),
+    Not(Box<SelectorSpec>),
+}
+
+impl From<DeviceSelector> for SelectorSpec {
+    fn from(selector: DeviceSelector) -> Self {
+        let specs =
+            |selectors: Vec<DeviceSelector>| selectors.into_iter().map(Into::into).collect();
+        match selector {
+            DeviceSelector::All => SelectorSpec::All,
+            DeviceSelector::ByName(names) => {
+                let mut names = names.iter().cloned().collect::<Vec<_>>();
+                names.sort();
+                SelectorSpec::Names(names)
+            }
+            DeviceSelector::ByIndex(indices) => {
+                let mut indices = indices.into_iter().collect::<Vec<_>>();
+                indices.sort();
+                SelectorSpec::Indices(indices)
+            }
+            DeviceSelector::ByKind(kinds) => {
+                let mut kinds = kinds.into_iter().collect::<Vec<_>>();
+                kinds.sort_by_key(ToString::to_string);
+                SelectorSpec::Kinds(kinds)
+            }
+            DeviceSelector::ByPattern(patterns) => {
+                SelectorSpec::Patterns(patterns.iter().map(ToString::to_string).collect())
+            }
+            DeviceSelector::ByRegex(regexes) => {
+                SelectorSpec::Regexes(regexes.iter().map(ToString::to_string).collect())
+            }
+            DeviceSelector::AllOf(selectors) => SelectorSpec::AllOf(specs(selectors)),
+            DeviceSelector::AnyOf(selectors) => SelectorSpec::AnyOf(specs(selectors)),
+            DeviceSelector::Not(selector) => SelectorSpec::Not(Box::new((*selector).into())),
+        }
+    }
+}
+
+impl TryFrom<SelectorSpec> for DeviceSelector {
+    type Error = String;
+
+    fn try_from(spec: SelectorSpec) -> Result<Self, Self::Error> {
+        let selectors = |specs: Vec<SelectorSpec>| {
+            specs
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(match spec {
+            SelectorSpec::All => DeviceSelector::All,
+            SelectorSpec::Names(names) => {
+                DeviceSelector::ByName(Arc::new(names.into_iter().collect()))
+            }
+            SelectorSpec::Indices(indices) => {
+                DeviceSelector::ByIndex(indices.into_iter().collect())
+            }
+            SelectorSpec::Kinds(kinds) => {
+                DeviceSelector::ByKind(kinds.into_iter().collect::<HashSet<_>>())
+            }
+            SelectorSpec::Patterns(patterns) => DeviceSelector::ByPattern(
+                patterns
+                    .iter()
+                    .map(|pattern| {
+                        Pattern::new(pattern)
+                            .map_err(|err| format!("Invalid pattern `{pattern}`: {err}"))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            SelectorSpec::Regexes(regexes) => DeviceSelector::ByRegex(
+                regexes
+                    .iter()
+                    .map(|regex| {
+                        Regex::new(regex)
+                            .map_err(|err| format!("Invalid regular expression `{regex}`: {err}"))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            SelectorSpec::AllOf(specs) => DeviceSelector::AllOf(selectors(specs)?),
+            SelectorSpec::AnyOf(specs) => DeviceSelector::AnyOf(selectors(specs)?),
+            SelectorSpec::Not(spec) => DeviceSelector::Not(Box::new((*spec).try_into()?)),
+        })
+    }
+}
+
+/// Serializes a `Duration` as a number of milliseconds.
+pub(crate) mod millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis().min(u128::from(u64::MAX)) as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
@@ -0,0 +1,136 @@
+use crate::funcs::{BrightnessOutput, Command, DeviceRecord, Report};
+use colored::Colorize;
+
+/// Renders the `Report` of a command in the output format requested by the command.
+///
+/// # Arguments
+///
+/// * `command`: The command that produced the report.
+/// * `report`: The report to render.
+///
+/// # Returns
+///
+/// Returns the rendered text, with one line per device. The text is empty for quiet outputs.
+pub fn render(command: &Command, report: &Report) -> String {
+    let lines = match command {
+        Command::BrightnessCommand { output, .. } => render_brightnesses(report, output),
+        Command::List {
+            output: BrightnessOutput::Default,
+        } => std::iter::once("Available devices:".to_string())
+            .chain(report.devices.iter().map(render_device_name))
+            .collect(),
+        Command::List { output } | Command::Watch { output, .. } => {
+            render_brightnesses(report, output)
+        }
+    };
+    lines.into_iter().map(|line| line + "\n").collect()
+}
+
+/// Renders the brightness levels of the devices of `report` in the given output format.
+fn render_brightnesses(report: &Report, output: &BrightnessOutput) -> Vec<String> {
+    match output {
+        BrightnessOutput::Default => report.devices.iter().map(render_default).collect(),
+        BrightnessOutput::Percent => report.devices.iter().map(render_percent).collect(),
+        BrightnessOutput::Json => {
+            vec![serde_json::to_string_pretty(&report.devices).unwrap()]
+        }
+        BrightnessOutput::JsonLines => report
+            .devices
+            .iter()
+            .map(|record| serde_json::to_string(record).unwrap())
+            .collect(),
+        BrightnessOutput::Template(template) => report
+            .devices
+            .iter()
+            .map(|record| match &record.error {
+                None => template.render(record),
+                Some(error) => render_error(record, error),
+            })
+            .collect(),
+        BrightnessOutput::Quiet => Vec::new(),
+    }
+}
+
+/// Renders the name of a device along with its aliases, kind, backlight type, maximum raw level
+/// and brightness limits.
+fn render_device_name(record: &DeviceRecord) -> String {
+    match (&record.name, &record.error) {
+        (Some(name), _) => {
+            let mut details = Vec::new();
+            details.extend(record.kind.map(|kind| kind.to_string()));
+            details.extend(record.backlight_type.map(|kind| kind.to_string()));
+            details.extend(record.max.map(|max| format!("max {max}")));
+            details.extend(
+                record
+                    .limits
+                    .map(|limits| format!("limits {}-{}%", limits.min, limits.max)),
+            );
+            let aliases = if record.aliases.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", record.aliases.join(", "))
+            };
+            format!(
+                "{}: {}{} ({})",
+                record.index,
+                name.blue().bold(),
+                aliases,
+                details.join(", ")
+            )
+        }
+        (None, error) => format!(
+            "{}: <{}>",
+            record.index,
+            error
+                .as_deref()
+                .unwrap_or("Error while retrieving monitor name")
+                .red()
+                .bold()
+        ),
+    }
+}
+
+/// Renders the brightness level of a device, its index and its name.
+fn render_default(record: &DeviceRecord) -> String {
+    let (Some(name), Some(brightness), None) = (&record.name, record.percent, &record.error) else {
+        return render_error(record, record.error.as_deref().unwrap_or_default());
+    };
+    let limits = record.limits.unwrap_or_default();
+    let name_str = format!("{}: {} brightness:", record.index, name.blue().bold());
+    let brightness_str = format!("{brightness}%").bold();
+    if brightness >= limits.max {
+        format!(
+            "{} {} [{} brightness level reached]",
+            name_str,
+            brightness_str.green(),
+            "Maximum".green().bold(),
+        )
+    } else if brightness <= limits.min {
+        format!(
+            "{} {} [{} brightness level reached]",
+            name_str,
+            brightness_str.red(),
+            "Minimum".red().bold(),
+        )
+    } else {
+        format!("{} {}", name_str, brightness_str.yellow())
+    }
+}
+
+/// Renders only the brightness level of a device.
+fn render_percent(record: &DeviceRecord) -> String {
+    match (record.percent, &record.error) {
+        (Some(percent), None) => format!("{percent}%").yellow().bold().to_string(),
+        (_, error) => error
+            .as_deref()
+            .unwrap_or_default()
+            .red()
+            .underline()
+            .to_string(),
+    }
+}
+
+/// Renders the error that occurred while retrieving the state of a device.
+fn render_error(record: &DeviceRecord, error: &str) -> String {
+    format!("{}: Error {}", record.index, error.red().underline())
+}
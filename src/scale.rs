@@ -0,0 +1,106 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Exponent used by `gamma` when none is given.
+pub const DEFAULT_GAMMA: f64 = 2.2;
+
+/// Represents the curve mapping the brightness levels handled by rumos to the light emitted by
+/// the devices.
+///
+/// With a perceptual scale, `get`, `set`, `inc` and `dec` work on the perceived brightness, so
+/// that a step of 10% looks the same at the bottom and at the top of the range.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Scale {
+    /// The level is proportional to the raw level of the device.
+    #[default]
+    Linear,
+    /// The raw level is the perceived level raised to the given exponent.
+    Gamma(f64),
+    /// The perceived level is the lightness of the CIE 1976 L* color space.
+    Cie,
+}
+
+impl Scale {
+    /// Converts a perceived level to a linear level, both between 0 and 1.
+    pub fn to_linear(self, perceived: f64) -> f64 {
+        let perceived = perceived.clamp(0.0, 1.0);
+        match self {
+            Scale::Linear => perceived,
+            Scale::Gamma(exponent) => perceived.powf(exponent),
+            Scale::Cie => {
+                let lightness = perceived * 100.0;
+                if lightness > 8.0 {
+                    ((lightness + 16.0) / 116.0).powi(3)
+                } else {
+                    lightness / 903.3
+                }
+            }
+        }
+    }
+
+    /// Converts a linear level to a perceived level, both between 0 and 1.
+    pub fn to_perceived(self, linear: f64) -> f64 {
+        let linear = linear.clamp(0.0, 1.0);
+        match self {
+            Scale::Linear => linear,
+            Scale::Gamma(exponent) => linear.powf(exponent.recip()),
+            Scale::Cie => {
+                let lightness = if linear > 0.008856 {
+                    116.0 * linear.cbrt() - 16.0
+                } else {
+                    903.3 * linear
+                };
+                lightness / 100.0
+            }
+        }
+    }
+}
+
+impl Display for Scale {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scale::Linear => write!(f, "linear"),
+            Scale::Gamma(exponent) => write!(f, "gamma:{exponent}"),
+            Scale::Cie => write!(f, "cie"),
+        }
+    }
+}
+
+impl FromStr for Scale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None => match s {
+                "linear" => Ok(Scale::Linear),
+                "gamma" => Ok(Scale::Gamma(DEFAULT_GAMMA)),
+                "cie" => Ok(Scale::Cie),
+                other => Err(format!(
+                    "Unknown scale `{other}`, expected linear, gamma, gamma:EXPONENT or cie"
+                )),
+            },
+            Some(("gamma", exponent)) => match exponent.parse::<f64>() {
+                Ok(exponent) if exponent > 0.0 && exponent.is_finite() => {
+                    Ok(Scale::Gamma(exponent))
+                }
+                _ => Err(format!("Invalid gamma exponent `{exponent}`")),
+            },
+            Some(_) => Err(format!("Unknown scale `{s}`")),
+        }
+    }
+}
+
+impl Serialize for Scale {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Scale {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
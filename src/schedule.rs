@@ -0,0 +1,454 @@
+use crate::backend::Backend;
+use crate::config::ScheduleConfig;
+use crate::error::Error;
+use crate::funcs::{ApplyOptions, BrightnessCommand, DeviceSelector, Report};
+use crate::transition::{parse_duration, Easing, Transition};
+use futures::executor;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter, Write};
+use std::ops::ControlFlow;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Number of minutes in a day.
+const DAY_MINUTES: f64 = 24.0 * 60.0;
+
+/// Zenith of the sun (in degrees) at sunrise and sunset, accounting for the refraction of the
+/// atmosphere and the radius of the sun.
+const SUNRISE_ZENITH: f64 = 90.833;
+
+/// Time taken by the scheduler to reach a new level, smoothing the steps of a transition.
+const STEP_TRANSITION: Duration = Duration::from_secs(1);
+
+/// Represents a day of the Gregorian calendar, such as `2024-06-21`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+/// Represents the time of day of a schedule point: a fixed time such as `07:30`, or a solar
+/// event such as `sunset-30min`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum TimeOfDay {
+    /// Minutes after midnight, local time.
+    Fixed(u32),
+    /// Time of sunrise, moved by a number of seconds.
+    Sunrise(i64),
+    /// Time of sunset, moved by a number of seconds.
+    Sunset(i64),
+}
+
+/// Represents the schedule of a given day, with every point resolved to a local time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DaySchedule {
+    /// Minutes after midnight at which the sun rises, unless it does not rise that day.
+    pub sunrise: Option<f64>,
+    /// Minutes after midnight at which the sun sets, unless it does not set that day.
+    pub sunset: Option<f64>,
+    /// Minutes after midnight and brightness level (in percent) of every point, by time. The
+    /// points relative to a solar event that does not happen that day are left out.
+    pub points: Vec<(f64, u32)>,
+    /// Time (in minutes) taken to fade from a level to the next one, ending at the next point.
+    pub transition: f64,
+}
+
+/// Represents the brightness of a set of devices following a schedule.
+#[derive(Debug)]
+pub struct Scheduler {
+    config: ScheduleConfig,
+    selector: DeviceSelector,
+    level: Option<u32>,
+}
+
+impl Date {
+    /// Returns the date of the day holding the Unix time `days * 86400`.
+    fn from_days(days: i64) -> Self {
+        // Algorithm of http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (yoe + era * 400 + i64::from(month <= 2)) as i32;
+        Date { year, month, day }
+    }
+
+    /// Returns the number of days between the Unix epoch and the date.
+    fn days(&self) -> i64 {
+        // Algorithm of http://howardhinnant.github.io/date_algorithms.html#days_from_civil.
+        let year = i64::from(self.year) - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let month = i64::from(self.month);
+        let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5
+            + i64::from(self.day)
+            - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146_097 + doe - 719_468
+    }
+
+    /// Returns the position of the date in its year, from 1 for the 1st of January.
+    fn ordinal(&self) -> i64 {
+        self.days()
+            - Date {
+                year: self.year,
+                month: 1,
+                day: 1,
+            }
+            .days()
+            + 1
+    }
+
+    fn is_leap_year(&self) -> bool {
+        (self.year % 4 == 0 && self.year % 100 != 0) || self.year % 400 == 0
+    }
+
+    /// Returns the current date and time of day (in minutes after midnight), local time.
+    pub fn now() -> (Date, f64) {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+        let local = seconds + local_offset(seconds);
+        (
+            Date::from_days(local.div_euclid(86_400)),
+            local.rem_euclid(86_400) as f64 / 60.0,
+        )
+    }
+}
+
+impl FromStr for Date {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid date `{value}`, expected a date such as 2024-06-21");
+        let mut parts = value.trim().splitn(3, '-');
+        let mut next = || parts.next().ok_or_else(invalid);
+        let date = Date {
+            year: next()?.parse().map_err(|_| invalid())?,
+            month: next()?.parse().map_err(|_| invalid())?,
+            day: next()?.parse().map_err(|_| invalid())?,
+        };
+        let days_in_month = match date.month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if date.is_leap_year() => 29,
+            2 => 28,
+            _ => return Err(invalid()),
+        };
+        match (1..=days_in_month).contains(&date.day) {
+            true => Ok(date),
+            false => Err(invalid()),
+        }
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl TimeOfDay {
+    /// Checks whether the time depends on the position of the sun.
+    pub fn is_solar(&self) -> bool {
+        !matches!(self, TimeOfDay::Fixed(_))
+    }
+
+    /// Returns the minutes after midnight of the time on a day when the sun rises at `sunrise`
+    /// and sets at `sunset`, unless its solar event does not happen that day.
+    fn resolve(&self, sunrise: Option<f64>, sunset: Option<f64>) -> Option<f64> {
+        let (event, offset) = match *self {
+            TimeOfDay::Fixed(minutes) => return Some(f64::from(minutes)),
+            TimeOfDay::Sunrise(offset) => (sunrise?, offset),
+            TimeOfDay::Sunset(offset) => (sunset?, offset),
+        };
+        Some((event + offset as f64 / 60.0).rem_euclid(DAY_MINUTES))
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let solar = |event: &str| -> Option<Result<i64, String>> {
+            let rest = value.strip_prefix(event)?;
+            let (sign, duration) = match rest.chars().next() {
+                None => return Some(Ok(0)),
+                Some('+') => (1, &rest[1..]),
+                Some('-') => (-1, &rest[1..]),
+                Some(_) => return None,
+            };
+            Some(
+                parse_duration(duration).and_then(|duration| match duration.as_secs() {
+                    seconds @ 0..=86_400 => Ok(sign * seconds as i64),
+                    _ => Err(format!("Invalid offset in `{value}`, expected at most 24h")),
+                }),
+            )
+        };
+        if let Some(offset) = solar("sunrise") {
+            return offset.map(TimeOfDay::Sunrise);
+        }
+        if let Some(offset) = solar("sunset") {
+            return offset.map(TimeOfDay::Sunset);
+        }
+        let invalid = || {
+            format!(
+                "Invalid time `{value}`, expected a time such as 07:30, sunrise+30min or \
+                 sunset-1h"
+            )
+        };
+        let (hours, minutes) = value.split_once(':').ok_or_else(invalid)?;
+        let hours = hours.parse::<u32>().map_err(|_| invalid())?;
+        let minutes = minutes.parse::<u32>().map_err(|_| invalid())?;
+        if hours > 23 || minutes > 59 {
+            return Err(invalid());
+        }
+        Ok(TimeOfDay::Fixed(hours * 60 + minutes))
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        time.to_string()
+    }
+}
+
+impl Display for TimeOfDay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (event, offset) = match *self {
+            TimeOfDay::Fixed(minutes) => {
+                return write!(f, "{:02}:{:02}", minutes / 60, minutes % 60)
+            }
+            TimeOfDay::Sunrise(offset) => ("sunrise", offset),
+            TimeOfDay::Sunset(offset) => ("sunset", offset),
+        };
+        write!(f, "{event}")?;
+        let sign = if offset < 0 { '-' } else { '+' };
+        match offset.unsigned_abs() {
+            0 => Ok(()),
+            seconds if seconds % 60 == 0 => write!(f, "{sign}{}min", seconds / 60),
+            seconds => write!(f, "{sign}{seconds}s"),
+        }
+    }
+}
+
+/// Computes the times of sunrise and sunset (in minutes after midnight, UTC) at the given
+/// position, with the equations of the NOAA Global Monitoring Division.
+///
+/// # Returns
+///
+/// The times of sunrise and sunset, or `None` during the polar night and the midnight sun.
+fn solar_events(date: Date, latitude: f64, longitude: f64) -> Option<(f64, f64)> {
+    let year_days = if date.is_leap_year() { 366.0 } else { 365.0 };
+    // Fractional year (in radians) at noon.
+    let gamma = 2.0 * std::f64::consts::PI / year_days * (date.ordinal() - 1) as f64;
+    let equation_of_time = 229.18
+        * (0.000075 + 0.001868 * gamma.cos()
+            - 0.032077 * gamma.sin()
+            - 0.014615 * (2.0 * gamma).cos()
+            - 0.040849 * (2.0 * gamma).sin());
+    let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2.0 * gamma).cos()
+        + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos()
+        + 0.00148 * (3.0 * gamma).sin();
+    let latitude = latitude.to_radians();
+    let cos_hour_angle = SUNRISE_ZENITH.to_radians().cos() / (latitude.cos() * declination.cos())
+        - latitude.tan() * declination.tan();
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+    let noon = 720.0 - 4.0 * longitude - equation_of_time;
+    Some((noon - 4.0 * hour_angle, noon + 4.0 * hour_angle))
+}
+
+/// Returns the offset (in seconds) of the local time zone from UTC at the Unix time `seconds`.
+#[cfg(target_os = "linux")]
+pub(crate) fn local_offset(seconds: i64) -> i64 {
+    let time = seconds as libc::time_t;
+    // SAFETY: `tm` is a plain structure for which zeroes are valid, and `localtime_r` only
+    // writes to it.
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    // SAFETY: Both pointers are valid for the duration of the call.
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff
+}
+
+/// Returns the offset of UTC from itself, as the local time zone cannot be read.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn local_offset(_seconds: i64) -> i64 {
+    0
+}
+
+impl ScheduleConfig {
+    /// Describes the schedule on `date`, one line at a time: the times of sunrise and sunset when
+    /// the location is known, then the level scheduled every `step`, such as `07:30 60%`, or
+    /// `none` when no level is scheduled.
+    pub fn preview(&self, date: Date, step: Duration) -> String {
+        let day = self.day(date);
+        let mut preview = String::new();
+        if self.latitude.is_some() && self.longitude.is_some() {
+            let event = |minutes: Option<f64>| minutes.map_or("none".to_owned(), format_minutes);
+            let _ = writeln!(preview, "sunrise {}", event(day.sunrise));
+            let _ = writeln!(preview, "sunset {}", event(day.sunset));
+        }
+        let step = (step.as_secs_f64() / 60.0).max(1.0);
+        let mut minutes = 0.0;
+        while minutes < DAY_MINUTES {
+            let _ = match day.level(minutes) {
+                Some(level) => writeln!(preview, "{} {level}%", format_minutes(minutes)),
+                None => writeln!(preview, "{} none", format_minutes(minutes)),
+            };
+            minutes += step;
+        }
+        preview
+    }
+
+    /// Resolves the points of the schedule on `date`, in the local time zone of the system.
+    pub fn day(&self, date: Date) -> DaySchedule {
+        let noon = date.days() * 86_400 + 43_200;
+        self.day_with_offset(date, local_offset(noon))
+    }
+
+    /// Resolves the points of the schedule on `date`, in the time zone `utc_offset` seconds
+    /// ahead of UTC.
+    pub fn day_with_offset(&self, date: Date, utc_offset: i64) -> DaySchedule {
+        let events = match (self.latitude, self.longitude) {
+            (Some(latitude), Some(longitude)) => solar_events(date, latitude, longitude),
+            _ => None,
+        };
+        let local = |minutes: f64| (minutes + utc_offset as f64 / 60.0).rem_euclid(DAY_MINUTES);
+        let sunrise = events.map(|(sunrise, _)| local(sunrise));
+        let sunset = events.map(|(_, sunset)| local(sunset));
+        let mut points = self
+            .points
+            .iter()
+            .filter_map(|point| Some((point.time.resolve(sunrise, sunset)?, point.percent)))
+            .collect::<Vec<_>>();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        DaySchedule {
+            sunrise,
+            sunset,
+            points,
+            transition: self.transition.as_secs_f64() / 60.0,
+        }
+    }
+}
+
+impl DaySchedule {
+    /// Returns the brightness level (in percent) scheduled `minutes` after midnight, unless the
+    /// schedule has no point that day.
+    ///
+    /// The level of a point is kept until the transition to the next point starts, then fades
+    /// linearly to the level of that point, the last point of the day leading to the first one.
+    pub fn level(&self, minutes: f64) -> Option<u32> {
+        let (first, last) = (self.points.first()?, self.points.last()?);
+        let next = self
+            .points
+            .iter()
+            .position(|&(time, _)| time > minutes)
+            .unwrap_or(self.points.len());
+        let (previous_time, previous) = match next {
+            0 => (last.0 - DAY_MINUTES, last.1),
+            _ => self.points[next - 1],
+        };
+        let (next_time, target) = match self.points.get(next) {
+            Some(&point) => point,
+            None => (first.0 + DAY_MINUTES, first.1),
+        };
+        let start = (next_time - self.transition).max(previous_time);
+        if minutes <= start || next_time <= start {
+            return Some(previous);
+        }
+        let ratio = (minutes - start) / (next_time - start);
+        Some(
+            (f64::from(previous) + ratio * (f64::from(target) - f64::from(previous))).round()
+                as u32,
+        )
+    }
+}
+
+/// Formats minutes after midnight as a time such as `07:30`.
+pub fn format_minutes(minutes: f64) -> String {
+    let minutes = minutes.round() as u32 % (24 * 60);
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+impl Scheduler {
+    /// Creates the scheduled brightness of the devices chosen by `selector`.
+    pub fn new(config: ScheduleConfig, selector: DeviceSelector) -> Self {
+        Scheduler {
+            config,
+            selector,
+            level: None,
+        }
+    }
+
+    /// Sets the level scheduled at `minutes` after midnight on `date` on the selected devices,
+    /// unless it is the level set by the previous step, so that the changes made by hand last
+    /// until the schedule moves on.
+    ///
+    /// # Returns
+    ///
+    /// The state of the devices whose brightness was changed.
+    pub async fn step<B: Backend>(
+        &mut self,
+        backend: &B,
+        date: Date,
+        minutes: f64,
+    ) -> Result<Report, Error> {
+        let level = self.config.day(date).level(minutes);
+        let Some(percent) = level.filter(|&level| Some(level) != self.level) else {
+            return Ok(Report::default());
+        };
+        let options = ApplyOptions {
+            transition: Some(Transition {
+                duration: STEP_TRANSITION,
+                easing: Easing::EaseInOut,
+            }),
+            concurrency: None,
+            allow_missing: true,
+        };
+        let report = BrightnessCommand::Set { percent }
+            .apply_with(backend, &self.selector, &options)
+            .await?;
+        self.level = Some(percent);
+        Ok(report)
+    }
+
+    /// Follows the schedule, checking it every `interval` of the configuration until `on_step`
+    /// breaks. This function blocks the current thread.
+    pub fn run<B: Backend>(
+        &mut self,
+        backend: &B,
+        mut on_step: impl FnMut(&Report) -> ControlFlow<()>,
+    ) -> Result<(), Error> {
+        loop {
+            let (date, minutes) = Date::now();
+            let report = executor::block_on(self.step(backend, date, minutes))?;
+            if on_step(&report).is_break() {
+                return Ok(());
+            }
+            thread::sleep(self.config.interval);
+        }
+    }
+}
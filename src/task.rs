@@ -0,0 +1,342 @@
+use crate::ambient::{AutoBrightness, LightSensor};
+use crate::backend::{Backend, Configured, DeviceKind};
+use crate::config::{AutoConfig, Config, ScheduleConfig};
+use crate::error::Error;
+use crate::funcs::{
+    ApplyOptions, BrightnessCommand, BrightnessOutput, Command, DeviceSelector, Report,
+};
+use crate::profile::{Profile, Profiles};
+use crate::protocol::Settings;
+use crate::schedule::Scheduler;
+use futures::executor;
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+
+/// Represents the work done on the devices of a backend, such as executing a command or serving
+/// the requests of the other invocations.
+pub enum Task {
+    /// Executes a command, on the daemon listening on the given socket when one is running.
+    Command {
+        command: Command,
+        daemon: Option<(PathBuf, Settings)>,
+    },
+    /// Serves the requests of the other invocations on the socket at the given location, refusing
+    /// those sent with other settings.
+    Daemon { socket: PathBuf, settings: Settings },
+    /// Serves the devices on the D-Bus bus at the given address, or on the session bus.
+    Dbus(Option<String>),
+    /// Follows the ambient light, reporting the changes like the given `watch` command.
+    Auto {
+        auto: Box<AutoBrightness>,
+        command: Command,
+        once: bool,
+    },
+    /// Saves the levels reported by the given `get` command as the profile with the given name.
+    SaveProfile {
+        profiles: Profiles,
+        name: String,
+        command: Command,
+    },
+    /// Sets the levels of a profile, reporting them like the given `set` command.
+    ApplyProfile {
+        profile: Profile,
+        options: ApplyOptions,
+        command: Command,
+    },
+    /// Follows the schedule, reporting the changes like the given `watch` command.
+    Schedule {
+        scheduler: Box<Scheduler>,
+        command: Command,
+        once: bool,
+    },
+}
+
+impl Task {
+    /// Creates the task following the ambient light read from `sensor` on the devices chosen by
+    /// `selector`, or on the backlights when it names no device, as the keyboard backlights and
+    /// the other LEDs do not follow the ambient light.
+    ///
+    /// # Arguments
+    ///
+    /// * `sensor`: The light sensor measuring the ambient light.
+    /// * `config`: The settings of the automatic brightness.
+    /// * `selector`: The devices whose brightness follows the ambient light.
+    /// * `output`: The format of the changes.
+    /// * `once`: Whether to stop after the first change.
+    pub fn auto(
+        sensor: LightSensor,
+        config: AutoConfig,
+        selector: DeviceSelector,
+        output: BrightnessOutput,
+        once: bool,
+    ) -> Self {
+        let selector = selector.with_default(backlights());
+        Task::Auto {
+            command: Command::Watch {
+                selector: selector.clone(),
+                output,
+                interval: config.interval,
+            },
+            auto: Box::new(AutoBrightness::new(sensor, config, selector)),
+            once,
+        }
+    }
+
+    /// Creates the task following the schedule of `config` on the devices chosen by `selector`,
+    /// or on the backlights when it names no device, as the keyboard backlights and the other
+    /// LEDs do not follow the time of day.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Error::EmptySchedule` if no level is scheduled.
+    pub fn schedule(
+        config: ScheduleConfig,
+        selector: DeviceSelector,
+        output: BrightnessOutput,
+        once: bool,
+    ) -> Result<Self, Error> {
+        if config.points.is_empty() {
+            return Err(Error::EmptySchedule);
+        }
+        let selector = selector.with_default(backlights());
+        Ok(Task::Schedule {
+            command: Command::Watch {
+                selector: selector.clone(),
+                output,
+                interval: config.interval,
+            },
+            scheduler: Box::new(Scheduler::new(config, selector)),
+            once,
+        })
+    }
+
+    /// Creates the task saving the levels of the devices chosen by `selector` as the profile
+    /// named `name`, or of the devices changed by default when it names no device, as applying
+    /// the profile changes them.
+    pub fn save_profile(
+        profiles: Profiles,
+        name: String,
+        selector: DeviceSelector,
+        allow_missing: bool,
+        output: BrightnessOutput,
+    ) -> Self {
+        Task::SaveProfile {
+            profiles,
+            name,
+            command: Command::BrightnessCommand {
+                command: BrightnessCommand::Get,
+                options: ApplyOptions {
+                    allow_missing,
+                    ..ApplyOptions::default()
+                },
+                selector: selector.with_default(DeviceSelector::default_for_changes()),
+                output,
+            },
+        }
+    }
+
+    /// Creates the task setting the levels of `profile` with `options`.
+    pub fn apply_profile(
+        profile: Profile,
+        options: ApplyOptions,
+        output: BrightnessOutput,
+    ) -> Self {
+        Task::ApplyProfile {
+            command: Command::BrightnessCommand {
+                command: BrightnessCommand::Set { percent: 0 },
+                selector: DeviceSelector::All,
+                output,
+                options,
+            },
+            profile,
+            options,
+        }
+    }
+
+    /// Executes the task on the devices of `backend`, configured with `config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend`: The backend providing the devices, unless the command is executed by a
+    ///   running daemon.
+    /// * `config`: The configuration of the devices.
+    /// * `on_change`: The function receiving the changes of the tasks following the devices, along
+    ///   with the command describing how to render them, until it breaks.
+    ///
+    /// # Returns
+    ///
+    /// Returns the report of a task executed once along with the command describing how to render
+    /// it, or `None` once a task following the devices or serving requests stops. Otherwise,
+    /// returns an `rumos::Error`.
+    pub fn run<B: Backend>(
+        self,
+        backend: B,
+        config: Config,
+        mut on_change: impl FnMut(&Command, &Report) -> ControlFlow<()>,
+    ) -> Result<Option<(Command, Report)>, Error> {
+        let concurrency = config.concurrency();
+        let backend = Configured::new(backend, config);
+        match self {
+            Task::Command { command, daemon } => {
+                if let Some((socket, settings)) = daemon {
+                    if let Some(result) = run_on_daemon(&command, &socket, settings, &mut on_change)
+                    {
+                        return result.map(|report| report.map(|report| (command, report)));
+                    }
+                }
+                if let Command::Watch {
+                    selector, interval, ..
+                } = &command
+                {
+                    selector.watch(&backend, *interval, |report| on_change(&command, report));
+                    return Ok(None);
+                }
+                let report = executor::block_on(command.handle(&backend))?;
+                Ok(Some((command, report)))
+            }
+            Task::Daemon { socket, settings } => {
+                serve(backend, &socket, concurrency, settings).map(|()| None)
+            }
+            Task::Dbus(address) => {
+                serve_dbus(backend, address.as_deref(), concurrency).map(|()| None)
+            }
+            Task::Auto {
+                mut auto,
+                command,
+                once,
+            } => {
+                auto.run(&backend, steps(&command, once, on_change))?;
+                Ok(None)
+            }
+            Task::SaveProfile {
+                profiles,
+                name,
+                command,
+            } => {
+                let report = executor::block_on(command.handle(&backend))?;
+                if !report.devices.is_empty() {
+                    profiles.save(&name, &Profile::from_report(&report))?;
+                }
+                Ok(Some((command, report)))
+            }
+            Task::ApplyProfile {
+                profile,
+                options,
+                command,
+            } => {
+                let report = executor::block_on(profile.apply(&backend, &options))?;
+                Ok(Some((command, report)))
+            }
+            Task::Schedule {
+                mut scheduler,
+                command,
+                once,
+            } => {
+                scheduler.run(&backend, steps(&command, once, on_change))?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Returns the selector of the devices following the ambient light or the time of day when none
+/// is named.
+fn backlights() -> DeviceSelector {
+    DeviceSelector::ByKind(HashSet::from([DeviceKind::Backlight]))
+}
+
+/// Returns the callback of a task adjusting the brightness in steps, passing the reports of the
+/// steps that changed something to `on_change` along with `command`, and stopping after the first
+/// step if `once`.
+fn steps<'a>(
+    command: &'a Command,
+    once: bool,
+    mut on_change: impl FnMut(&Command, &Report) -> ControlFlow<()> + 'a,
+) -> impl FnMut(&Report) -> ControlFlow<()> + 'a {
+    move |report| {
+        if !(report.devices.is_empty() && report.unmatched.is_empty())
+            && on_change(command, report).is_break()
+        {
+            return ControlFlow::Break(());
+        }
+        match once {
+            true => ControlFlow::Break(()),
+            false => ControlFlow::Continue(()),
+        }
+    }
+}
+
+/// Executes `command` on the daemon listening on `socket`, unless no daemon is running. The daemon
+/// refuses the command if it does not use `settings`.
+#[cfg(unix)]
+fn run_on_daemon(
+    command: &Command,
+    socket: &Path,
+    settings: Settings,
+    on_change: &mut impl FnMut(&Command, &Report) -> ControlFlow<()>,
+) -> Option<Result<Option<Report>, Error>> {
+    let mut client = crate::daemon::Client::connect(socket)
+        .ok()?
+        .with_settings(settings);
+    Some(match command {
+        Command::Watch { .. } => client
+            .follow(command.request(), |report| on_change(command, report))
+            .map(|()| None),
+        _ => client.request(command.request()).map(Some),
+    })
+}
+
+#[cfg(not(unix))]
+fn run_on_daemon(
+    _command: &Command,
+    _socket: &Path,
+    _settings: Settings,
+    _on_change: &mut impl FnMut(&Command, &Report) -> ControlFlow<()>,
+) -> Option<Result<Option<Report>, Error>> {
+    None
+}
+
+/// Serves the requests of the other invocations on `socket` until the daemon is stopped.
+#[cfg(unix)]
+fn serve<B: Backend>(
+    backend: B,
+    socket: &Path,
+    concurrency: Option<usize>,
+    settings: Settings,
+) -> Result<(), Error> {
+    crate::daemon::serve(backend, socket, concurrency, Some(settings))
+}
+
+#[cfg(not(unix))]
+fn serve<B: Backend>(
+    _backend: B,
+    _socket: &Path,
+    _concurrency: Option<usize>,
+    _settings: Settings,
+) -> Result<(), Error> {
+    Err(Error::DaemonError(
+        "The daemon is only available on Unix systems".to_string(),
+    ))
+}
+
+/// Serves the devices on the D-Bus bus at `address` until the service is stopped.
+#[cfg(target_os = "linux")]
+fn serve_dbus<B: Backend>(
+    backend: B,
+    address: Option<&str>,
+    concurrency: Option<usize>,
+) -> Result<(), Error> {
+    crate::dbus::serve_dbus(backend, address, concurrency)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn serve_dbus<B: Backend>(
+    _backend: B,
+    _address: Option<&str>,
+    _concurrency: Option<usize>,
+) -> Result<(), Error> {
+    Err(Error::DaemonError(
+        "The D-Bus service is only available on Linux".to_string(),
+    ))
+}
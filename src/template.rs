@@ -0,0 +1,146 @@
+use crate::funcs::DeviceRecord;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Width of the bar rendered by the `{bar}` placeholder.
+const DEFAULT_BAR_WIDTH: usize = 10;
+/// Widths accepted by the `{bar:WIDTH}` placeholder.
+const BAR_WIDTHS: std::ops::RangeInclusive<usize> = 1..=200;
+const BAR_FILLED: char = '█';
+const BAR_EMPTY: char = '░';
+
+/// Represents a user-defined output line, such as `{name}: {percent}%`.
+///
+/// The supported placeholders are `{index}`, `{name}`, `{kind}`, `{percent}`, `{raw}`, `{max}` and
+/// `{bar}` (or `{bar:WIDTH}`). Literal braces are written `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Index,
+    Name,
+    Kind,
+    Percent,
+    Raw,
+    Max,
+    Bar { width: usize },
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("Unclosed placeholder in `{s}`"))?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(Field::parse(&rest[..end])?));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(format!("Unmatched `}}` in `{s}`, use `}}}}` instead")),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+}
+
+impl Template {
+    /// Renders the template for a device. Values that could not be retrieved are left empty.
+    pub fn render(&self, record: &DeviceRecord) -> String {
+        let mut line = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => line.push_str(literal),
+                Part::Field(field) => field.render(record, &mut line),
+            }
+        }
+        line
+    }
+}
+
+impl Field {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name.split_once(':') {
+            None => match name {
+                "index" => Ok(Field::Index),
+                "name" => Ok(Field::Name),
+                "kind" => Ok(Field::Kind),
+                "percent" => Ok(Field::Percent),
+                "raw" => Ok(Field::Raw),
+                "max" => Ok(Field::Max),
+                "bar" => Ok(Field::Bar {
+                    width: DEFAULT_BAR_WIDTH,
+                }),
+                other => Err(format!(
+                    "Unknown placeholder `{{{other}}}`, expected one of index, name, kind, \
+                     percent, raw, max or bar"
+                )),
+            },
+            Some(("bar", width)) => width
+                .parse()
+                .ok()
+                .filter(|width| BAR_WIDTHS.contains(width))
+                .map(|width| Field::Bar { width })
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid bar width `{width}`, expected a value between {} and {}",
+                        BAR_WIDTHS.start(),
+                        BAR_WIDTHS.end()
+                    )
+                }),
+            Some(_) => Err(format!("Unknown placeholder `{{{name}}}`")),
+        }
+    }
+
+    fn render(&self, record: &DeviceRecord, line: &mut String) {
+        fn push<T: std::fmt::Display>(line: &mut String, value: Option<T>) {
+            if let Some(value) = value {
+                let _ = write!(line, "{value}");
+            }
+        }
+        match self {
+            Field::Index => push(line, Some(record.index)),
+            Field::Name => push(line, record.name.as_ref()),
+            Field::Kind => push(line, record.kind),
+            Field::Percent => push(line, record.percent),
+            Field::Raw => push(line, record.raw),
+            Field::Max => push(line, record.max),
+            Field::Bar { width } => {
+                let percent = record.percent.unwrap_or(0).min(100) as usize;
+                let filled = (percent * width + 50) / 100;
+                line.extend(std::iter::repeat_n(BAR_FILLED, filled));
+                line.extend(std::iter::repeat_n(BAR_EMPTY, width - filled));
+            }
+        }
+    }
+}
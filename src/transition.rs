@@ -0,0 +1,301 @@
+use crate::backend::{BrightnessResult, Device};
+use futures_timer::Delay;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use std::{env, fs, process};
+
+/// Time between two steps of a transition.
+const STEP_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Represents the curve followed by the brightness level during a transition.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Easing {
+    /// Changes the level at a constant rate.
+    #[default]
+    Linear,
+    /// Starts and ends slowly, changing the level faster in the middle of the transition.
+    EaseInOut,
+    /// Starts slowly and accelerates, which looks steadier to the eye at low levels.
+    Exponential,
+}
+
+/// Represents a timed change of brightness, stepping each device from its current level to the
+/// target level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transition {
+    /// Time taken to reach the target level.
+    #[serde(rename = "duration_ms", with = "crate::protocol::millis")]
+    pub duration: Duration,
+    /// Curve followed by the level between the current and the target level.
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+/// Represents the ownership of a device by the transition of the latest invocation changing its
+/// brightness.
+///
+/// Claiming a device stores a token unique to the invocation in a file of the runtime directory,
+/// removed when the transition ends. A running transition stops as soon as a newer invocation has
+/// replaced or removed its token. The file is locked while the token is checked and the level is
+/// set, so that a transition never overwrites the level set by a newer invocation.
+pub(crate) struct Claim {
+    file: File,
+    path: PathBuf,
+    token: String,
+}
+
+/// Represents the lock taken on a claim while a step of its transition changes the level.
+pub(crate) struct Step<'a>(&'a File);
+
+/// Represents the handover of a device to an invocation changing its level at once, which stops
+/// the transition running on the device, if any.
+pub(crate) struct Handover {
+    file: File,
+    path: PathBuf,
+}
+
+impl Easing {
+    /// Maps the elapsed fraction of a transition (between 0 and 1) to the fraction of the change
+    /// of level that is applied.
+    pub fn apply(self, progress: f64) -> f64 {
+        let progress = progress.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => progress,
+            Easing::EaseInOut => progress * progress * (3.0 - 2.0 * progress),
+            Easing::Exponential if progress >= 1.0 => 1.0,
+            Easing::Exponential => (2f64.powf(10.0 * progress) - 1.0) / 1023.0,
+        }
+    }
+}
+
+impl Transition {
+    /// Steps `device` from the level `from` to the level `to`, stopping early when `claim` is
+    /// superseded by a newer invocation.
+    pub(crate) async fn run<D: Device>(
+        &self,
+        device: &mut D,
+        from: u32,
+        to: u32,
+        claim: Option<&Claim>,
+    ) -> BrightnessResult<()> {
+        let start = Instant::now();
+        let mut level = from;
+        loop {
+            let progress = if self.duration.is_zero() {
+                1.0
+            } else {
+                start.elapsed().as_secs_f64() / self.duration.as_secs_f64()
+            };
+            let change = (f64::from(to) - f64::from(from)) * self.easing.apply(progress);
+            let next = (f64::from(from) + change).round() as u32;
+            // Held until the level is set, so that no newer invocation changes it meanwhile.
+            let step = match claim.map(Claim::step) {
+                Some(None) => return Ok(()),
+                step => step.flatten(),
+            };
+            if next != level || progress >= 1.0 {
+                device.set(next).await?;
+                level = next;
+            }
+            drop(step);
+            if progress >= 1.0 {
+                return Ok(());
+            }
+            Delay::new(STEP_INTERVAL).await;
+        }
+    }
+}
+
+impl Claim {
+    /// Claims the device named `name` for a transition of the current invocation, superseding
+    /// the transitions of older invocations.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if the claim cannot be stored, such as without a private runtime directory,
+    /// in which case the transition simply cannot be interrupted.
+    pub(crate) fn acquire(name: &str) -> Option<Self> {
+        let path = claim_path(name)?;
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let token = format!("{}-{nanos}", process::id());
+        let mut file = open_locked(&path, true).ok()?;
+        let written = file
+            .set_len(0)
+            .and_then(|_| file.write_all(token.as_bytes()));
+        let _ = file.unlock();
+        written.ok()?;
+        Some(Claim { file, path, token })
+    }
+
+    /// Locks the claim for a step of the transition.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if a newer invocation has superseded the claim.
+    pub(crate) fn step(&self) -> Option<Step<'_>> {
+        self.file.lock().ok()?;
+        let step = Step(&self.file);
+        self.is_current().then_some(step)
+    }
+
+    /// Checks whether the file still holds the token of the claim, the file being locked.
+    fn is_current(&self) -> bool {
+        let mut token = String::new();
+        let mut file = &self.file;
+        file.rewind()
+            .and_then(|_| file.read_to_string(&mut token))
+            .is_ok_and(|_| token == self.token)
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        if let Some(_step) = self.step() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl Drop for Step<'_> {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+impl Handover {
+    /// Stops the transition running on the device named `name`, if any, before the current
+    /// invocation changes its level. The transition cannot change the level until the handover
+    /// is dropped.
+    pub(crate) fn take(name: &str) -> Option<Self> {
+        let path = claim_path(name)?;
+        let file = open_locked(&path, false).ok()?;
+        file.set_len(0).ok()?;
+        Some(Handover { file, path })
+    }
+}
+
+impl Drop for Handover {
+    fn drop(&mut self) {
+        // Removed while locked, so that no newer claim is stored in the removed file.
+        let _ = fs::remove_file(&self.path);
+        let _ = self.file.unlock();
+    }
+}
+
+/// Returns the location of the claim of the device named `name`, in the runtime directory.
+///
+/// Every character of the name but the ASCII letters and digits is escaped, so that two names
+/// never share a claim.
+fn claim_path(name: &str) -> Option<PathBuf> {
+    let mut escaped = String::new();
+    for byte in name.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => escaped.push(char::from(byte)),
+            _ => escaped.push_str(&format!("_{byte:02x}")),
+        }
+    }
+    Some(runtime_dir()?.join(format!("rumos-{escaped}.transition")))
+}
+
+/// Opens and locks the file at `path`, creating it if `create` is set, without following a
+/// symbolic link.
+///
+/// The file is opened again if it was removed or replaced while waiting for the lock, so that
+/// the lock taken is the one of the file at `path`.
+#[cfg(unix)]
+fn open_locked(path: &Path, create: bool) -> io::Result<File> {
+    use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+    loop {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(create)
+            .mode(0o600)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(path)?;
+        file.lock()?;
+        let (opened, current) = (file.metadata()?, fs::symlink_metadata(path));
+        match current {
+            Ok(current) if (current.dev(), current.ino()) == (opened.dev(), opened.ino()) => {
+                return Ok(file)
+            }
+            Ok(_) => continue,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Fails, as the claims are only stored on Unix.
+#[cfg(not(unix))]
+fn open_locked(_path: &Path, _create: bool) -> io::Result<File> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Returns the private directory holding the runtime files of rumos: `$XDG_RUNTIME_DIR`, or
+/// `rumos-<uid>` in the temporary directory when the runtime directory is not set.
+///
+/// # Returns
+///
+/// Returns `None` if the directory in the temporary directory cannot be created, or is not a
+/// directory owned by the current user and closed to the others, who could otherwise tamper with
+/// its files.
+pub(crate) fn runtime_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+    private_temp_dir()
+}
+
+#[cfg(unix)]
+fn private_temp_dir() -> Option<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+    // SAFETY: `getuid` cannot fail and has no side effect.
+    let uid = unsafe { libc::getuid() };
+    let dir = env::temp_dir().join(format!("rumos-{uid}"));
+    match fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(_) => return None,
+    }
+    let metadata = fs::symlink_metadata(&dir).ok()?;
+    (metadata.is_dir() && metadata.uid() == uid && metadata.mode() & 0o077 == 0).then_some(dir)
+}
+
+#[cfg(not(unix))]
+fn private_temp_dir() -> Option<PathBuf> {
+    None
+}
+
+/// Parses a duration such as `300ms`, `2s` or `1.5s`.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount = amount
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid duration `{value}`, expected a value such as 300ms or 2s"))?;
+    let seconds = match unit {
+        "ms" => amount / 1000.0,
+        "s" => amount,
+        "min" => amount * 60.0,
+        "h" => amount * 3600.0,
+        "" if amount == 0.0 => 0.0,
+        _ => {
+            return Err(format!(
+                "Invalid duration unit in `{value}`, expected ms, s, min or h"
+            ))
+        }
+    };
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| format!("Invalid duration `{value}`, the value is too large"))
+}
@@ -0,0 +1,91 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Default location of the rules written by `rumos setup udev`.
+pub const DEFAULT_UDEV_RULES_PATH: &str = "/etc/udev/rules.d/90-rumos.rules";
+
+/// Group granted write access to the brightness of the devices.
+pub const UDEV_GROUP: &str = "video";
+
+const SUBSYSTEMS: [&str; 2] = ["backlight", "leds"];
+
+/// Represents the udev rules granting the `video` group write access to the brightness of the
+/// backlights and the LEDs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdevRules {
+    /// Subsystem and kernel name of every device covered by the rules.
+    pub devices: Vec<(String, String)>,
+}
+
+impl UdevRules {
+    /// Creates the rules covering every backlight and LED found in the sysfs tree mounted at
+    /// `sysfs_root`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sysfs_root` - The mount point of sysfs, usually `/sys`.
+    ///
+    /// # Returns
+    ///
+    /// The rules, or the error met while reading a class directory other than a missing one.
+    pub fn discover(sysfs_root: &Path) -> io::Result<Self> {
+        let mut devices = Vec::new();
+        for subsystem in SUBSYSTEMS {
+            let entries = match fs::read_dir(sysfs_root.join("class").join(subsystem)) {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            let mut names = entries
+                .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+                .collect::<io::Result<Vec<_>>>()?;
+            names.sort();
+            devices.extend(names.into_iter().map(|name| (subsystem.to_string(), name)));
+        }
+        Ok(UdevRules { devices })
+    }
+
+    /// Writes the rules to the file at `path`, creating its directory if needed.
+    pub fn install(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_string())
+    }
+}
+
+impl Display for UdevRules {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "# Generated by `rumos setup udev`: grants the `{UDEV_GROUP}` group write access to \
+             the brightness"
+        )?;
+        if self.devices.is_empty() {
+            return writeln!(f, "# No backlight or LED was found");
+        }
+        for (subsystem, name) in &self.devices {
+            let brightness = format!("/sys/class/{subsystem}/%k/brightness");
+            writeln!(
+                f,
+                "ACTION==\"add\", SUBSYSTEM==\"{subsystem}\", KERNEL==\"{}\", \
+                 RUN+=\"/bin/chgrp {UDEV_GROUP} {brightness}\", RUN+=\"/bin/chmod g+w {brightness}\"",
+                escape(name)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Escapes the characters that udev would interpret as a pattern or as the end of the value.
+fn escape(name: &str) -> String {
+    name.chars().fold(String::new(), |mut escaped, c| {
+        if matches!(c, '*' | '?' | '[' | ']' | '|' | '"' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
@@ -0,0 +1,250 @@
+use crate::backend::{Backend, Device, DeviceKind};
+use crate::funcs::{Command, DeviceRecord, DeviceSelector, Report};
+use futures::executor;
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// Delay between two readings of the devices whose changes cannot be watched, unless configured
+/// otherwise.
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+impl DeviceSelector {
+    /// Watches the devices of `backend` chosen by the selector until `on_change` breaks.
+    ///
+    /// `on_change` is first called with the state of every selected device, then with the state
+    /// of the devices whose brightness changed, whoever changed it. The changes are noticed
+    /// through inotify on the files returned by `Device::watched_files`, and the devices are read
+    /// again every `interval` in any case, so that devices without such files and devices
+    /// plugged in later are still watched. The external monitors are only probed again if one of
+    /// them was first selected, as probing them takes time. This function blocks the current
+    /// thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend`: The backend providing the devices.
+    /// * `interval`: The delay between two readings of the devices when nothing is noticed.
+    /// * `on_change`: The function receiving the reports of the changes.
+    pub fn watch<B: Backend>(
+        &self,
+        backend: &B,
+        interval: Duration,
+        mut on_change: impl FnMut(&Report) -> ControlFlow<()>,
+    ) {
+        let mut watcher = Watcher::new();
+        let mut last = HashMap::<(usize, Option<String>), DeviceRecord>::new();
+        let mut first = true;
+        let mut kinds = self.kinds();
+        loop {
+            let (records, unmatched, files) = executor::block_on(async {
+                let selection = Command::select_devices_of(backend, self, &kinds).await;
+                let mut records = Vec::new();
+                let mut files = Vec::new();
+                for (index, dev) in selection.devices {
+                    if let Ok(device) = &dev {
+                        files.extend(device.watched_files());
+                    }
+                    records.push(Command::device_record(index, dev).await);
+                }
+                (records, selection.unmatched, files)
+            });
+            // Watch the files before reporting the state, so that no later change goes unnoticed.
+            watcher.add(&files);
+            if first
+                && !records
+                    .iter()
+                    .any(|record| record.kind == Some(DeviceKind::Ddc))
+            {
+                kinds.remove(&DeviceKind::Ddc);
+            }
+            let devices = records
+                .into_iter()
+                .filter(|record| {
+                    let key = (record.index, record.name.clone());
+                    last.insert(key, record.clone()).as_ref() != Some(record)
+                })
+                .collect::<Vec<_>>();
+            if first || !devices.is_empty() {
+                let report = Report {
+                    devices,
+                    unmatched: if first { unmatched } else { Vec::new() },
+                };
+                if on_change(&report).is_break() {
+                    return;
+                }
+                first = false;
+            }
+            watcher.wait(interval);
+        }
+    }
+}
+
+/// Waits for the modification of the watched files, falling back to sleeping when inotify is
+/// not available.
+struct Watcher {
+    #[cfg(target_os = "linux")]
+    inotify: Option<inotify::Inotify>,
+}
+
+impl Watcher {
+    fn new() -> Self {
+        Watcher {
+            #[cfg(target_os = "linux")]
+            inotify: inotify::Inotify::new().ok(),
+        }
+    }
+
+    /// Watches the modifications of `files`, in addition to the files already watched.
+    #[cfg(target_os = "linux")]
+    fn add(&mut self, files: &[PathBuf]) {
+        if let Some(inotify) = &mut self.inotify {
+            for file in files {
+                inotify.add(file);
+            }
+        }
+    }
+
+    /// Waits until one of the watched files is modified, or at most `timeout`.
+    #[cfg(target_os = "linux")]
+    fn wait(&mut self, timeout: Duration) {
+        match &mut self.inotify {
+            Some(inotify) => {
+                if inotify.wait(timeout).is_err() {
+                    self.inotify = None;
+                    thread::sleep(timeout);
+                }
+            }
+            None => thread::sleep(timeout),
+        }
+    }
+
+    /// Ignores `files`, as their modifications cannot be watched.
+    #[cfg(not(target_os = "linux"))]
+    fn add(&mut self, _files: &[PathBuf]) {}
+
+    /// Waits `timeout`, as the modifications of files cannot be watched.
+    #[cfg(not(target_os = "linux"))]
+    fn wait(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::collections::HashMap;
+    use std::ffi::CString;
+    use std::io;
+    use std::mem;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    /// Events signaling that a file was modified, or that its watch must be added again.
+    const WATCHED_EVENTS: u32 = libc::IN_MODIFY
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_ATTRIB
+        | libc::IN_DELETE_SELF
+        | libc::IN_MOVE_SELF;
+
+    /// An inotify instance watching a set of files.
+    pub struct Inotify {
+        fd: OwnedFd,
+        watches: HashMap<i32, PathBuf>,
+    }
+
+    impl Inotify {
+        pub fn new() -> io::Result<Self> {
+            // SAFETY: `inotify_init1` has no preconditions and returns a new descriptor owned by
+            // the caller.
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Inotify {
+                // SAFETY: `fd` is a valid descriptor that nothing else owns.
+                fd: unsafe { OwnedFd::from_raw_fd(fd) },
+                watches: HashMap::new(),
+            })
+        }
+
+        /// Watches `path` unless it is already watched, ignoring the files that cannot be
+        /// watched since they are read again periodically anyway.
+        pub fn add(&mut self, path: &Path) {
+            if self.watches.values().any(|watched| watched == path) {
+                return;
+            }
+            let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+                return;
+            };
+            // SAFETY: `c_path` is a valid NUL-terminated string.
+            let wd = unsafe {
+                libc::inotify_add_watch(self.fd.as_raw_fd(), c_path.as_ptr(), WATCHED_EVENTS)
+            };
+            if wd >= 0 {
+                self.watches.insert(wd, path.to_path_buf());
+            }
+        }
+
+        /// Waits until an event is received or `timeout` elapses, then consumes the pending
+        /// events.
+        pub fn wait(&mut self, timeout: Duration) -> io::Result<()> {
+            let mut pollfd = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+            // SAFETY: `pollfd` is a single valid `pollfd` structure.
+            if unsafe { libc::poll(&mut pollfd, 1, timeout) } < 0 {
+                let err = io::Error::last_os_error();
+                return match err.kind() {
+                    io::ErrorKind::Interrupted => Ok(()),
+                    _ => Err(err),
+                };
+            }
+            self.read_events()
+        }
+
+        /// Consumes the pending events, forgetting the files whose watch was removed.
+        fn read_events(&mut self) -> io::Result<()> {
+            let mut buffer = [0u8; 4096];
+            loop {
+                // SAFETY: `buffer` is valid for writes of its whole length.
+                let length = unsafe {
+                    libc::read(
+                        self.fd.as_raw_fd(),
+                        buffer.as_mut_ptr().cast(),
+                        buffer.len(),
+                    )
+                };
+                if length < 0 {
+                    let err = io::Error::last_os_error();
+                    return match err.kind() {
+                        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => Ok(()),
+                        _ => Err(err),
+                    };
+                }
+                let length = length as usize;
+                let mut offset = 0;
+                while offset + mem::size_of::<libc::inotify_event>() <= length {
+                    // SAFETY: The kernel wrote a whole event at `offset`, which may not be
+                    // aligned.
+                    let event = unsafe {
+                        buffer
+                            .as_ptr()
+                            .add(offset)
+                            .cast::<libc::inotify_event>()
+                            .read_unaligned()
+                    };
+                    if event.mask & libc::IN_IGNORED != 0 {
+                        self.watches.remove(&event.wd);
+                    }
+                    offset += mem::size_of::<libc::inotify_event>() + event.len as usize;
+                }
+            }
+        }
+    }
+}
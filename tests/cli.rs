@@ -2,14 +2,69 @@
 mod tests {
     use assert_cmd::Command;
     use predicates::prelude::*;
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
 
     // TestType
-    type TestResult = Result<(), brightness::Error>;
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    const FAKE_DEVICES: &str = r#"{
+        "devices": [
+            { "name": "intel_backlight", "brightness": 937, "max_brightness": 937 },
+            { "name": "external", "brightness": 30 }
+        ]
+    }"#;
 
     // Helpers
-    fn set_brightness_for_test() {
+    fn fake_state() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("state.json"), FAKE_DEVICES).unwrap();
+        dir
+    }
+
+    fn rumos(state: &TempDir) -> Command {
         let mut cmd = Command::cargo_bin("rumos").unwrap();
-        cmd.args(["set", "50"]).assert().success();
+        cmd.env("RUMOS_BACKEND", "fake")
+            .env("RUMOS_FAKE_STATE", state.path().join("state.json"))
+            .env("XDG_RUNTIME_DIR", state.path())
+            .env("RUMOS_CONFIG", state.path().join("config.toml"));
+        cmd
+    }
+
+    fn sysfs_root() -> TempDir {
+        let root = TempDir::new().unwrap();
+        for (name, kind, brightness, max) in [
+            ("acpi_video0", "firmware", "10", "10"),
+            ("intel_backlight", "raw", "468", "937"),
+        ] {
+            let device = root.path().join("class/backlight").join(name);
+            fs::create_dir_all(&device).unwrap();
+            fs::write(device.join("type"), format!("{kind}\n")).unwrap();
+            fs::write(device.join("brightness"), format!("{brightness}\n")).unwrap();
+            fs::write(device.join("actual_brightness"), format!("{brightness}\n")).unwrap();
+            fs::write(device.join("max_brightness"), format!("{max}\n")).unwrap();
+        }
+        let led = root.path().join("class/leds/tpacpi::kbd_backlight");
+        fs::create_dir_all(&led).unwrap();
+        fs::write(led.join("brightness"), "0\n").unwrap();
+        fs::write(led.join("max_brightness"), "2\n").unwrap();
+        root
+    }
+
+    fn rumos_sysfs(root: &TempDir) -> Command {
+        let mut cmd = Command::cargo_bin("rumos").unwrap();
+        cmd.args(["--backend", "sysfs", "--sysfs-root"])
+            .arg(root.path())
+            .env("RUMOS_CONFIG", root.path().join("config.toml"))
+            .env("RUMOS_SOCKET", root.path().join("rumos.sock"));
+        cmd
+    }
+
+    fn set_brightness_for_test(state: &TempDir) {
+        rumos(state).args(["set", "50"]).assert().success();
     }
 
     // Tests
@@ -23,21 +78,119 @@ mod tests {
     }
 
     #[test]
-    fn set_brightness_from_args() -> TestResult {
+    fn backend_from_args() -> TestResult {
+        let state = fake_state();
         let mut cmd = Command::cargo_bin("rumos").unwrap();
+        cmd.env("RUMOS_FAKE_STATE", state.path().join("state.json"))
+            .env("XDG_RUNTIME_DIR", state.path())
+            .env("RUMOS_SOCKET", state.path().join("rumos.sock"))
+            .env("RUMOS_CONFIG", state.path().join("config.toml"))
+            .args(["--backend", "fake", "get", "-p"])
+            .assert()
+            .success()
+            .stdout("100%\n30%\n");
+        Ok(())
+    }
+
+    #[test]
+    fn devices_from_env() -> TestResult {
+        let state = TempDir::new()?;
+        rumos(&state)
+            .env(
+                "RUMOS_FAKE_DEVICES",
+                r#"{ "devices": [{ "name": "panel", "brightness": 20 }] }"#,
+            )
+            .args(["get", "-p"])
+            .assert()
+            .success()
+            .stdout("20%\n");
+        Ok(())
+    }
+
+    #[test]
+    fn devices_from_env_need_state_file() -> TestResult {
+        let state = TempDir::new()?;
+        rumos(&state)
+            .env_remove("RUMOS_FAKE_STATE")
+            .env(
+                "RUMOS_FAKE_DEVICES",
+                r#"{ "devices": [{ "name": "panel", "brightness": 20 }] }"#,
+            )
+            .args(["get", "-p"])
+            .assert()
+            .code(1)
+            .stderr(predicate::str::contains(
+                "RUMOS_FAKE_DEVICES needs RUMOS_FAKE_STATE",
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn list_devices() -> TestResult {
+        let state = fake_state();
+        rumos(&state)
+            .arg("list")
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "0: intel_backlight (backlight, max 937, limits 5-100%)",
+            ))
+            .stdout(predicate::str::contains(
+                "1: external (backlight, max 100, limits 5-100%)",
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn set_brightness_from_args() -> TestResult {
+        let state = fake_state();
         let expected = "50";
-        cmd.args(["set", expected])
+        rumos(&state)
+            .args(["set", expected])
             .assert()
             .success()
             .stdout(predicate::str::contains(expected));
         Ok(())
     }
 
+    #[test]
+    fn set_brightness_by_name() -> TestResult {
+        let state = fake_state();
+        rumos(&state)
+            .args(["set", "50", "-d", "external"])
+            .assert()
+            .success();
+        rumos(&state)
+            .args(["get", "-p"])
+            .assert()
+            .success()
+            .stdout("100%\n50%\n");
+        Ok(())
+    }
+
+    #[test]
+    fn set_brightness_by_index() -> TestResult {
+        let state = fake_state();
+        rumos(&state)
+            .args(["set", "50", "-i", "0"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("intel_backlight"))
+            .stdout(predicate::str::contains("external").not());
+        rumos(&state)
+            .args(["get", "-p"])
+            .assert()
+            .success()
+            .stdout("50%\n30%\n");
+        Ok(())
+    }
+
     #[test]
     fn set_max_brightness() -> TestResult {
-        let mut cmd = Command::cargo_bin("rumos").unwrap();
+        let state = fake_state();
         let expected = "Maximum brightness level reached";
-        cmd.arg("max")
+        rumos(&state)
+            .arg("max")
             .assert()
             .success()
             .stdout(predicate::str::contains(expected));
@@ -46,9 +199,10 @@ mod tests {
 
     #[test]
     fn set_min_brightness() -> TestResult {
-        let mut cmd = Command::cargo_bin("rumos").unwrap();
+        let state = fake_state();
         let expected = "Minimum brightness level reached";
-        cmd.arg("min")
+        rumos(&state)
+            .arg("min")
             .assert()
             .success()
             .stdout(predicate::str::contains(expected));
@@ -57,10 +211,11 @@ mod tests {
 
     #[test]
     fn decrease_brightness() -> TestResult {
+        let state = fake_state();
         let expected = "40";
-        set_brightness_for_test();
-        let mut cmd = Command::cargo_bin("rumos").unwrap();
-        cmd.args(["dec", "10"])
+        set_brightness_for_test(&state);
+        rumos(&state)
+            .args(["dec", "10"])
             .assert()
             .success()
             .stdout(predicate::str::contains(expected));
@@ -69,11 +224,11 @@ mod tests {
 
     #[test]
     fn increase_brightness() -> TestResult {
+        let state = fake_state();
         let expected = "60";
-        set_brightness_for_test();
-        let mut cmd = Command::cargo_bin("rumos").unwrap();
-        set_brightness_for_test();
-        cmd.args(["inc", "10"])
+        set_brightness_for_test(&state);
+        rumos(&state)
+            .args(["inc", "10"])
             .assert()
             .success()
             .stdout(predicate::str::contains(expected));
@@ -82,21 +237,1159 @@ mod tests {
 
     #[test]
     fn quiet_argument() -> TestResult {
-        let mut cmd = Command::cargo_bin("rumos").unwrap();
-        set_brightness_for_test();
-        cmd.args(["-q", "inc", "10"]).assert().success().stdout("");
+        let state = fake_state();
+        set_brightness_for_test(&state);
+        rumos(&state)
+            .args(["inc", "10", "-q"])
+            .assert()
+            .success()
+            .stdout("");
         Ok(())
     }
 
     #[test]
     fn percent_argument() -> TestResult {
+        let state = fake_state();
+        let expected = "40%\n40%\n";
+        set_brightness_for_test(&state);
+        rumos(&state)
+            .args(["dec", "10", "-p"])
+            .assert()
+            .success()
+            .stdout(expected);
+        Ok(())
+    }
+
+    #[test]
+    fn sysfs_list_devices() -> TestResult {
+        let root = sysfs_root();
+        rumos_sysfs(&root)
+            .arg("list")
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "0: acpi_video0 (backlight, firmware, max 10, limits 5-100%)",
+            ))
+            .stdout(predicate::str::contains(
+                "1: intel_backlight (backlight, raw, max 937, limits 5-100%)",
+            ))
+            .stdout(predicate::str::contains(
+                "2: tpacpi::kbd_backlight (led, max 2, limits 5-100%)",
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn sysfs_root_from_env() -> TestResult {
+        let root = sysfs_root();
         let mut cmd = Command::cargo_bin("rumos").unwrap();
-        let expected = "\n40%\n";
-        set_brightness_for_test();
-        cmd.args(["-p", "dec", "10"])
+        cmd.env("RUMOS_BACKEND", "sysfs")
+            .env("RUMOS_SYSFS_ROOT", root.path())
+            .args(["get", "-p"])
             .assert()
             .success()
-            .stdout(predicate::str::contains(expected));
+            .stdout("100%\n50%\n0%\n");
+        Ok(())
+    }
+
+    #[test]
+    fn sysfs_set_brightness() -> TestResult {
+        let root = sysfs_root();
+        rumos_sysfs(&root)
+            .args(["set", "20", "-d", "intel_backlight", "-q"])
+            .assert()
+            .success();
+        let brightness = fs::read_to_string(
+            root.path()
+                .join("class/backlight/intel_backlight/brightness"),
+        )?;
+        assert_eq!(brightness, "187");
+        Ok(())
+    }
+
+    #[test]
+    fn sysfs_set_led_brightness() -> TestResult {
+        let root = sysfs_root();
+        rumos_sysfs(&root)
+            .args(["set", "50", "-d", "tpacpi::kbd_backlight", "-q"])
+            .assert()
+            .success();
+        let brightness = fs::read_to_string(
+            root.path()
+                .join("class/leds/tpacpi::kbd_backlight/brightness"),
+        )?;
+        assert_eq!(brightness, "1");
+        Ok(())
+    }
+
+    #[test]
+    fn select_devices_by_kind() -> TestResult {
+        let root = sysfs_root();
+        rumos_sysfs(&root)
+            .args(["get", "--kind", "led"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("tpacpi::kbd_backlight"))
+            .stdout(predicate::str::contains("intel_backlight").not());
+        Ok(())
+    }
+
+    #[test]
+    fn select_devices_by_pattern_and_regex() -> TestResult {
+        let root = sysfs_root();
+        rumos_sysfs(&root)
+            .args(["get", "-d", "*_backlight", "--format", "{name}"])
+            .assert()
+            .success()
+            .stdout("intel_backlight\ntpacpi::kbd_backlight\n");
+        rumos_sysfs(&root)
+            .args(["get", "--regex", "^(acpi|intel)_", "--format", "{name}"])
+            .assert()
+            .success()
+            .stdout("acpi_video0\nintel_backlight\n");
+        Ok(())
+    }
+
+    #[test]
+    fn combine_selectors() -> TestResult {
+        let root = sysfs_root();
+        rumos_sysfs(&root)
+            .args(["get", "--kind", "backlight", "--exclude", "acpi_*"])
+            .args(["--format", "{name}"])
+            .assert()
+            .success()
+            .stdout("intel_backlight\n");
+        rumos_sysfs(&root)
+            .args([
+                "get",
+                "-d",
+                "*_backlight",
+                "--kind",
+                "backlight",
+                "--format",
+                "{name}",
+            ])
+            .assert()
+            .success()
+            .stdout("intel_backlight\n");
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_selector_pattern() -> TestResult {
+        let root = sysfs_root();
+        rumos_sysfs(&root)
+            .args(["get", "--regex", "intel_(backlight"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Invalid regular expression"));
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_excluded_device_name() -> TestResult {
+        let state = fake_state();
+        rumos(&state)
+            .args(["set", "50", "--exclude", "externl"])
+            .assert()
+            .code(4)
+            .stderr(predicate::str::contains(
+                "No device matches `externl`, did you mean `external`?",
+            ));
+        rumos(&state)
+            .args(["get", "-p"])
+            .assert()
+            .success()
+            .stdout("100%\n30%\n");
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_device_name() -> TestResult {
+        let state = fake_state();
+        rumos(&state)
+            .args(["set", "50", "-d", "externl", "-d", "intel_backlight"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "No device matches `externl`, did you mean `external`?",
+            ));
+        rumos(&state)
+            .args(["get", "-p"])
+            .assert()
+            .success()
+            .stdout("100%\n30%\n");
+        rumos(&state)
+            .args(["set", "50", "-d", "externl", "-d", "intel_backlight"])
+            .args(["--allow-missing", "-p"])
+            .assert()
+            .success()
+            .stdout("50%\n")
+            .stderr(predicate::str::contains(
+                "Warning: No device matches `externl`",
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn out_of_range_index() -> TestResult {
+        let state = fake_state();
+        rumos(&state)
+            .args(["max", "-i", "1", "-i", "7"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "No device has the index 7, the indices go from 0 to 1",
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn failing_device_does_not_stop_others() -> TestResult {
+        let state = TempDir::new()?;
+        fs::write(
+            state.path().join("state.json"),
+            r#"{
+                "devices": [
+                    { "name": "panel", "brightness": 20, "fail": "io" },
+                    { "name": "external", "brightness": 30 }
+                ]
+            }"#,
+        )?;
+        let output = rumos(&state)
+            .args(["set", "60", "--format", "json-lines"])
+            .output()?;
+        assert_eq!(output.status.code(), Some(3));
+        let records = String::from_utf8(output.stdout)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()?;
+        assert_eq!(records[0]["error_kind"], "device-failed");
+        assert_eq!(records[0]["percent"], 20);
+        assert_eq!(records[1]["error"], serde_json::Value::Null);
+        assert_eq!(records[1]["percent"], 60);
+        Ok(())
+    }
+
+    #[test]
+    fn unopenable_device_is_reported_to_selectors() -> TestResult {
+        let root = sysfs_root();
+        // Without `max_brightness`, the device cannot be opened, so neither its name nor its kind
+        // is known.
+        fs::create_dir_all(root.path().join("class/backlight/zz_broken"))?;
+        for selector in [
+            ["-d", "intel_backlight"],
+            ["-d", "zz_broken"],
+            ["-k", "led"],
+        ] {
+            rumos_sysfs(&root)
+                .args(["set", "50", "-q"])
+                .args(selector)
+                .assert()
+                .code(3)
+                .stderr(predicate::str::contains(
+                    "Error: 2: Failed to get brightness device zz_broken information",
+                ));
+        }
+        rumos_sysfs(&root)
+            .args(["set", "50", "-i", "1", "-p"])
+            .assert()
+            .success()
+            .stdout("50%\n");
+        Ok(())
+    }
+
+    #[test]
+    fn exit_codes() -> TestResult {
+        let state = TempDir::new()?;
+        fs::write(
+            state.path().join("state.json"),
+            r#"{ "devices": [{ "name": "panel", "brightness": 20, "fail": "permission-denied" }] }"#,
+        )?;
+        rumos(&state).args(["get", "-q"]).assert().code(0);
+        rumos(&state)
+            .args(["max", "-q"])
+            .assert()
+            .code(5)
+            .stderr(predicate::str::contains("Error: 0: "));
+        rumos(&state)
+            .args(["max", "-q", "--kind", "led"])
+            .assert()
+            .code(4)
+            .stderr(predicate::str::contains("No device matches the selection"));
+        rumos(&state)
+            .args(["max", "-q", "--kind", "led", "--allow-missing"])
+            .assert()
+            .code(0);
+        rumos(&state)
+            .args(["max", "-q", "-d", "missing"])
+            .assert()
+            .code(4);
+        fs::write(state.path().join("state.json"), "{")?;
+        rumos(&state).args(["max", "-q"]).assert().code(6);
+        fs::write(state.path().join("config.toml"), "[defaults]\nstep = -1\n")?;
+        rumos(&state).args(["max", "-q"]).assert().code(1);
+        Ok(())
+    }
+
+    #[test]
+    fn json_list() -> TestResult {
+        let state = fake_state();
+        let output = rumos(&state).args(["list", "--format", "json"]).output()?;
+        assert!(output.status.success());
+        let devices: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        assert_eq!(
+            devices,
+            serde_json::json!([
+                {
+                    "index": 0,
+                    "name": "intel_backlight",
+                    "id": null,
+                    "aliases": [],
+                    "kind": "backlight",
+                    "backlight_type": null,
+                    "limits": { "min": 5, "max": 100 },
+                    "before": null,
+                    "percent": 100,
+                    "raw": 937,
+                    "max": 937,
+                    "error": null,
+                    "error_kind": null
+                },
+                {
+                    "index": 1,
+                    "name": "external",
+                    "id": null,
+                    "aliases": [],
+                    "kind": "backlight",
+                    "backlight_type": null,
+                    "limits": { "min": 5, "max": 100 },
+                    "before": null,
+                    "percent": 30,
+                    "raw": 30,
+                    "max": 100,
+                    "error": null,
+                    "error_kind": null
+                }
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn json_lines_keep_device_indices() -> TestResult {
+        let state = fake_state();
+        rumos(&state)
+            .args(["set", "50", "-i", "1", "--format", "json-lines"])
+            .assert()
+            .success()
+            .stdout(concat!(
+                r#"{"index":1,"name":"external","id":null,"aliases":[],"kind":"backlight","backlight_type":null,"limits":{"min":5,"max":100},"before":30,"percent":50,"raw":50,"max":100,"error":null,"error_kind":null}"#,
+                "\n"
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn json_reports_device_errors() -> TestResult {
+        let root = sysfs_root();
+        fs::write(
+            root.path()
+                .join("class/backlight/acpi_video0/max_brightness"),
+            "broken",
+        )?;
+        let output = rumos_sysfs(&root)
+            .args(["get", "--format", "json"])
+            .output()?;
+        let devices: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        let device = &devices[0];
+        assert_eq!(device["index"], 0);
+        assert!(device["error"]
+            .as_str()
+            .is_some_and(|error| error.contains("acpi_video0")));
+        Ok(())
+    }
+
+    #[test]
+    fn template_format() -> TestResult {
+        let state = fake_state();
+        rumos(&state)
+            .args([
+                "dec",
+                "10",
+                "-i",
+                "1",
+                "--format",
+                "{index}:{name} {percent}% {bar}",
+            ])
+            .assert()
+            .success()
+            .stdout("1:external 20% ██░░░░░░░░\n");
+        Ok(())
+    }
+
+    #[test]
+    fn template_format_for_list() -> TestResult {
+        let state = fake_state();
+        rumos(&state)
+            .args(["list", "--format", "{{{kind}}} {name} {raw}/{max} {bar:4}"])
+            .assert()
+            .success()
+            .stdout("{backlight} intel_backlight 937/937 ████\n{backlight} external 30/100 █░░░\n");
+        Ok(())
+    }
+
+    #[test]
+    fn template_with_unknown_placeholder() -> TestResult {
+        let state = fake_state();
+        rumos(&state)
+            .args(["get", "--format", "{brightness}%"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "Unknown placeholder `{brightness}`",
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn transition_reaches_target() -> TestResult {
+        let state = fake_state();
+        let start = Instant::now();
+        rumos(&state)
+            .args([
+                "set",
+                "50",
+                "-i",
+                "1",
+                "--transition",
+                "200ms",
+                "--easing",
+                "ease-in-out",
+            ])
+            .args(["-p"])
+            .assert()
+            .success()
+            .stdout("50%\n");
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        Ok(())
+    }
+
+    #[test]
+    fn newer_invocation_cancels_transition() -> TestResult {
+        let state = fake_state();
+        let start = Instant::now();
+        let mut fading = std::process::Command::new(assert_cmd::cargo::cargo_bin("rumos"))
+            .env("RUMOS_BACKEND", "fake")
+            .env("RUMOS_FAKE_STATE", state.path().join("state.json"))
+            .env("XDG_RUNTIME_DIR", state.path())
+            .args(["set", "100", "-i", "1", "--transition", "5s", "-q"])
+            .spawn()?;
+        thread::sleep(Duration::from_millis(500));
+        rumos(&state)
+            .args(["set", "20", "-i", "1", "-q"])
+            .assert()
+            .success();
+        assert!(fading.wait()?.success());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        rumos(&state)
+            .args(["get", "-i", "1", "-p"])
+            .assert()
+            .success()
+            .stdout("20%\n");
+        assert_eq!(claims(&state), Vec::<String>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn claims_are_removed_after_changes() -> TestResult {
+        let state = fake_state();
+        rumos(&state).args(["set", "50", "-q"]).assert().success();
+        assert_eq!(claims(&state), Vec::<String>::new());
+        rumos(&state)
+            .args(["set", "40", "--transition", "50ms", "-q"])
+            .assert()
+            .success();
+        assert_eq!(claims(&state), Vec::<String>::new());
+        Ok(())
+    }
+
+    fn claims(state: &TempDir) -> Vec<String> {
+        fs::read_dir(state.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".transition"))
+            .collect()
+    }
+
+    #[test]
+    fn template_with_invalid_bar_width() -> TestResult {
+        let state = fake_state();
+        for width in ["0", "4000000000", "18446744073709551615"] {
+            rumos(&state)
+                .args(["get", "--format", &format!("{{bar:{width}}}")])
+                .assert()
+                .code(2)
+                .stderr(predicate::str::contains(format!(
+                    "Invalid bar width `{width}`, expected a value between 1 and 200"
+                )));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn transition_with_invalid_duration() -> TestResult {
+        let state = fake_state();
+        rumos(&state)
+            .args(["set", "50", "--transition", "fast"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Invalid duration `fast`"));
+        rumos(&state)
+            .args(["set", "40", "--transition", "99999999999999999999999h"])
+            .assert()
+            .code(2)
+            .stderr(predicate::str::contains("the value is too large"));
+        Ok(())
+    }
+
+    #[test]
+    fn perceptual_scale_for_every_device() -> TestResult {
+        let root = sysfs_root();
+        rumos_sysfs(&root)
+            .args([
+                "--scale",
+                "gamma",
+                "set",
+                "50",
+                "-d",
+                "intel_backlight",
+                "-q",
+            ])
+            .assert()
+            .success();
+        let brightness = fs::read_to_string(
+            root.path()
+                .join("class/backlight/intel_backlight/brightness"),
+        )?;
+        assert_eq!(brightness, "204");
+        Ok(())
+    }
+
+    #[test]
+    fn perceptual_scale_for_one_device() -> TestResult {
+        let root = sysfs_root();
+        rumos_sysfs(&root)
+            .args(["--device-scale", "intel_backlight=cie", "get", "-p"])
+            .assert()
+            .success()
+            .stdout("100%\n76%\n0%\n");
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_scale() -> TestResult {
+        let root = sysfs_root();
+        rumos_sysfs(&root)
+            .args(["--scale", "log", "get"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Unknown scale `log`"));
+        Ok(())
+    }
+
+    #[test]
+    fn defaults_from_config_file() -> TestResult {
+        let state = fake_state();
+        fs::write(
+            state.path().join("config.toml"),
+            "[defaults]\nformat = \"{name}={percent}\"\nstep = 5\n",
+        )?;
+        rumos(&state)
+            .args(["dec", "-i", "1"])
+            .assert()
+            .success()
+            .stdout("external=25\n");
+        rumos(&state)
+            .args(["get", "-i", "1", "-p"])
+            .assert()
+            .success()
+            .stdout("25%\n");
+        Ok(())
+    }
+
+    #[test]
+    fn config_limits_for_one_device() -> TestResult {
+        let state = fake_state();
+        fs::write(
+            state.path().join("config.toml"),
+            "[defaults]\nmax = 90\n\n[devices.external]\nmin = 0\n",
+        )?;
+        rumos(&state).args(["min", "-q"]).assert().success();
+        rumos(&state)
+            .args(["get", "-p"])
+            .assert()
+            .success()
+            .stdout("5%\n0%\n");
+        rumos(&state)
+            .args(["max", "-p"])
+            .assert()
+            .success()
+            .stdout("90%\n90%\n");
+        Ok(())
+    }
+
+    #[test]
+    fn limits_by_kind_and_alias() -> TestResult {
+        let root = sysfs_root();
+        fs::write(
+            root.path().join("config.toml"),
+            concat!(
+                "[devices.backlight]\nmin = 20\n\n",
+                "[devices.led]\nmin = 0\nmax = 50\n\n",
+                "[devices.panel]\nmin = 30\n\n",
+                "[aliases]\npanel = \"intel_backlight\"\n",
+            ),
+        )?;
+        rumos_sysfs(&root)
+            .arg("list")
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "0: acpi_video0 (backlight, firmware, max 10, limits 20-100%)",
+            ))
+            .stdout(predicate::str::contains(
+                "1: intel_backlight [panel] (backlight, raw, max 937, limits 30-100%)",
+            ))
+            .stdout(predicate::str::contains(
+                "2: tpacpi::kbd_backlight (led, max 2, limits 0-50%)",
+            ));
+        rumos_sysfs(&root)
+            .args(["max", "-q", "-k", "led"])
+            .assert()
+            .success();
+        let led = root
+            .path()
+            .join("class/leds/tpacpi::kbd_backlight/brightness");
+        assert_eq!(fs::read_to_string(led)?, "1");
+        Ok(())
+    }
+
+    #[test]
+    fn leds_are_changed_only_when_selected() -> TestResult {
+        let root = sysfs_root();
+        let capslock = root.path().join("class/leds/input3::capslock");
+        fs::create_dir_all(&capslock)?;
+        fs::write(capslock.join("brightness"), "0\n")?;
+        fs::write(capslock.join("max_brightness"), "1\n")?;
+        rumos_sysfs(&root)
+            .args(["set", "50", "-f", "{name}"])
+            .assert()
+            .success()
+            .stdout("acpi_video0\nintel_backlight\n");
+        assert_eq!(fs::read_to_string(capslock.join("brightness"))?, "0\n");
+        rumos_sysfs(&root)
+            .args(["set", "50", "--exclude", "acpi_video0", "-f", "{name}"])
+            .assert()
+            .success()
+            .stdout("intel_backlight\n");
+        rumos_sysfs(&root)
+            .args(["set", "100", "-d", "input3::capslock", "-p"])
+            .assert()
+            .success()
+            .stdout("100%\n");
+        rumos_sysfs(&root)
+            .args(["get", "-f", "{name}"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("input3::capslock"));
+        Ok(())
+    }
+
+    #[test]
+    fn select_devices_by_alias() -> TestResult {
+        let state = TempDir::new()?;
+        fs::write(
+            state.path().join("state.json"),
+            r#"{
+                "devices": [
+                    { "name": "amdgpu_bl1", "brightness": 40 },
+                    { "name": "DELL U2720Q", "id": "edid:DEL-A0B4-12345678", "kind": "ddc", "brightness": 30 },
+                    { "name": "tpacpi::kbd_backlight", "kind": "led", "brightness": 0 }
+                ]
+            }"#,
+        )?;
+        fs::write(
+            state.path().join("config.toml"),
+            concat!(
+                "[aliases]\n",
+                "internal = \"amdgpu_bl*\"\n",
+                "left-monitor = \"edid:DEL-A0B4-12345678\"\n",
+                "kbd = \"tpacpi::kbd_backlight\"\n",
+            ),
+        )?;
+        rumos(&state)
+            .args(["set", "70", "-d", "internal", "-d", "left-monitor", "-p"])
+            .assert()
+            .success()
+            .stdout("70%\n70%\n");
+        rumos(&state)
+            .args(["get", "-d", "kbd", "--format", "{name}"])
+            .assert()
+            .success()
+            .stdout("tpacpi::kbd_backlight\n");
+        rumos(&state)
+            .arg("list")
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "0: amdgpu_bl1 [internal] (backlight",
+            ))
+            .stdout(predicate::str::contains(
+                "1: DELL U2720Q [left-monitor] (ddc",
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn limits_from_args() -> TestResult {
+        let state = fake_state();
+        fs::write(
+            state.path().join("config.toml"),
+            "[devices.external]\nmin = 0\n",
+        )?;
+        rumos(&state)
+            .args([
+                "--max-level",
+                "80",
+                "--device-limits",
+                "external=10:",
+                "max",
+                "-p",
+            ])
+            .assert()
+            .success()
+            .stdout("80%\n80%\n");
+        rumos(&state)
+            .args([
+                "--device-limits",
+                "intel_backlight=0:",
+                "--device-limits",
+                "external=10:",
+                "min",
+                "-p",
+            ])
+            .assert()
+            .success()
+            .stdout("0%\n10%\n");
+        rumos(&state)
+            .args(["--device-limits", "external=60:40", "get"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("the minimum exceeds the maximum"));
+        Ok(())
+    }
+
+    #[test]
+    fn config_show_merges_options() -> TestResult {
+        let state = fake_state();
+        fs::write(
+            state.path().join("config.toml"),
+            "[defaults]\nstep = 5\nscale = \"cie\"\n",
+        )?;
+        rumos(&state)
+            .args(["--scale", "gamma:2", "--concurrency", "1", "config", "show"])
+            .assert()
+            .success()
+            .stdout(concat!(
+                "[defaults]\n",
+                "step = 5\n",
+                "scale = \"gamma:2\"\n",
+                "min = 5\n",
+                "max = 100\n",
+                "concurrency = 1\n",
+                "\n",
+                "[devices]\n",
+                "\n",
+                "[aliases]\n",
+                "\n",
+                "[auto]\n",
+                "curve = [[0.0, 10], [10.0, 20], [100.0, 40], [1000.0, 70], [10000.0, 100]]\n",
+                "hysteresis = 5\n",
+                "smoothing = 0.5\n",
+                "interval = \"2s\"\n",
+                "transition = \"1s\"\n",
+                "\n",
+                "[schedule]\n",
+                "transition = \"30min\"\n",
+                "interval = \"1min\"\n",
+                "points = []\n",
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_limits_from_args() -> TestResult {
+        let state = fake_state();
+        rumos(&state)
+            .args(["--min-level", "80", "--max-level", "20", "set", "50"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "Invalid command-line options: Invalid limits in [defaults]",
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_config_file() -> TestResult {
+        let state = fake_state();
+        fs::write(
+            state.path().join("config.toml"),
+            "[defaults]\nmin = 50\nmax = 20\n",
+        )?;
+        rumos(&state)
+            .arg("get")
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Invalid limits in [defaults]"));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_auto_curve() -> TestResult {
+        let state = fake_state();
+        fs::write(
+            state.path().join("config.toml"),
+            "[auto]\ncurve = [[100, 40], [10, 20]]\n",
+        )?;
+        rumos(&state)
+            .arg("get")
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "the points must be sorted by increasing illuminance",
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn auto_adjusts_backlights_once() -> TestResult {
+        let root = sysfs_root();
+        let sensor = root.path().join("bus/iio/devices/iio:device0");
+        fs::create_dir_all(&sensor)?;
+        fs::write(sensor.join("in_illuminance_input"), "100\n")?;
+        fs::write(
+            root.path().join("config.toml"),
+            "[auto]\ncurve = [[0, 0], [1000, 100]]\ntransition = \"0s\"\n",
+        )?;
+        rumos_sysfs(&root)
+            .args(["auto", "--once", "-f", "{name}"])
+            .assert()
+            .success()
+            .stdout("acpi_video0\nintel_backlight\n");
+        let brightness = |device: &str| {
+            fs::read_to_string(root.path().join("class").join(device).join("brightness"))
+                .map(|brightness| brightness.trim().to_string())
+        };
+        assert_eq!(brightness("backlight/acpi_video0")?, "1");
+        assert_eq!(brightness("backlight/intel_backlight")?, "94");
+        assert_eq!(brightness("leds/tpacpi::kbd_backlight")?, "0");
+        Ok(())
+    }
+
+    #[test]
+    fn schedule_preview_prints_levels() -> TestResult {
+        let state = fake_state();
+        fs::write(
+            state.path().join("config.toml"),
+            concat!(
+                "[schedule]\n",
+                "latitude = 48.8566\n",
+                "longitude = 2.3522\n",
+                "transition = \"1h\"\n",
+                "points = [\n",
+                "  { time = \"07:00\", percent = 60 },\n",
+                "  { time = \"sunset-30min\", percent = 20 },\n",
+                "]\n",
+            ),
+        )?;
+        rumos(&state)
+            .env("TZ", "UTC")
+            .args([
+                "schedule",
+                "preview",
+                "--date",
+                "2024-06-21",
+                "--step",
+                "3h",
+            ])
+            .assert()
+            .success()
+            .stdout(concat!(
+                "sunrise 03:47\n",
+                "sunset 19:58\n",
+                "00:00 20%\n",
+                "03:00 20%\n",
+                "06:00 20%\n",
+                "09:00 60%\n",
+                "12:00 60%\n",
+                "15:00 60%\n",
+                "18:00 60%\n",
+                "21:00 20%\n",
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn empty_schedule() -> TestResult {
+        let state = fake_state();
+        rumos(&state)
+            .args(["schedule", "--once"])
+            .assert()
+            .code(1)
+            .stderr(
+                "Error: No brightness level is scheduled in the [schedule] section of the \
+                 configuration\n",
+            );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_schedule_point() -> TestResult {
+        let state = fake_state();
+        fs::write(
+            state.path().join("config.toml"),
+            "[schedule]\npoints = [{ time = \"sunrise\", percent = 50 }]\n",
+        )?;
+        rumos(&state)
+            .args(["schedule", "preview"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("latitude"));
+        Ok(())
+    }
+
+    #[test]
+    fn schedule_sets_backlights_once() -> TestResult {
+        let state = fake_state();
+        fs::write(
+            state.path().join("config.toml"),
+            "[schedule]\npoints = [{ time = \"12:00\", percent = 60 }]\n",
+        )?;
+        rumos(&state)
+            .args(["schedule", "--once", "-f", "{name} {percent}"])
+            .assert()
+            .success()
+            .stdout("intel_backlight 60\nexternal 60\n");
+        rumos(&state)
+            .arg("get")
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("external brightness: 60%"));
+        Ok(())
+    }
+
+    #[test]
+    fn profile_save_and_apply() -> TestResult {
+        let state = fake_state();
+        rumos(&state)
+            .args(["profile", "save", "reading", "-d", "external", "-q"])
+            .assert()
+            .success();
+        rumos(&state).args(["set", "80"]).assert().success();
+        rumos(&state)
+            .args(["profile", "list"])
+            .assert()
+            .success()
+            .stdout("reading\n");
+        rumos(&state)
+            .args([
+                "profile",
+                "apply",
+                "reading",
+                "-t",
+                "50ms",
+                "-f",
+                "{name} {percent}",
+            ])
+            .assert()
+            .success()
+            .stdout("external 30\n");
+        rumos(&state)
+            .arg("get")
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("external brightness: 30%"))
+            .stdout(predicate::str::contains("intel_backlight brightness: 80%"));
+        rumos(&state)
+            .args(["profile", "delete", "reading"])
+            .assert()
+            .success();
+        rumos(&state)
+            .args(["profile", "apply", "reading"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("No profile is named `reading`"));
+        Ok(())
+    }
+
+    #[test]
+    fn profile_reports_missing_devices() -> TestResult {
+        let state = fake_state();
+        fs::create_dir(state.path().join("profiles"))?;
+        fs::write(
+            state.path().join("profiles/movie.toml"),
+            "[[devices]]\nname = \"external\"\npercent = 10\n\n\
+             [[devices]]\nname = \"projector\"\npercent = 90\n",
+        )?;
+        rumos(&state)
+            .args(["profile", "apply", "movie"])
+            .assert()
+            .code(4)
+            .stderr(predicate::str::contains("No device matches `projector`"));
+        rumos(&state)
+            .args(["profile", "apply", "movie", "--allow-missing", "-p"])
+            .assert()
+            .success()
+            .stdout("10%\n")
+            .stderr(predicate::str::contains(
+                "Warning: No device matches `projector`",
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn doctor_checks_devices() -> TestResult {
+        let root = sysfs_root();
+        rumos_sysfs(&root)
+            .arg("doctor")
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "[ok] sysfs intel_backlight: The brightness is writable",
+            ))
+            .stdout(predicate::str::contains(
+                "[ok] sysfs tpacpi::kbd_backlight: The brightness is writable",
+            ))
+            .stdout(predicate::str::contains("i2c-dev: "));
+        Ok(())
+    }
+
+    #[test]
+    fn setup_udev_dry_run() -> TestResult {
+        let root = sysfs_root();
+        rumos_sysfs(&root)
+            .args(["setup", "udev", "--dry-run", "--output"])
+            .arg(root.path().join("rules.d/90-rumos.rules"))
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "ACTION==\"add\", SUBSYSTEM==\"backlight\", KERNEL==\"intel_backlight\", \
+                 RUN+=\"/bin/chgrp video /sys/class/backlight/%k/brightness\", \
+                 RUN+=\"/bin/chmod g+w /sys/class/backlight/%k/brightness\"\n",
+            ))
+            .stdout(predicate::str::contains(
+                "SUBSYSTEM==\"leds\", KERNEL==\"tpacpi::kbd_backlight\"",
+            ));
+        assert!(!root.path().join("rules.d").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn setup_udev_writes_rules() -> TestResult {
+        let root = sysfs_root();
+        let path = root.path().join("rules.d/90-rumos.rules");
+        rumos_sysfs(&root)
+            .args(["setup", "udev", "-o"])
+            .arg(&path)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("sudo udevadm control --reload"));
+        let rules = fs::read_to_string(&path)?;
+        assert_eq!(
+            rules
+                .lines()
+                .filter(|line| line.starts_with("ACTION"))
+                .count(),
+            3
+        );
+        assert!(rules.contains("KERNEL==\"acpi_video0\""));
+        Ok(())
+    }
+
+    #[test]
+    fn watch_prints_changes() -> TestResult {
+        let root = sysfs_root();
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("rumos"))
+            .args(["--backend", "sysfs", "--sysfs-root"])
+            .arg(root.path())
+            .env("RUMOS_CONFIG", root.path().join("config.toml"))
+            .args(["watch", "-d", "intel_backlight", "--interval", "30s"])
+            .args(["-f", "{name} {raw}/{max}"])
+            .stdout(std::process::Stdio::piped())
+            .spawn()?;
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        let first = lines.next().transpose()?;
+        fs::write(
+            root.path()
+                .join("class/backlight/intel_backlight/actual_brightness"),
+            "700\n",
+        )?;
+        let second = lines.next().transpose()?;
+        child.kill()?;
+        child.wait()?;
+        assert_eq!(first.as_deref(), Some("intel_backlight 468/937"));
+        assert_eq!(second.as_deref(), Some("intel_backlight 700/937"));
+        Ok(())
+    }
+
+    #[test]
+    fn commands_use_running_daemon() -> TestResult {
+        let state = fake_state();
+        let mut daemon = std::process::Command::new(assert_cmd::cargo::cargo_bin("rumos"))
+            .arg("daemon")
+            .env("RUMOS_BACKEND", "fake")
+            .env("RUMOS_FAKE_STATE", state.path().join("state.json"))
+            .env("XDG_RUNTIME_DIR", state.path())
+            .env("RUMOS_CONFIG", state.path().join("config.toml"))
+            .spawn()?;
+        let start = Instant::now();
+        while !state.path().join("rumos.sock").exists() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "The daemon did not start"
+            );
+            thread::sleep(Duration::from_millis(20));
+        }
+        // The devices of this invocation differ from those of the daemon, so the output tells
+        // which one handled the command.
+        let other = TempDir::new()?;
+        let client = |args: &[&str]| {
+            let mut cmd = Command::cargo_bin("rumos").unwrap();
+            cmd.env("RUMOS_BACKEND", "fake")
+                .env("RUMOS_FAKE_STATE", other.path().join("state.json"))
+                .env("XDG_RUNTIME_DIR", state.path())
+                .env("RUMOS_CONFIG", state.path().join("config.toml"))
+                .args(args);
+            cmd
+        };
+        let result = client(&["set", "20", "-d", "external", "-f", "{name} {percent}"]).assert();
+        let no_daemon = client(&["--no-daemon", "get", "-f", "{name} {percent}"]).assert();
+        let unmatched = client(&["set", "20", "-d", "missing"]).assert();
+        let limited = client(&["--max-level", "50", "set", "20", "-d", "external"]).assert();
+        daemon.kill()?;
+        daemon.wait()?;
+
+        result.success().stdout("external 20\n");
+        no_daemon.success().stdout("fake_backlight 100\n");
+        unmatched
+            .code(4)
+            .stderr(predicate::str::contains("No device matches `missing`"));
+        limited.failure().stderr(predicate::str::contains(
+            "The daemon uses the limits 5..=100, access the devices directly",
+        ));
+        assert!(fs::read_to_string(state.path().join("state.json"))?.contains("\"brightness\": 20"));
         Ok(())
     }
 }
@@ -0,0 +1,1323 @@
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use futures::stream::{self, BoxStream, StreamExt};
+    use glob::Pattern;
+    use regex::Regex;
+    use rumos::backend::{
+        Backend, BrightnessResult, Chain, Configured, Device, DeviceKind, FakeBackend, Limits,
+        SysfsBackend,
+    };
+    use rumos::{
+        format_minutes, render, ApplyOptions, AutoBrightness, AutoConfig, BrightnessCommand,
+        BrightnessOutput, Client, Command, Config, Date, DeviceConfig, DeviceRecord,
+        DeviceSelector, Easing, Environment, Error, ErrorKind, LightSensor, Profile, ProfileDevice,
+        Profiles, Report, RequestBody, Scale, ScheduleConfig, SchedulePoint, Scheduler, Status,
+        Target, TimeOfDay, Transition, Unmatched,
+    };
+    use std::collections::{HashMap, HashSet};
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::ops::ControlFlow;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::os::unix::net::UnixStream;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    // TestType
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    const FAKE_DEVICES: &str = r#"{
+        "devices": [
+            { "name": "intel_backlight", "brightness": 50 },
+            { "name": "tpacpi::kbd_backlight", "kind": "led", "brightness": 1, "max_brightness": 2 }
+        ]
+    }"#;
+
+    // Helpers
+    fn fake_backend(state: &TempDir) -> FakeBackend {
+        FakeBackend::open(state.path().join("state.json"), Some(FAKE_DEVICES)).unwrap()
+    }
+
+    /// Creates the brightness file of a device with the given permissions, returning its group.
+    fn brightness_file(root: &TempDir, device: &str, mode: u32) -> u32 {
+        let path = root.path().join("sys/class").join(device);
+        fs::create_dir_all(&path).unwrap();
+        let path = path.join("brightness");
+        fs::write(&path, "1\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        fs::metadata(&path).unwrap().gid()
+    }
+
+    /// Backend without devices, recording the kinds of the devices asked for.
+    #[derive(Default)]
+    struct Monitors {
+        requests: Arc<Mutex<Vec<HashSet<DeviceKind>>>>,
+    }
+
+    impl Backend for Monitors {
+        type Device = Box<dyn Device>;
+
+        fn devices(&self) -> BoxStream<'_, BrightnessResult<Self::Device>> {
+            self.devices_of(&HashSet::from(DeviceKind::ALL))
+        }
+
+        fn devices_of(
+            &self,
+            kinds: &HashSet<DeviceKind>,
+        ) -> BoxStream<'_, BrightnessResult<Self::Device>> {
+            self.requests.lock().unwrap().push(kinds.clone());
+            stream::empty().boxed()
+        }
+    }
+
+    fn fake_environment(root: &TempDir) -> Environment {
+        Environment {
+            sysfs_root: root.path().join("sys"),
+            dev_root: root.path().join("dev"),
+            udev_rule_dirs: vec![root.path().join("rules.d")],
+            group_file: root.path().join("group"),
+            system_bus_socket: root.path().join("system_bus_socket"),
+            uid: 1000,
+            gids: vec![1000],
+            vars: HashMap::from([("USER".to_string(), "user".to_string())]),
+        }
+    }
+
+    /// Starts a daemon serving the fake devices, returning the location of its socket.
+    fn start_daemon(state: &TempDir) -> PathBuf {
+        let socket = state.path().join("rumos.sock");
+        // Written beforehand so that the subscribers can watch the state file.
+        fs::write(state.path().join("state.json"), FAKE_DEVICES).unwrap();
+        let backend = fake_backend(state);
+        let path = socket.clone();
+        thread::spawn(move || rumos::serve(backend, &path, None, None));
+        wait_for_socket(&socket);
+        socket
+    }
+
+    fn wait_for_socket(socket: &Path) {
+        let start = Instant::now();
+        while UnixStream::connect(socket).is_err() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "The daemon did not start"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Creates a fake IIO light sensor measuring `lux`, returning the file holding its raw
+    /// reading.
+    fn light_sensor(root: &TempDir, lux: u32) -> PathBuf {
+        let accelerometer = root.path().join("bus/iio/devices/iio:device0");
+        fs::create_dir_all(&accelerometer).unwrap();
+        fs::write(accelerometer.join("in_accel_x_raw"), "12\n").unwrap();
+        let sensor = root.path().join("bus/iio/devices/iio:device1");
+        fs::create_dir_all(&sensor).unwrap();
+        fs::write(sensor.join("in_illuminance_scale"), "0.5\n").unwrap();
+        let raw = sensor.join("in_illuminance_raw");
+        fs::write(&raw, format!("{}\n", lux * 2)).unwrap();
+        raw
+    }
+
+    /// Represents a private session bus, stopped when dropped.
+    #[cfg(target_os = "linux")]
+    struct Bus {
+        process: std::process::Child,
+        address: String,
+    }
+
+    #[cfg(target_os = "linux")]
+    impl Bus {
+        /// Starts a private session bus, unless `dbus-daemon` is not installed.
+        fn start() -> Option<Bus> {
+            let mut process = std::process::Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(std::process::Stdio::piped())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(process.stdout.take()?)
+                .read_line(&mut address)
+                .ok()?;
+            Some(Bus {
+                process,
+                address: address.trim().to_string(),
+            })
+        }
+
+        /// Connects to the bus, once the service owns its name.
+        fn connect(&self) -> zbus::blocking::Connection {
+            let connection = zbus::blocking::ConnectionBuilder::address(self.address.as_str())
+                .unwrap()
+                .build()
+                .unwrap();
+            let bus = zbus::blocking::fdo::DBusProxy::new(&connection).unwrap();
+            let start = Instant::now();
+            while !bus
+                .name_has_owner(rumos::DBUS_NAME.try_into().unwrap())
+                .unwrap()
+            {
+                assert!(
+                    start.elapsed() < Duration::from_secs(10),
+                    "The service did not start"
+                );
+                thread::sleep(Duration::from_millis(10));
+            }
+            connection
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.process.kill();
+            let _ = self.process.wait();
+        }
+    }
+
+    // Tests
+    #[test]
+    fn report_of_all_devices() -> TestResult {
+        let state = TempDir::new()?;
+        let report = block_on(DeviceSelector::All.report(&fake_backend(&state)));
+        assert_eq!(
+            report.devices,
+            vec![
+                DeviceRecord {
+                    index: 0,
+                    name: Some("intel_backlight".to_string()),
+                    id: None,
+                    aliases: Vec::new(),
+                    kind: Some(DeviceKind::Backlight),
+                    backlight_type: None,
+                    limits: Some(Limits::default()),
+                    before: None,
+                    percent: Some(50),
+                    raw: Some(50),
+                    max: Some(100),
+                    error: None,
+                    error_kind: None,
+                },
+                DeviceRecord {
+                    index: 1,
+                    name: Some("tpacpi::kbd_backlight".to_string()),
+                    id: None,
+                    aliases: Vec::new(),
+                    kind: Some(DeviceKind::Led),
+                    backlight_type: None,
+                    limits: Some(Limits::default()),
+                    before: None,
+                    percent: Some(50),
+                    raw: Some(1),
+                    max: Some(2),
+                    error: None,
+                    error_kind: None,
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn apply_returns_selected_devices() -> TestResult {
+        let state = TempDir::new()?;
+        let backend = fake_backend(&state);
+        let selector =
+            DeviceSelector::ByName(Arc::new(HashSet::from(["intel_backlight".to_string()])));
+        let report = block_on(BrightnessCommand::Inc { percent: 20 }.apply(&backend, &selector))?;
+        assert_eq!(report.devices.len(), 1);
+        assert_eq!(report.devices[0].before, Some(50));
+        assert_eq!(report.devices[0].percent, Some(70));
+
+        let reopened = fake_backend(&state);
+        let report = block_on(DeviceSelector::ByIndex(HashSet::from([0])).report(&reopened));
+        assert_eq!(report.devices[0].percent, Some(70));
+        Ok(())
+    }
+
+    #[test]
+    fn composed_selectors() -> TestResult {
+        let state = TempDir::new()?;
+        let backend = fake_backend(&state);
+        let selector = DeviceSelector::AllOf(vec![
+            DeviceSelector::ByPattern(vec![Pattern::new("*backlight")?]),
+            DeviceSelector::Not(Box::new(DeviceSelector::ByKind(HashSet::from([
+                DeviceKind::Backlight,
+            ])))),
+        ]);
+        let report = block_on(selector.report(&backend));
+        assert_eq!(report.devices.len(), 1);
+        assert_eq!(report.devices[0].index, 1);
+
+        let selector = DeviceSelector::AnyOf(vec![
+            DeviceSelector::ByRegex(vec![Regex::new("^intel")?]),
+            DeviceSelector::ByIndex(HashSet::from([1])),
+        ]);
+        assert_eq!(block_on(selector.report(&backend)).devices.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn selectors_fall_back_to_default_devices() {
+        let backlights = DeviceSelector::ByKind(HashSet::from([DeviceKind::Backlight]));
+        let excluded = DeviceSelector::Not(Box::new(DeviceSelector::ByIndex(HashSet::from([0]))));
+        let named = DeviceSelector::ByIndex(HashSet::from([1]));
+        let cases = [
+            (DeviceSelector::All, backlights.clone()),
+            (
+                excluded.clone(),
+                DeviceSelector::AllOf(vec![backlights.clone(), excluded]),
+            ),
+            (named.clone(), named),
+        ];
+        for (selector, expected) in cases {
+            assert_eq!(
+                format!("{:?}", selector.with_default(backlights.clone())),
+                format!("{expected:?}")
+            );
+        }
+    }
+
+    #[test]
+    fn selectors_tell_which_kinds_to_enumerate() -> TestResult {
+        let state = TempDir::new()?;
+        let monitors = Monitors::default();
+        let requests = monitors.requests.clone();
+        let backend = Chain(fake_backend(&state), monitors);
+        let backlights = DeviceSelector::ByKind(HashSet::from([DeviceKind::Backlight]));
+        block_on(BrightnessCommand::Get.apply(&backend, &backlights))?;
+        block_on(BrightnessCommand::Get.apply(&backend, &DeviceSelector::default_for_changes()))?;
+        block_on(BrightnessCommand::Get.apply(&backend, &DeviceSelector::All))?;
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                HashSet::from([DeviceKind::Backlight]),
+                HashSet::from([DeviceKind::Backlight, DeviceKind::Ddc]),
+                HashSet::from(DeviceKind::ALL),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn watch_stops_probing_unselected_monitors() -> TestResult {
+        let state = TempDir::new()?;
+        let monitors = Monitors::default();
+        let requests = monitors.requests.clone();
+        let backend = Chain(fake_backend(&state), monitors);
+        let mut readings = 0;
+        DeviceSelector::All.watch(&backend, Duration::from_millis(10), |_| {
+            readings += 1;
+            if readings == 1 {
+                block_on(
+                    BrightnessCommand::Set { percent: 70 }.apply(&backend, &DeviceSelector::All),
+                )
+                .unwrap();
+                return ControlFlow::Continue(());
+            }
+            ControlFlow::Break(())
+        });
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0], HashSet::from(DeviceKind::ALL));
+        assert_eq!(
+            requests.last(),
+            Some(&HashSet::from([DeviceKind::Backlight, DeviceKind::Led]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unmatched_names_are_rejected() -> TestResult {
+        let state = TempDir::new()?;
+        let backend = fake_backend(&state);
+        let selector = DeviceSelector::ByName(Arc::new(HashSet::from([
+            "intel_backlight".to_string(),
+            "intel_backlite".to_string(),
+        ])));
+        let err = block_on(BrightnessCommand::Max.apply(&backend, &selector)).unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::UnmatchedDevices(unmatched) if unmatched == &vec![Unmatched::Name {
+                name: "intel_backlite".to_string(),
+                suggestions: vec!["intel_backlight".to_string()],
+            }]
+        ));
+        assert_eq!(
+            err.to_string(),
+            "No device matches `intel_backlite`, did you mean `intel_backlight`?"
+        );
+        let report = block_on(DeviceSelector::All.report(&backend));
+        assert_eq!(report.devices[0].percent, Some(50));
+        Ok(())
+    }
+
+    #[test]
+    fn render_report_without_devices() -> TestResult {
+        let state = TempDir::new()?;
+        let command = Command::BrightnessCommand {
+            command: BrightnessCommand::Get,
+            selector: DeviceSelector::ByIndex(HashSet::from([5])),
+            output: BrightnessOutput::Percent,
+            options: ApplyOptions {
+                allow_missing: true,
+                ..ApplyOptions::default()
+            },
+        };
+        let report = block_on(command.handle(&fake_backend(&state)))?;
+        assert!(report.devices.is_empty());
+        assert_eq!(
+            report.unmatched,
+            vec![Unmatched::Index { index: 5, count: 2 }]
+        );
+        assert_eq!(render(&command, &report), "");
+        Ok(())
+    }
+
+    #[test]
+    fn render_report_as_json_lines() {
+        let record = DeviceRecord {
+            index: 0,
+            name: Some("intel_backlight".to_string()),
+            id: None,
+            aliases: Vec::new(),
+            kind: Some(DeviceKind::Backlight),
+            backlight_type: None,
+            limits: Some(Limits::default()),
+            before: Some(40),
+            percent: Some(60),
+            raw: Some(60),
+            max: Some(100),
+            error: None,
+            error_kind: None,
+        };
+        let command = Command::BrightnessCommand {
+            command: BrightnessCommand::Inc { percent: 20 },
+            selector: DeviceSelector::All,
+            output: BrightnessOutput::JsonLines,
+            options: ApplyOptions::default(),
+        };
+        let report = Report {
+            devices: vec![record],
+            ..Report::default()
+        };
+        assert_eq!(
+            render(&command, &report),
+            concat!(
+                r#"{"index":0,"name":"intel_backlight","id":null,"aliases":[],"kind":"backlight","#,
+                r#""backlight_type":null,"#,
+                r#""limits":{"min":5,"max":100},"before":40,"percent":60,"raw":60,"max":100,"error":null,"error_kind":null}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn easing_curves_span_the_whole_change() {
+        for easing in [Easing::Linear, Easing::EaseInOut, Easing::Exponential] {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(1.0), 1.0);
+            assert!(easing.apply(0.25) <= easing.apply(0.75));
+        }
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+        assert!(Easing::Exponential.apply(0.5) < Easing::Linear.apply(0.5));
+    }
+
+    #[test]
+    fn fade_to_new_level() -> TestResult {
+        let state = TempDir::new()?;
+        let backend = fake_backend(&state);
+        let transition = Transition {
+            duration: Duration::ZERO,
+            easing: Easing::Linear,
+        };
+        let selector = DeviceSelector::ByIndex(HashSet::from([1]));
+        let report = block_on(BrightnessCommand::Max.fade(&backend, &selector, &transition))?;
+        assert_eq!(report.devices[0].before, Some(50));
+        assert_eq!(report.devices[0].percent, Some(100));
+        Ok(())
+    }
+
+    #[test]
+    fn scales_round_trip() {
+        for scale in [Scale::Linear, Scale::Gamma(2.2), Scale::Cie] {
+            for percent in [0.0, 0.05, 0.5, 1.0] {
+                let linear = scale.to_linear(percent);
+                assert!((scale.to_perceived(linear) - percent).abs() < 1e-9);
+            }
+        }
+        assert!(Scale::Cie.to_linear(0.5) < 0.5);
+        assert_eq!("gamma:1.8".parse(), Ok(Scale::Gamma(1.8)));
+    }
+
+    #[test]
+    fn scaled_devices_work_on_perceived_brightness() -> TestResult {
+        let state = TempDir::new()?;
+        let mut config = Config::default();
+        config.defaults.scale = Scale::Gamma(2.0);
+        config.devices.insert(
+            "tpacpi::kbd_backlight".to_string(),
+            DeviceConfig {
+                scale: Some(Scale::Linear),
+                ..DeviceConfig::default()
+            },
+        );
+        let backend = Configured::new(fake_backend(&state), config);
+        let report = block_on(DeviceSelector::All.report(&backend));
+        assert_eq!(report.devices[0].percent, Some(71));
+        assert_eq!(report.devices[1].percent, Some(50));
+
+        let selector = DeviceSelector::ByIndex(HashSet::from([0]));
+        let report = block_on(BrightnessCommand::Set { percent: 30 }.apply(&backend, &selector))?;
+        assert_eq!(report.devices[0].percent, Some(30));
+        assert_eq!(report.devices[0].raw, Some(9));
+        Ok(())
+    }
+
+    #[test]
+    fn configured_limits_restrict_commands() -> TestResult {
+        let state = TempDir::new()?;
+        let mut config = Config::default();
+        config.defaults.max = 80;
+        config.devices.insert(
+            "tpacpi::kbd_backlight".to_string(),
+            DeviceConfig {
+                min: Some(0),
+                ..DeviceConfig::default()
+            },
+        );
+        let backend = Configured::new(fake_backend(&state), config);
+        let report = block_on(BrightnessCommand::Max.apply(&backend, &DeviceSelector::All))?;
+        assert_eq!(report.devices[0].percent, Some(80));
+        let report = block_on(BrightnessCommand::Min.apply(&backend, &DeviceSelector::All))?;
+        assert_eq!(report.devices[0].percent, Some(5));
+        assert_eq!(report.devices[1].percent, Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn doctor_reports_unwritable_devices() -> TestResult {
+        let root = TempDir::new()?;
+        let gid = brightness_file(&root, "backlight/intel_backlight", 0o664);
+        brightness_file(&root, "leds/input0::capslock", 0o666);
+        brightness_file(&root, "leds/tpacpi::kbd_backlight", 0o644);
+        fs::write(root.path().join("group"), format!("video:x:{gid}:\n"))?;
+        let checks = fake_environment(&root).diagnose();
+        let check = |topic: &str| {
+            checks
+                .iter()
+                .find(|check| check.topic == topic)
+                .unwrap_or_else(|| panic!("No check about {topic} in {checks:?}"))
+        };
+
+        let backlight = check("sysfs intel_backlight");
+        assert_eq!(backlight.status, Status::Error);
+        assert!(backlight.remediation[0].contains("sudo usermod -aG video $USER"));
+        assert_eq!(check("sysfs input0::capslock").status, Status::Ok);
+        let led = check("sysfs tpacpi::kbd_backlight");
+        assert_eq!(led.status, Status::Error);
+        assert!(led.remediation[0].contains("sudo rumos setup udev"));
+        assert_eq!(check("groups").status, Status::Warning);
+        assert_eq!(check("logind").status, Status::Warning);
+        assert_eq!(check("d-bus").status, Status::Warning);
+        assert_eq!(check("udev").status, Status::Warning);
+        assert_eq!(check("i2c-dev").status, Status::Warning);
+        Ok(())
+    }
+
+    #[test]
+    fn doctor_accepts_a_configured_environment() -> TestResult {
+        let root = TempDir::new()?;
+        let gid = brightness_file(&root, "backlight/intel_backlight", 0o664);
+        fs::write(root.path().join("group"), format!("video:x:{gid}:user\n"))?;
+        fs::write(root.path().join("system_bus_socket"), "")?;
+        fs::create_dir_all(root.path().join("rules.d"))?;
+        fs::write(
+            root.path().join("rules.d/90-backlight.rules"),
+            "ACTION==\"add\", SUBSYSTEM==\"backlight\", RUN+=\"/bin/chmod g+w              /sys/class/backlight/%k/brightness\"\n",
+        )?;
+        fs::create_dir_all(root.path().join("dev"))?;
+        fs::write(root.path().join("dev/i2c-3"), "")?;
+        fs::set_permissions(
+            root.path().join("dev/i2c-3"),
+            fs::Permissions::from_mode(0o660),
+        )?;
+        let mut environment = fake_environment(&root);
+        environment.gids.push(gid);
+        environment
+            .vars
+            .insert("XDG_SESSION_ID".to_string(), "2".to_string());
+
+        let checks = environment.diagnose();
+        assert!(
+            checks.iter().all(|check| check.status == Status::Ok),
+            "{checks:?}"
+        );
+        assert_eq!(
+            checks
+                .iter()
+                .map(|check| check.topic.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "sysfs intel_backlight",
+                "groups",
+                "logind",
+                "d-bus",
+                "udev",
+                "i2c-dev"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn doctor_relies_on_logind_without_sysfs_access() -> TestResult {
+        let root = TempDir::new()?;
+        brightness_file(&root, "backlight/intel_backlight", 0o644);
+        fs::write(root.path().join("system_bus_socket"), "")?;
+        let mut environment = fake_environment(&root);
+        environment
+            .vars
+            .insert("XDG_SESSION_ID".to_string(), "2".to_string());
+
+        let checks = environment.diagnose();
+        assert_eq!(checks[0].status, Status::Warning);
+        assert!(checks[0].summary.contains("through logind"));
+        assert!(checks.iter().all(|check| check.status != Status::Error));
+        Ok(())
+    }
+
+    #[test]
+    fn sysfs_ids_leave_out_card_indices() -> TestResult {
+        let root = TempDir::new()?;
+        let device = root
+            .path()
+            .join("devices/pci0000:00/0000:00:02.0/drm/card1/card1-eDP-1/intel_backlight");
+        fs::create_dir_all(&device)?;
+        fs::write(device.join("brightness"), "5\n")?;
+        fs::write(device.join("max_brightness"), "10\n")?;
+        fs::create_dir_all(root.path().join("class/backlight"))?;
+        std::os::unix::fs::symlink(&device, root.path().join("class/backlight/intel_backlight"))?;
+        let report = block_on(DeviceSelector::All.report(&SysfsBackend::new(root.path())));
+        assert_eq!(
+            report.devices[0].id.as_deref(),
+            Some("sysfs:devices/pci0000:00/0000:00:02.0/drm/card/card-eDP-1/intel_backlight")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn watch_notices_changes_of_sysfs_files() -> TestResult {
+        let root = TempDir::new()?;
+        for name in ["acpi_video0", "intel_backlight"] {
+            let device = root.path().join("class/backlight").join(name);
+            fs::create_dir_all(&device)?;
+            fs::write(device.join("brightness"), "5\n")?;
+            fs::write(device.join("max_brightness"), "10\n")?;
+        }
+        let brightness = root
+            .path()
+            .join("class/backlight/intel_backlight/brightness");
+        let backend = SysfsBackend::new(root.path());
+        let start = Instant::now();
+        let mut reports = Vec::new();
+        DeviceSelector::All.watch(&backend, Duration::from_secs(30), |report| {
+            reports.push(report.clone());
+            if reports.len() == 1 {
+                let brightness = brightness.clone();
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(100));
+                    fs::write(brightness, "8\n").unwrap();
+                });
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
+        });
+        // The change is noticed through inotify rather than by reading the devices again.
+        assert!(start.elapsed() < Duration::from_secs(10));
+        let levels = |report: &Report| {
+            report
+                .devices
+                .iter()
+                .map(|record| (record.name.clone().unwrap(), record.percent.unwrap()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            levels(&reports[0]),
+            [
+                ("acpi_video0".to_string(), 50),
+                ("intel_backlight".to_string(), 50)
+            ]
+        );
+        assert_eq!(levels(&reports[1]), [("intel_backlight".to_string(), 80)]);
+        Ok(())
+    }
+
+    #[test]
+    fn watch_reads_devices_periodically() -> TestResult {
+        let state = TempDir::new()?;
+        let backend = fake_backend(&state);
+        let names = Arc::new(HashSet::from(["intel_backlight".to_string()]));
+        let backlight = DeviceSelector::ByName(names.clone());
+        let selector = DeviceSelector::AnyOf(vec![
+            DeviceSelector::ByName(names),
+            DeviceSelector::ByIndex(HashSet::from([5])),
+        ]);
+        let mut reports = Vec::new();
+        selector.watch(&backend, Duration::from_millis(10), |report| {
+            reports.push(report.clone());
+            if reports.len() == 1 {
+                // Changed before the state file is watched, so only a new reading notices it.
+                block_on(BrightnessCommand::Set { percent: 70 }.apply(&backend, &backlight))
+                    .unwrap();
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
+        });
+        assert_eq!(reports[0].devices[0].percent, Some(50));
+        assert_eq!(
+            reports[0].unmatched,
+            [Unmatched::Index { index: 5, count: 2 }]
+        );
+        assert_eq!(reports[1].devices.len(), 1);
+        assert_eq!(reports[1].devices[0].percent, Some(70));
+        assert!(reports[1].unmatched.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn daemon_executes_requests() -> TestResult {
+        let state = TempDir::new()?;
+        let socket = start_daemon(&state);
+        let mut client = Client::connect(&socket)?;
+        let target = Target {
+            selector: DeviceSelector::ByIndex(HashSet::from([0])),
+            allow_missing: false,
+        };
+        let report = client.request(RequestBody::Inc {
+            percent: 20,
+            target: target.clone(),
+            transition: None,
+        })?;
+        assert_eq!(report.devices.len(), 1);
+        assert_eq!(report.devices[0].before, Some(50));
+        assert_eq!(report.devices[0].percent, Some(70));
+        let report = client.request(RequestBody::Fade {
+            percent: 40,
+            target,
+            transition: Transition {
+                duration: Duration::from_millis(50),
+                easing: Easing::EaseInOut,
+            },
+        })?;
+        assert_eq!(report.devices[0].percent, Some(40));
+        let report = client.request(RequestBody::List)?;
+        assert_eq!(report.devices.len(), 2);
+        let missing = client.request(RequestBody::Get {
+            target: Target {
+                selector: DeviceSelector::ByName(Arc::new(HashSet::from(["hdmi".to_string()]))),
+                allow_missing: false,
+            },
+        });
+        assert!(matches!(missing, Err(Error::UnmatchedDevices(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn daemon_changes_supersede_transitions() -> TestResult {
+        let state = TempDir::new()?;
+        let socket = state.path().join("rumos.sock");
+        let devices = r#"{ "devices": [{ "name": "fading_panel", "brightness": 0 }] }"#;
+        let backend = FakeBackend::open(state.path().join("state.json"), Some(devices))?;
+        let path = socket.clone();
+        thread::spawn(move || rumos::serve(backend, &path, None, None));
+        wait_for_socket(&socket);
+        let target = Target {
+            selector: DeviceSelector::ByName(Arc::new(HashSet::from(["fading_panel".to_string()]))),
+            allow_missing: false,
+        };
+
+        let start = Instant::now();
+        let fade = {
+            let mut client = Client::connect(&socket)?;
+            let target = target.clone();
+            thread::spawn(move || {
+                client.request(RequestBody::Fade {
+                    percent: 100,
+                    target,
+                    transition: Transition {
+                        duration: Duration::from_secs(30),
+                        easing: Easing::Linear,
+                    },
+                })
+            })
+        };
+        let mut client = Client::connect(&socket)?;
+        let get = RequestBody::Get {
+            target: target.clone(),
+        };
+        while client.request(get.clone())?.devices[0].percent == Some(0) {
+            assert!(start.elapsed() < Duration::from_secs(10), "No fade started");
+            thread::sleep(Duration::from_millis(20));
+        }
+        let report = client.request(RequestBody::Set {
+            percent: 10,
+            target: target.clone(),
+            transition: None,
+        })?;
+        assert_eq!(report.devices[0].percent, Some(10));
+        fade.join().unwrap()?;
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(client.request(get)?.devices[0].percent, Some(10));
+        Ok(())
+    }
+
+    #[test]
+    fn daemon_keeps_indices_of_unopenable_devices() -> TestResult {
+        let root = TempDir::new()?;
+        // Without `max_brightness`, the first device cannot be opened.
+        fs::create_dir_all(root.path().join("class/backlight/aa_broken"))?;
+        let device = root.path().join("class/backlight/intel_backlight");
+        fs::create_dir_all(&device)?;
+        fs::write(device.join("brightness"), "5\n")?;
+        fs::write(device.join("max_brightness"), "10\n")?;
+        let socket = root.path().join("rumos.sock");
+        let backend = SysfsBackend::new(root.path());
+        let path = socket.clone();
+        thread::spawn(move || rumos::serve(backend, &path, None, None));
+        wait_for_socket(&socket);
+
+        let mut client = Client::connect(&socket)?;
+        let report = client.request(RequestBody::Get {
+            target: Target {
+                selector: DeviceSelector::ByIndex(HashSet::from([1])),
+                allow_missing: false,
+            },
+        })?;
+        assert_eq!(report.devices[0].name.as_deref(), Some("intel_backlight"));
+        assert_eq!(report.devices[0].percent, Some(50));
+        let report = client.request(RequestBody::Get {
+            target: Target::default(),
+        })?;
+        assert_eq!(report.devices.len(), 2);
+        assert_eq!(report.devices[0].error_kind, Some(ErrorKind::DeviceFailed));
+        assert!(report.devices[0]
+            .error
+            .as_deref()
+            .is_some_and(|error| error.contains("aa_broken")));
+        Ok(())
+    }
+
+    #[test]
+    fn daemon_speaks_versioned_json() -> TestResult {
+        let state = TempDir::new()?;
+        let socket = start_daemon(&state);
+        let mut stream = UnixStream::connect(&socket)?;
+        let mut lines = BufReader::new(stream.try_clone()?).lines();
+        let mut exchange =
+            |request: &str| -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+                writeln!(stream, "{request}")?;
+                Ok(serde_json::from_str(&lines.next().unwrap()?)?)
+            };
+
+        let response = exchange(
+            r#"{"version": 1, "request": "set", "percent": 30, "selector": {"names": ["intel_backlight"]}}"#,
+        )?;
+        assert_eq!(response["version"], 1);
+        assert_eq!(response["response"], "report");
+        assert_eq!(response["devices"][0]["percent"], 30);
+        let response = exchange(r#"{"version": 2, "request": "list"}"#)?;
+        assert_eq!(response["response"], "error");
+        assert_eq!(
+            response["message"],
+            "Unsupported protocol version 2, the daemon speaks version 1"
+        );
+        let response = exchange(r#"{"version": 1, "request": "explode"}"#)?;
+        assert_eq!(response["response"], "error");
+        Ok(())
+    }
+
+    #[test]
+    fn daemon_notifies_subscribers() -> TestResult {
+        let state = TempDir::new()?;
+        let socket = start_daemon(&state);
+        let mut subscriber = Client::connect(&socket)?;
+        let mut reports = Vec::new();
+        subscriber.subscribe(
+            DeviceSelector::ByKind(HashSet::from([DeviceKind::Led])),
+            Duration::from_secs(30),
+            |report| {
+                reports.push(report.clone());
+                if reports.len() == 1 {
+                    let mut client = Client::connect(&socket).unwrap();
+                    client
+                        .request(RequestBody::Max {
+                            target: Target::default(),
+                            transition: None,
+                        })
+                        .unwrap();
+                    ControlFlow::Continue(())
+                } else {
+                    ControlFlow::Break(())
+                }
+            },
+        )?;
+        assert_eq!(reports[0].devices[0].percent, Some(50));
+        assert_eq!(reports[1].devices.len(), 1);
+        assert_eq!(
+            reports[1].devices[0].name.as_deref(),
+            Some("tpacpi::kbd_backlight")
+        );
+        assert_eq!(reports[1].devices[0].percent, Some(100));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn dbus_service_controls_devices() -> TestResult {
+        let Some(bus) = Bus::start() else {
+            eprintln!("Skipped: dbus-daemon is not installed");
+            return Ok(());
+        };
+        let state = TempDir::new()?;
+        fs::write(state.path().join("state.json"), FAKE_DEVICES)?;
+        let backend = fake_backend(&state);
+        let address = bus.address.clone();
+        thread::spawn(move || rumos::serve_dbus(backend, Some(&address), None));
+        let connection = bus.connect();
+        let manager = zbus::blocking::Proxy::new(
+            &connection,
+            rumos::DBUS_NAME,
+            rumos::DBUS_PATH,
+            "org.rumos.Brightness1",
+        )?;
+
+        let devices: Vec<(u32, String, zbus::zvariant::OwnedObjectPath)> =
+            manager.call("List", &())?;
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[1].1, "tpacpi::kbd_backlight");
+        assert_eq!(devices[0].2.as_str(), "/org/rumos/Brightness1/devices/0");
+        let levels: Vec<(String, u32)> = manager.call("Get", &("",))?;
+        assert_eq!(
+            levels,
+            vec![
+                ("intel_backlight".to_string(), 50),
+                ("tpacpi::kbd_backlight".to_string(), 50)
+            ]
+        );
+        let levels: Vec<(String, u32)> = manager.call("Set", &("intel_backlight", 30u32, 0u32))?;
+        assert_eq!(levels, vec![("intel_backlight".to_string(), 30)]);
+        let levels: Vec<(String, u32)> = manager.call("Adjust", &("0", -10i32, 20u32))?;
+        assert_eq!(levels, vec![("intel_backlight".to_string(), 20)]);
+        let missing = manager.call::<_, _, Vec<(String, u32)>>("Get", &("hdmi",));
+        assert!(matches!(
+            missing,
+            Err(zbus::Error::MethodError(name, _, _)) if name.as_str() == "org.freedesktop.DBus.Error.InvalidArgs"
+        ));
+
+        let device = zbus::blocking::Proxy::new(
+            &connection,
+            rumos::DBUS_NAME,
+            "/org/rumos/Brightness1/devices/1",
+            "org.rumos.Brightness1.Device",
+        )?;
+        assert_eq!(
+            device.get_property::<String>("Name")?,
+            "tpacpi::kbd_backlight"
+        );
+        assert_eq!(device.get_property::<String>("Kind")?, "led");
+        assert_eq!(device.get_property::<u32>("Brightness")?, 50);
+        assert_eq!(device.get_property::<u32>("RawBrightness")?, 1);
+        assert_eq!(device.get_property::<u32>("MaxRawBrightness")?, 2);
+
+        let properties = zbus::blocking::fdo::PropertiesProxy::builder(&connection)
+            .destination(rumos::DBUS_NAME)?
+            .path("/org/rumos/Brightness1/devices/1")?
+            .build()?;
+        let mut changes = properties.receive_properties_changed()?;
+        let _: Vec<(String, u32)> =
+            manager.call("Set", &("tpacpi::kbd_backlight", 100u32, 0u32))?;
+        let change = changes.next().unwrap();
+        let args = change.args()?;
+        assert_eq!(
+            args.interface_name().as_str(),
+            "org.rumos.Brightness1.Device"
+        );
+        assert_eq!(
+            u32::try_from(args.changed_properties()["Brightness"].clone())?,
+            100
+        );
+        assert_eq!(
+            u32::try_from(args.changed_properties()["RawBrightness"].clone())?,
+            2
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn dbus_reads_levels_during_transitions() -> TestResult {
+        let Some(bus) = Bus::start() else {
+            eprintln!("Skipped: dbus-daemon is not installed");
+            return Ok(());
+        };
+        let state = TempDir::new()?;
+        let devices = r#"{ "devices": [{ "name": "dbus_fading_panel", "brightness": 0 }] }"#;
+        let backend = FakeBackend::open(state.path().join("state.json"), Some(devices))?;
+        let address = bus.address.clone();
+        thread::spawn(move || rumos::serve_dbus(backend, Some(&address), None));
+        let connection = bus.connect();
+        let manager = zbus::blocking::Proxy::new(
+            &connection,
+            rumos::DBUS_NAME,
+            rumos::DBUS_PATH,
+            "org.rumos.Brightness1",
+        )?;
+        // Without a cache, so that every read of a property asks the service.
+        let device = zbus::blocking::ProxyBuilder::<zbus::blocking::Proxy>::new_bare(&connection)
+            .destination(rumos::DBUS_NAME)?
+            .path("/org/rumos/Brightness1/devices/0")?
+            .interface("org.rumos.Brightness1.Device")?
+            .cache_properties(zbus::CacheProperties::No)
+            .build()?;
+
+        let start = Instant::now();
+        let fade = {
+            let manager = manager.clone();
+            thread::spawn(move || {
+                manager.call::<_, _, Vec<(String, u32)>>("Set", &("", 100u32, 30_000u32))
+            })
+        };
+        while device.get_property::<u32>("Brightness")? == 0 {
+            assert!(start.elapsed() < Duration::from_secs(10), "No fade started");
+            thread::sleep(Duration::from_millis(20));
+        }
+        let levels: Vec<(String, u32)> = manager.call("Get", &("",))?;
+        assert!(levels[0].1 > 0 && levels[0].1 < 100);
+        let levels: Vec<(String, u32)> = manager.call("Set", &("", 10u32, 0u32))?;
+        assert_eq!(levels, vec![("dbus_fading_panel".to_string(), 10)]);
+        fade.join().unwrap()?;
+        assert!(start.elapsed() < Duration::from_secs(10));
+        Ok(())
+    }
+
+    #[test]
+    fn light_sensor_reads_illuminance() -> TestResult {
+        let root = TempDir::new()?;
+        let raw = light_sensor(&root, 100);
+        let sensor = LightSensor::discover(root.path(), None)?;
+        assert_eq!(sensor.name(), "iio:device1");
+        assert_eq!(sensor.read()?, 100.0);
+        fs::write(raw.with_file_name("in_illuminance_offset"), "10\n")?;
+        assert_eq!(sensor.read()?, 105.0);
+        fs::write(raw.with_file_name("in_illuminance_input"), "321.5\n")?;
+        assert_eq!(sensor.read()?, 321.5);
+        assert!(LightSensor::discover(root.path(), Some("iio:device0")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn auto_brightness_follows_ambient_light() -> TestResult {
+        let root = TempDir::new()?;
+        let raw = light_sensor(&root, 100);
+        let backend = fake_backend(&root);
+        let selector =
+            DeviceSelector::ByName(Arc::new(HashSet::from(["intel_backlight".to_string()])));
+        let config = AutoConfig {
+            smoothing: 0.0,
+            transition: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut auto = AutoBrightness::new(
+            LightSensor::discover(root.path(), None)?,
+            config,
+            selector.clone(),
+        );
+        let percents = |report: Report| -> Vec<Option<u32>> {
+            report.devices.iter().map(|record| record.percent).collect()
+        };
+
+        assert_eq!(percents(block_on(auto.step(&backend))?), [Some(40)]);
+        fs::write(&raw, "2000\n")?;
+        assert_eq!(percents(block_on(auto.step(&backend))?), [Some(70)]);
+        // Within the hysteresis.
+        fs::write(&raw, "2100\n")?;
+        assert!(block_on(auto.step(&backend))?.devices.is_empty());
+        // Corrected by the user, which shifts the curve.
+        block_on(BrightnessCommand::Inc { percent: 20 }.apply(&backend, &selector))?;
+        assert!(block_on(auto.step(&backend))?.devices.is_empty());
+        fs::write(&raw, "200\n")?;
+        assert_eq!(percents(block_on(auto.step(&backend))?), [Some(60)]);
+        Ok(())
+    }
+
+    #[test]
+    fn auto_brightness_smooths_readings() -> TestResult {
+        let root = TempDir::new()?;
+        let raw = light_sensor(&root, 0);
+        let backend = fake_backend(&root);
+        let config = AutoConfig {
+            curve: vec![(0.0, 0), (1000.0, 100)],
+            smoothing: 0.5,
+            transition: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut auto = AutoBrightness::new(
+            LightSensor::discover(root.path(), None)?,
+            config,
+            DeviceSelector::ByIndex(HashSet::from([0])),
+        );
+        block_on(auto.step(&backend))?;
+        fs::write(&raw, "2000\n")?;
+        let report = block_on(auto.step(&backend))?;
+        assert_eq!(auto.lux(), Some(500.0));
+        assert_eq!(report.devices[0].percent, Some(50));
+        Ok(())
+    }
+
+    #[test]
+    fn auto_curve_interpolates_levels() {
+        let config = AutoConfig {
+            curve: vec![(10.0, 20), (100.0, 40), (1000.0, 80)],
+            ..Default::default()
+        };
+        assert_eq!(config.level(0.0), 20);
+        assert_eq!(config.level(55.0), 30);
+        assert_eq!(config.level(550.0), 60);
+        assert_eq!(config.level(5000.0), 80);
+    }
+
+    #[test]
+    fn date_parsing() {
+        assert_eq!(
+            "2024-02-29".parse(),
+            Ok(Date {
+                year: 2024,
+                month: 2,
+                day: 29
+            })
+        );
+        assert!("2023-02-29".parse::<Date>().is_err());
+        assert!("2024-13-01".parse::<Date>().is_err());
+        assert!("tomorrow".parse::<Date>().is_err());
+    }
+
+    #[test]
+    fn time_of_day_parsing() {
+        assert_eq!("07:30".parse(), Ok(TimeOfDay::Fixed(7 * 60 + 30)));
+        assert_eq!("sunrise".parse(), Ok(TimeOfDay::Sunrise(0)));
+        assert_eq!("sunset-30min".parse(), Ok(TimeOfDay::Sunset(-30 * 60)));
+        assert_eq!(TimeOfDay::Sunrise(3600).to_string(), "sunrise+60min");
+        assert!("24:00".parse::<TimeOfDay>().is_err());
+        assert!("noon".parse::<TimeOfDay>().is_err());
+        assert!("sunset+25h".parse::<TimeOfDay>().is_err());
+        assert!("sunrise+99999999999999999999999h"
+            .parse::<TimeOfDay>()
+            .is_err());
+    }
+
+    #[test]
+    fn schedule_computes_solar_events() {
+        let paris = ScheduleConfig {
+            latitude: Some(48.8566),
+            longitude: Some(2.3522),
+            points: vec![
+                SchedulePoint {
+                    time: TimeOfDay::Sunrise(0),
+                    percent: 80,
+                },
+                SchedulePoint {
+                    time: TimeOfDay::Sunset(-30 * 60),
+                    percent: 30,
+                },
+            ],
+            ..Default::default()
+        };
+        let summer = paris.day_with_offset("2024-06-21".parse().unwrap(), 2 * 3600);
+        assert_eq!(summer.sunrise.map(format_minutes).as_deref(), Some("05:47"));
+        assert_eq!(summer.sunset.map(format_minutes).as_deref(), Some("21:58"));
+        assert_eq!(summer.points.len(), 2);
+        let winter = paris.day_with_offset("2024-12-21".parse().unwrap(), 3600);
+        assert_eq!(winter.sunrise.map(format_minutes).as_deref(), Some("08:41"));
+        assert_eq!(winter.sunset.map(format_minutes).as_deref(), Some("16:56"));
+
+        // The sun does not rise during the polar night, so its points are left out.
+        let tromso = ScheduleConfig {
+            latitude: Some(69.6492),
+            longitude: Some(18.9553),
+            ..paris
+        };
+        let night = tromso.day_with_offset("2024-12-21".parse().unwrap(), 3600);
+        assert_eq!((night.sunrise, night.sunset), (None, None));
+        assert!(night.points.is_empty());
+        assert_eq!(night.level(12.0 * 60.0), None);
+    }
+
+    #[test]
+    fn schedule_fades_between_points() {
+        let config = ScheduleConfig {
+            transition: Duration::from_secs(3600),
+            points: vec![
+                SchedulePoint {
+                    time: TimeOfDay::Fixed(8 * 60),
+                    percent: 60,
+                },
+                SchedulePoint {
+                    time: TimeOfDay::Fixed(20 * 60),
+                    percent: 20,
+                },
+            ],
+            ..Default::default()
+        };
+        let day = config.day_with_offset("2024-06-21".parse().unwrap(), 0);
+        let level = |time: &str| {
+            let TimeOfDay::Fixed(minutes) = time.parse().unwrap() else {
+                unreachable!()
+            };
+            day.level(f64::from(minutes))
+        };
+        assert_eq!(level("00:00"), Some(20));
+        assert_eq!(level("07:00"), Some(20));
+        assert_eq!(level("07:30"), Some(40));
+        assert_eq!(level("08:00"), Some(60));
+        assert_eq!(level("19:00"), Some(60));
+        assert_eq!(level("19:45"), Some(30));
+        assert_eq!(level("23:59"), Some(20));
+    }
+
+    #[test]
+    fn scheduler_sets_scheduled_level() -> TestResult {
+        let state = TempDir::new()?;
+        let backend = fake_backend(&state);
+        let selector =
+            DeviceSelector::ByName(Arc::new(HashSet::from(["intel_backlight".to_string()])));
+        let config = ScheduleConfig {
+            transition: Duration::from_secs(3600),
+            points: vec![
+                SchedulePoint {
+                    time: TimeOfDay::Fixed(8 * 60),
+                    percent: 60,
+                },
+                SchedulePoint {
+                    time: TimeOfDay::Fixed(20 * 60),
+                    percent: 20,
+                },
+            ],
+            ..Default::default()
+        };
+        let mut scheduler = Scheduler::new(config, selector.clone());
+        let date = "2024-06-21".parse()?;
+        let percents = |report: Report| -> Vec<Option<u32>> {
+            report.devices.iter().map(|record| record.percent).collect()
+        };
+
+        assert_eq!(
+            percents(block_on(scheduler.step(&backend, date, 10.0 * 60.0))?),
+            [Some(60)]
+        );
+        assert!(block_on(scheduler.step(&backend, date, 11.0 * 60.0))?
+            .devices
+            .is_empty());
+        // Changed by hand, which lasts until the schedule moves on.
+        block_on(BrightnessCommand::Set { percent: 90 }.apply(&backend, &selector))?;
+        assert!(block_on(scheduler.step(&backend, date, 12.0 * 60.0))?
+            .devices
+            .is_empty());
+        assert_eq!(
+            percents(block_on(scheduler.step(&backend, date, 19.5 * 60.0))?),
+            [Some(40)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn profiles_are_saved_by_name() -> TestResult {
+        let root = TempDir::new()?;
+        let profiles = Profiles::new(root.path().join("profiles"));
+        assert!(profiles.names()?.is_empty());
+        let profile = Profile {
+            devices: vec![ProfileDevice {
+                id: Some(
+                    "sysfs:devices/pci0000:00/0000:00:02.0/drm/card/card-eDP-1/intel_backlight"
+                        .to_string(),
+                ),
+                name: Some("intel_backlight".to_string()),
+                percent: 40,
+            }],
+        };
+        profiles.save("reading", &profile)?;
+        profiles.save("movie", &Profile::default())?;
+        assert_eq!(profiles.names()?, ["movie", "reading"]);
+        assert_eq!(profiles.load("reading")?, profile);
+        profiles.delete("movie")?;
+        assert_eq!(profiles.names()?, ["reading"]);
+        assert!(profiles.load("movie").is_err());
+        assert!(profiles.save("../reading", &profile).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn profile_applies_levels_by_identity() -> TestResult {
+        let state = TempDir::new()?;
+        let backend = FakeBackend::open(
+            state.path().join("state.json"),
+            Some(
+                r#"{
+                    "devices": [
+                        { "name": "card1-eDP-1-backlight", "id": "sysfs:devices/pci0000:00/0000:00:02.0/drm/card/card-eDP-1/intel_backlight", "brightness": 50 },
+                        { "name": "tpacpi::kbd_backlight", "kind": "led", "brightness": 2, "max_brightness": 2 }
+                    ]
+                }"#,
+            ),
+        )?;
+        // Saved while the backlight had another name and index.
+        let profile = Profile {
+            devices: vec![
+                ProfileDevice {
+                    id: None,
+                    name: Some("tpacpi::kbd_backlight".to_string()),
+                    percent: 0,
+                },
+                ProfileDevice {
+                    id: None,
+                    name: Some("external".to_string()),
+                    percent: 70,
+                },
+                ProfileDevice {
+                    id: Some(
+                        "sysfs:devices/pci0000:00/0000:00:02.0/drm/card/card-eDP-1/intel_backlight"
+                            .to_string(),
+                    ),
+                    name: Some("intel_backlight".to_string()),
+                    percent: 30,
+                },
+            ],
+        };
+        let missing = vec![Unmatched::Name {
+            name: "external".to_string(),
+            suggestions: Vec::new(),
+        }];
+
+        let err = block_on(profile.apply(&backend, &ApplyOptions::default())).unwrap_err();
+        assert!(matches!(&err, Error::UnmatchedDevices(unmatched) if unmatched == &missing));
+        let options = ApplyOptions {
+            allow_missing: true,
+            ..ApplyOptions::default()
+        };
+        let report = block_on(profile.apply(&backend, &options))?;
+        assert_eq!(report.unmatched, missing);
+        let levels = report
+            .devices
+            .iter()
+            .map(|record| (record.index, record.before, record.percent))
+            .collect::<Vec<_>>();
+        assert_eq!(levels, [(0, Some(50), Some(30)), (1, Some(100), Some(0))]);
+        Ok(())
+    }
+}